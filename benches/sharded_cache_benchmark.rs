@@ -0,0 +1,49 @@
+#[macro_use]
+extern crate criterion;
+
+use bhatho::cache::config::CacheConfig;
+use bhatho::cache::sharded_cache::ShardedCache;
+use criterion::Criterion;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use scoped_threadpool::Pool;
+
+fn sharded_cache(sub_shards_per_shard: usize) -> ShardedCache {
+    let mut cache_config = CacheConfig::default();
+    cache_config.cache_capacity = 1_000_000;
+    cache_config.num_shards = 4;
+    cache_config.sub_shards_per_shard = sub_shards_per_shard;
+    ShardedCache::new(&cache_config).unwrap()
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let mut r_th = rand::thread_rng();
+    let mut data = Vec::with_capacity(100_000);
+    for _ in 0..100_000 {
+        let key = r_th.sample_iter(&Alphanumeric).take(32).collect::<String>();
+        data.push(key);
+    }
+
+    //few shards and many threads, so every shard lock is hot
+    for sub_shards in [1, 16].iter() {
+        let cache = sharded_cache(*sub_shards);
+        let mut pool = Pool::new(8);
+        c.bench_function(&format!("sharded_cache_put_mt_sub_shards_{}", sub_shards), |b| {
+            b.iter(|| {
+                pool.scoped(|scoped| {
+                    for chunk in data.chunks(data.len() / 8) {
+                        let cache = &cache;
+                        scoped.execute(move || {
+                            for key in chunk.iter() {
+                                let _ = cache.put(key.as_bytes(), key.as_bytes());
+                            }
+                        });
+                    }
+                });
+            })
+        });
+    }
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
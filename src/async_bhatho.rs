@@ -0,0 +1,56 @@
+/************************************************
+
+   File Name: bhatho:async_bhatho
+   Author: Rohit Joshi <rohit.c.joshi@gmail.com>
+   Date: 2019-02-17:15:15
+   License: Apache 2.0
+
+**************************************************/
+use std::sync::Arc;
+use tokio::task;
+
+use crate::keyval::KeyVal;
+use crate::Bhatho;
+
+///
+/// async facade over Bhatho. each call runs the blocking Bhatho call on
+/// the tokio blocking thread pool, so the async worker threads are never blocked
+#[derive(Clone)]
+pub struct AsyncBhatho {
+    bhatho: Arc<Bhatho>,
+}
+
+impl AsyncBhatho {
+    pub fn new(bhatho: Bhatho) -> AsyncBhatho {
+        AsyncBhatho {
+            bhatho: Arc::new(bhatho),
+        }
+    }
+
+    /// the wrapped Bhatho instance for the calls not provided by the facade
+    pub fn inner(&self) -> &Bhatho {
+        &self.bhatho
+    }
+
+    pub async fn get(&self, kv: KeyVal) -> Result<Option<(Vec<u8>, bool)>, String> {
+        let bhatho = self.bhatho.clone();
+        AsyncBhatho::join(task::spawn_blocking(move || bhatho.get(&kv))).await
+    }
+
+    pub async fn put(&self, kv: KeyVal) -> Result<(), String> {
+        let bhatho = self.bhatho.clone();
+        AsyncBhatho::join(task::spawn_blocking(move || bhatho.put(&kv))).await
+    }
+
+    pub async fn delete(&self, kv: KeyVal) -> Result<(), String> {
+        let bhatho = self.bhatho.clone();
+        AsyncBhatho::join(task::spawn_blocking(move || bhatho.delete(&kv))).await
+    }
+
+    async fn join<T>(handle: task::JoinHandle<Result<T, String>>) -> Result<T, String> {
+        match handle.await {
+            Ok(res) => res,
+            Err(e) => Err(format!("blocking task failed. Error:{}", e)),
+        }
+    }
+}
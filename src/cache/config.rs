@@ -21,14 +21,55 @@ impl Default for CacheManagerConfig {
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct CacheConfig {
     pub enabled: bool,
     pub cache_capacity: usize,
     pub num_shards: usize,
     pub cache_update_on_db_read: bool,
     pub cache_update_on_db_write: bool,
+    /// with cache_update_on_db_write, a write refreshes the cached value of a key
+    /// already cached but never caches a new key. keys are cached when read
+    pub cache_update_on_write_only_if_present: bool,
     pub keys_dump_enabled: bool,
     pub keys_dump_file: String,
+    /// seed used to hash keys into shards. changing it invalidates existing cache placement
+    pub hash_seed: u64,
+    /// derive num_shards from the number of cpus instead of the configured num_shards
+    pub auto_shards: bool,
+    /// "lru" or "lfu"
+    pub eviction_policy: String,
+    /// expire cache entries after ttl_secs. 0 means entries never expire
+    pub ttl_secs: u64,
+    /// interval for the background sweeper removing expired entries. 0 disables the sweeper
+    pub cache_sweep_interval_secs: u64,
+    /// buffer cache updates on db read and flush them as a batch. 0 updates the cache immediately
+    pub read_through_batch_size: usize,
+    /// "none" or "lz4"
+    pub cache_value_compression: String,
+    /// number of lru buckets per shard, each with its own lock. 0 or 1 disables striping
+    pub sub_shards_per_shard: usize,
+    /// number of keys loaded into the cache in background after the db is restored from a backup
+    pub warm_cache_keys: usize,
+    /// store a CRC16 with each cached value and treat a mismatch on read as a miss
+    pub cache_checksum: bool,
+    /// "jumphash" or "modulo" routing of keys to shards
+    pub shard_router: String,
+    /// number of keys written per chunk while exporting keys, from a copy of the shard keys.
+    /// 0 holds the shard lock for the whole export
+    pub export_chunk_size: usize,
+    /// separator written after each exported key
+    pub keys_dump_separator: String,
+    /// export each key as a 4 byte big endian length followed by the key instead of
+    /// using the separator, so keys containing the separator bytes are preserved
+    pub keys_dump_binary: bool,
+    /// values larger than cache_max_value_size bytes are not cached, so a few large values
+    /// don't evict many small ones. 0 means unlimited
+    pub cache_max_value_size: usize,
+    /// track which keys would be cached and record the hits and misses, but never serve
+    /// a value from the cache. values are not stored, so the cache size can be tuned
+    /// before committing the memory
+    pub shadow_mode: bool,
 }
 
 impl Default for CacheConfig {
@@ -39,8 +80,41 @@ impl Default for CacheConfig {
             num_shards: 1024,
             cache_update_on_db_read: true,
             cache_update_on_db_write: true,
+            cache_update_on_write_only_if_present: false,
             keys_dump_enabled: true,
             keys_dump_file: "/tmp/kanudo_lru_keys.dump".to_string(),
+            hash_seed: 0,
+            auto_shards: false,
+            eviction_policy: "lru".to_string(),
+            ttl_secs: 0,
+            cache_sweep_interval_secs: 0,
+            read_through_batch_size: 0,
+            cache_value_compression: "none".to_string(),
+            sub_shards_per_shard: 1,
+            warm_cache_keys: 0,
+            cache_checksum: false,
+            shard_router: "jumphash".to_string(),
+            export_chunk_size: 10_000,
+            keys_dump_separator: "\r\n".to_string(),
+            keys_dump_binary: false,
+            cache_max_value_size: 0,
+            shadow_mode: false,
+        }
+    }
+}
+
+/// number of cache shards per cpu when auto_shards is enabled
+pub const AUTO_SHARDS_PER_CPU: usize = 64;
+
+impl CacheConfig {
+    ///
+    /// number of shards to create.
+    /// with auto_shards, it is number of cpus * AUTO_SHARDS_PER_CPU rounded to a power of two
+    pub fn effective_num_shards(&self) -> usize {
+        if self.auto_shards {
+            (num_cpus::get() * AUTO_SHARDS_PER_CPU).next_power_of_two()
+        } else {
+            self.num_shards
         }
     }
 }
@@ -7,26 +7,51 @@
 
 **************************************************/
 use lru::LruCache;
-use parking_lot::Mutex;
+use parking_lot::{Mutex, MutexGuard, RwLock};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Write;
 use std::result::Result;
 use std::str;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 //use twox_hash::RandomXxHashBuilder;
 //use twox_hash::XxHash;
 //use std::collections::HashMap;
 use crate::keyval::KeyVal;
+use crc16::{State, XMODEM};
+
+use crate::cache::config::CacheConfig;
+use crate::cache::policy::{CacheEntry, CachePolicy, LfuCache};
 
 //use std::sync::atomic::{Ordering, AtomicUsize};
 //type LruCacheVec = HashMap<Vec<u8>, Vec<u8>>;
-type LruCacheVec = LruCache<Vec<u8>, Vec<u8>>;
+type LruCacheVec = LruCache<Vec<u8>, CacheEntry>;
+
+/// header byte of a stored value when compression is enabled
+const VALUE_RAW: u8 = 0;
+const VALUE_LZ4: u8 = 1;
+
+/// callback invoked with the key and value of an entry evicted to make room for a put
+pub type EvictCallback = Arc<dyn Fn(&[u8], &[u8]) + Send + Sync>;
+
+/// key, decoded value and expiry of a cached entry
+pub(crate) type SnapshotEntry = (Vec<u8>, Vec<u8>, Option<Instant>);
+
+/// guard holding the lock of a cache shard
+pub(crate) type CacheGuard<'a> = MutexGuard<'a, Box<dyn CachePolicy>>;
 
 pub struct Lru {
     id: usize,
-    cache: Arc<Mutex<LruCacheVec>>,
+    cache: Arc<Mutex<Box<dyn CachePolicy>>>,
     cache_capacity: usize,
+    ttl: Option<Duration>,
+    compress: bool,
+    checksum: bool,
+    //pinned entries are never evicted and don't count against cache_capacity
+    pinned: Arc<RwLock<HashMap<Vec<u8>, Vec<u8>>>>,
+    on_evict: Option<EvictCallback>,
 }
 
 /// send safe
@@ -42,6 +67,11 @@ impl Clone for Lru {
             id: self.id,
             cache: self.cache.clone(),
             cache_capacity: self.cache_capacity,
+            ttl: self.ttl,
+            compress: self.compress,
+            checksum: self.checksum,
+            pinned: self.pinned.clone(),
+            on_evict: self.on_evict.clone(),
         }
     }
 }
@@ -59,28 +89,281 @@ impl Lru {
         //            }
         //            None => {}
         //        }
-        let cache = Arc::new(Mutex::new(LruCacheVec::new(cache_capacity)));
+        let cache: Box<dyn CachePolicy> = Box::new(LruCacheVec::new(cache_capacity));
         //let cache = Arc::new(Mutex::new(HashMap::<Vec<u8>, Vec<u8>>::with_capacity(cache_capacity)));
         Lru {
             id,
-            cache,
+            cache: Arc::new(Mutex::new(cache)),
             cache_capacity,
+            ttl: None,
+            compress: false,
+            checksum: false,
+            pinned: Arc::new(RwLock::new(HashMap::new())),
+            on_evict: None,
         }
     }
 
+    /// create a new object with a given eviction policy: "lru" or "lfu"
+    pub fn with_eviction_policy(id: usize, cache_capacity: usize, eviction_policy: &str) -> Lru {
+        let cache: Box<dyn CachePolicy> = match eviction_policy {
+            "lfu" => Box::new(LfuCache::new(cache_capacity)),
+            "lru" => Box::new(LruCacheVec::new(cache_capacity)),
+            _ => {
+                warn!(
+                    "Unknown eviction policy: {}. Using lru eviction policy",
+                    eviction_policy
+                );
+                Box::new(LruCacheVec::new(cache_capacity))
+            }
+        };
+        Lru {
+            id,
+            cache: Arc::new(Mutex::new(cache)),
+            cache_capacity,
+            ttl: None,
+            compress: false,
+            checksum: false,
+            pinned: Arc::new(RwLock::new(HashMap::new())),
+            on_evict: None,
+        }
+    }
+
+    /// create a new object using eviction policy and ttl from the cache config
+    pub fn with_config(id: usize, cache_capacity: usize, config: &CacheConfig) -> Lru {
+        let mut lru = Lru::with_eviction_policy(id, cache_capacity, &config.eviction_policy);
+        if config.ttl_secs > 0 {
+            lru.ttl = Some(Duration::from_secs(config.ttl_secs));
+        }
+        match config.cache_value_compression.as_str() {
+            "lz4" => lru.compress = true,
+            "none" => {}
+            other => warn!("Unknown cache value compression: {}. Not compressing", other),
+        }
+        lru.checksum = config.cache_checksum;
+        lru
+    }
+
     /// get key as str
     #[inline(always)]
     pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
         //warn!("LruCache::Key:{}, shard:{}, Get",  String::from_utf8_lossy(&key), self.id);
         //get from cache first,
-        match self.cache.lock().get(&key.to_vec()) {
-            Some(val) => Some(val.to_vec()),
+        if let Some(val) = self.get_pinned(key) {
+            return Some(val);
+        }
+        let mut cache = self.cache.lock();
+        match cache.get(&key) {
+            Some(entry) => {
+                if !entry.is_expired() {
+                    return self.decode_value(&entry.val);
+                }
+            }
             None => {
                 //warn!("LruCache::Key:{}, shard:{}, GetNotFound",  String::from_utf8_lossy(&key), self.id);
-                None
+                return None;
+            }
+        }
+        //expired entry
+        cache.pop(&key);
+        None
+    }
+
+    /// get the value without updating its recency
+    #[inline]
+    pub fn peek(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if let Some(val) = self.get_pinned(key) {
+            return Some(val);
+        }
+        let cache = self.cache.lock();
+        match cache.peek(&key) {
+            Some(entry) if !entry.is_expired() => self.decode_value(&entry.val),
+            _ => None,
+        }
+    }
+
+    /// true if the key is cached and not expired. doesn't update its recency or decode the value
+    #[inline]
+    pub fn contains(&self, key: &[u8]) -> bool {
+        if self.pinned.read().contains_key(key) {
+            return true;
+        }
+        let cache = self.cache.lock();
+        match cache.peek(&key) {
+            Some(entry) => !entry.is_expired(),
+            None => false,
+        }
+    }
+
+    #[inline(always)]
+    fn get_pinned(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let pinned = self.pinned.read();
+        if pinned.is_empty() {
+            return None;
+        }
+        pinned.get(key).cloned()
+    }
+
+    /// register a callback invoked with the key and value of each entry evicted by a put
+    /// because the shard is full. It is called after the shard lock is released
+    pub fn on_evict(&mut self, f: Box<dyn Fn(&[u8], &[u8]) + Send + Sync>) {
+        self.set_evict_callback(Arc::from(f));
+    }
+
+    /// share a callback registered with the ShardedCache
+    pub(crate) fn set_evict_callback(&mut self, on_evict: EvictCallback) {
+        self.on_evict = Some(on_evict);
+    }
+
+    /// pass the evicted entry to the on_evict callback
+    #[inline(always)]
+    pub(crate) fn notify_evicted(&self, evicted: Option<(Vec<u8>, CacheEntry)>) {
+        if let (Some(on_evict), Some((key, entry))) = (self.on_evict.as_ref(), evicted) {
+            if let Some(val) = self.decode_value(&entry.val) {
+                on_evict(&key, &val);
             }
         }
     }
+
+    /// copy of the unexpired entries as (key, decoded value, expiry) and of the pinned entries.
+    /// the shard is locked while it is copied
+    pub(crate) fn snapshot(&self) -> (Vec<SnapshotEntry>, Vec<(Vec<u8>, Vec<u8>)>) {
+        let entries = {
+            let cache = self.cache.lock();
+            let mut entries = Vec::with_capacity(cache.len());
+            for (key, entry) in cache.iter() {
+                if entry.is_expired() {
+                    continue;
+                }
+                if let Some(val) = self.decode_value(&entry.val) {
+                    entries.push((key.clone(), val, entry.expires_at));
+                }
+            }
+            entries
+        };
+        let pinned = self
+            .pinned
+            .read()
+            .iter()
+            .map(|(key, val)| (key.clone(), val.clone()))
+            .collect();
+        (entries, pinned)
+    }
+
+    /// keep the key val pair in memory until it is unpinned or deleted.
+    /// pinned entries are never evicted, don't expire and don't count against cache capacity.
+    /// a put of a pinned key updates its pinned value
+    pub fn pin(&self, key: &[u8], val: &[u8]) {
+        self.cache.lock().pop(&key);
+        self.pinned.write().insert(key.to_vec(), val.to_vec());
+    }
+
+    /// move a pinned entry back to the cache where it is subject to eviction.
+    /// returns false if the key is not pinned
+    pub fn unpin(&self, key: &[u8]) -> bool {
+        let val = match self.pinned.write().remove(key) {
+            Some(val) => val,
+            None => return false,
+        };
+        let entry = self.new_entry(&val);
+        let evicted = self.cache.lock().put(key.to_vec(), entry);
+        self.notify_evicted(evicted);
+        true
+    }
+
+    /// update the value if the key is pinned. returns false if the key is not pinned
+    #[inline(always)]
+    fn update_pinned(&self, key: &[u8], val: &[u8]) -> bool {
+        if self.pinned.read().is_empty() {
+            return false;
+        }
+        match self.pinned.write().get_mut(key) {
+            Some(pinned) => {
+                *pinned = val.to_vec();
+                true
+            }
+            None => false,
+        }
+    }
+
+    #[inline(always)]
+    fn new_entry(&self, val: &[u8]) -> CacheEntry {
+        self.new_entry_with_ttl(val, None)
+    }
+
+    /// entry expiring after ttl, or after the ttl of the config if None
+    #[inline(always)]
+    fn new_entry_with_ttl(&self, val: &[u8], ttl: Option<Duration>) -> CacheEntry {
+        CacheEntry {
+            val: self.encode_value(val),
+            expires_at: ttl.or(self.ttl).map(|ttl| Instant::now() + ttl),
+        }
+    }
+
+    /// value as stored in the cache.
+    /// with compression, first byte tells whether the rest is lz4 compressed or raw.
+    /// with checksum, last two bytes are CRC16 of the rest
+    #[inline(always)]
+    fn encode_value(&self, val: &[u8]) -> Vec<u8> {
+        let mut stored = if self.compress {
+            Lru::compress_value(val)
+        } else {
+            val.to_vec()
+        };
+        if self.checksum {
+            let crc = State::<XMODEM>::calculate(&stored);
+            stored.extend_from_slice(&crc.to_be_bytes());
+        }
+        stored
+    }
+
+    #[inline(always)]
+    fn compress_value(val: &[u8]) -> Vec<u8> {
+        if let Ok(compressed) = lz4::block::compress(val, None, true) {
+            if compressed.len() < val.len() {
+                let mut stored = Vec::with_capacity(compressed.len() + 3);
+                stored.push(VALUE_LZ4);
+                stored.extend_from_slice(&compressed);
+                return stored;
+            }
+        }
+        let mut stored = Vec::with_capacity(val.len() + 3);
+        stored.push(VALUE_RAW);
+        stored.extend_from_slice(val);
+        stored
+    }
+
+    #[inline(always)]
+    fn decode_value(&self, stored: &[u8]) -> Option<Vec<u8>> {
+        let stored = if self.checksum {
+            if stored.len() < 2 {
+                error!("Cache value too short for checksum in shard:{}", self.id);
+                return None;
+            }
+            let (data, crc) = stored.split_at(stored.len() - 2);
+            if State::<XMODEM>::calculate(data).to_be_bytes()[..] != *crc {
+                error!("Cache value checksum mismatch in shard:{}", self.id);
+                return None;
+            }
+            data
+        } else {
+            stored
+        };
+        if !self.compress {
+            return Some(stored.to_vec());
+        }
+        match stored.split_first() {
+            Some((&VALUE_LZ4, compressed)) => match lz4::block::decompress(compressed, None) {
+                Ok(val) => Some(val),
+                Err(e) => {
+                    error!("Failed to decompress cache value in shard:{}. Error:{:?}", self.id, e);
+                    None
+                }
+            },
+            Some((_, val)) => Some(val.to_vec()),
+            None => None,
+        }
+    }
+
     /// get key as str (wrapper function)
     #[inline(always)]
     pub fn get_str(&self, key: &str) -> Option<String> {
@@ -99,8 +382,24 @@ impl Lru {
     /// put key as str
     #[inline(always)]
     pub fn put(&self, key: &[u8], val: &[u8]) -> Result<(), String> {
+        self.put_with_ttl(key, val, None)
+    }
+
+    /// put the key val pair expiring after ttl. None uses the ttl of the config
+    #[inline(always)]
+    pub fn put_with_ttl(
+        &self,
+        key: &[u8],
+        val: &[u8],
+        ttl: Option<Duration>,
+    ) -> Result<(), String> {
         //warn!("LruCache::Key:{}, shard:{}, Put",  String::from_utf8_lossy(&key), self.id);
-        self.cache.lock().put(key.to_vec(), val.to_vec());
+        if self.update_pinned(key, val) {
+            return Ok(());
+        }
+        let entry = self.new_entry_with_ttl(val, ttl);
+        let evicted = self.cache.lock().put(key.to_vec(), entry);
+        self.notify_evicted(evicted);
         Ok(())
         /*
         match self.cache.lock().put(String::from_utf8(key.to_vec()).unwrap(), val.to_vec()) {
@@ -112,46 +411,210 @@ impl Lru {
     /// put key as str
     #[inline(always)]
     pub fn batch_put(&self, data: &[KeyVal]) -> Result<(), String> {
-        for kv in data.iter() {
-            self.cache.lock().put(kv.key.clone(), kv.val.clone());
-        }
+        self.put_iter(data.iter())
+    }
 
+    /// put all the key val pairs holding the lock once
+    #[inline(always)]
+    pub fn put_iter<'a, I>(&self, data: I) -> Result<(), String>
+    where
+        I: Iterator<Item = &'a KeyVal>,
+    {
+        let mut evicted = Vec::new();
+        {
+            let mut cache = self.cache.lock();
+            for kv in data {
+                if self.update_pinned(&kv.key, &kv.val) {
+                    continue;
+                }
+                let entry = self.new_entry_with_ttl(&kv.val, kv.ttl_secs.map(Duration::from_secs));
+                if let Some(entry) = cache.put(kv.key.clone(), entry) {
+                    if self.on_evict.is_some() {
+                        evicted.push(entry);
+                    }
+                }
+            }
+        }
+        for entry in evicted {
+            self.notify_evicted(Some(entry));
+        }
         Ok(())
     }
-    /// delete key
+    /// delete key. a pinned key is unpinned
     #[inline(always)]
     pub fn delete(&self, key: &[u8]) -> Result<(), String> {
-        self.cache.lock().pop(&key.to_owned());
+        if !self.pinned.read().is_empty() {
+            self.pinned.write().remove(key);
+        }
+        self.cache.lock().pop(&key);
         //self.cache.lock().pop(&key.to_owned());
         Ok(())
     }
+    /// lock the cache. used by operations spanning multiple shards
+    #[inline(always)]
+    pub(crate) fn lock(&self) -> CacheGuard {
+        self.cache.lock()
+    }
+
     #[inline(always)]
     pub fn len(&self) -> usize {
         self.cache.lock().len()
     }
 
+    /// change the capacity of the shard. shrinking evicts the excess entries immediately
+    pub fn resize(&self, new_capacity: usize) {
+        self.cache.lock().resize(new_capacity);
+    }
+
     #[inline(always)]
     pub fn is_empty(&self) -> bool {
         self.cache.lock().len() > 0
     }
 
-    pub fn export_keys(&self, file: &mut File) -> Result<u64, String> {
-        let cache = &self.cache.lock();
-        debug!("Total Keys {} in shard:{}", cache.len(), self.id);
-        let mut total = 0u64;
-        for (key, _) in cache.iter() {
-            if let Err(e) = file.write(key) {
-                error!("export keys: Failed to write to the file.");
-                return Err(e.to_string());
+    /// approximate bytes used by the keys and values stored in the shard
+    pub fn estimated_bytes(&self) -> usize {
+        let cache = self.cache.lock();
+        cache
+            .iter()
+            .map(|(key, entry)| key.len() + entry.val.len())
+            .sum()
+    }
+
+    /// remove all the expired entries. returns number of entries removed
+    pub fn remove_expired(&self) -> u64 {
+        let mut cache = self.cache.lock();
+        let expired: Vec<Vec<u8>> = cache
+            .iter()
+            .filter(|(_, entry)| entry.is_expired())
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired.iter() {
+            cache.pop(key);
+        }
+        expired.len() as u64
+    }
+
+    /// remove all the entries with the key starting with prefix. returns number of entries removed
+    pub fn remove_prefix(&self, prefix: &[u8]) -> u64 {
+        let mut cache = self.cache.lock();
+        let matched: Vec<Vec<u8>> = cache
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in matched.iter() {
+            cache.pop(key);
+        }
+        matched.len() as u64
+    }
+
+    /// remove all the entries, including the pinned ones. returns number of entries removed.
+    /// the cache lock is taken before the pinned lock, in the order of the puts
+    pub fn clear(&self) -> u64 {
+        let mut cache = self.cache.lock();
+        let keys: Vec<Vec<u8>> = cache.iter().map(|(key, _)| key.clone()).collect();
+        for key in keys.iter() {
+            cache.pop(key);
+        }
+        let mut pinned = self.pinned.write();
+        let removed = pinned.len() as u64;
+        pinned.clear();
+        removed + keys.len() as u64
+    }
+
+    /// write a key to the keys dump. keys_dump_binary writes a 4 byte big endian length
+    /// followed by the key, otherwise the key is followed by keys_dump_separator
+    #[inline]
+    fn write_dump_key<W: Write>(
+        file: &mut W,
+        key: &[u8],
+        config: &CacheConfig,
+    ) -> Result<(), String> {
+        let res = if config.keys_dump_binary {
+            file.write_all(&(key.len() as u32).to_be_bytes())
+                .and_then(|_| file.write_all(key))
+        } else {
+            file.write_all(key)
+                .and_then(|_| file.write_all(config.keys_dump_separator.as_bytes()))
+        };
+        if let Err(e) = res {
+            error!("export keys: Failed to write to the file.");
+            return Err(e.to_string());
+        }
+        Ok(())
+    }
+
+    /// read the keys written by export_keys in the format of the config
+    pub fn read_dump_keys(data: &[u8], config: &CacheConfig) -> Result<Vec<Vec<u8>>, String> {
+        let mut keys = Vec::new();
+        if config.keys_dump_binary {
+            let mut pos = 0;
+            while pos < data.len() {
+                if pos + 4 > data.len() {
+                    return Err("truncated key length in keys dump".to_string());
+                }
+                let mut len = [0u8; 4];
+                len.copy_from_slice(&data[pos..pos + 4]);
+                let len = u32::from_be_bytes(len) as usize;
+                pos += 4;
+                if pos + len > data.len() {
+                    return Err("truncated key in keys dump".to_string());
+                }
+                keys.push(data[pos..pos + len].to_vec());
+                pos += len;
+            }
+            return Ok(keys);
+        }
+        let separator = config.keys_dump_separator.as_bytes();
+        if separator.is_empty() {
+            return Err("keys_dump_separator is empty".to_string());
+        }
+        let mut start = 0;
+        let mut pos = 0;
+        while pos + separator.len() <= data.len() {
+            if &data[pos..pos + separator.len()] == separator {
+                keys.push(data[start..pos].to_vec());
+                pos += separator.len();
+                start = pos;
+            } else {
+                pos += 1;
+            }
+        }
+        if start < data.len() {
+            keys.push(data[start..].to_vec());
+        }
+        Ok(keys)
+    }
+
+    /// write the keys to the file in the keys dump format of the config. the key list is
+    /// copied once and written export_chunk_size keys at a time with the lock released.
+    /// 0 writes the keys while holding the lock. keys added or evicted while exporting
+    /// are not reflected
+    pub fn export_keys(&self, file: &mut File, config: &CacheConfig) -> Result<u64, String> {
+        let chunk_size = config.export_chunk_size;
+        debug!("Total Keys {} in shard:{}", self.len(), self.id);
+        if chunk_size == 0 {
+            let cache = self.cache.lock();
+            for (key, _) in cache.iter() {
+                Lru::write_dump_key(file, key, config)?;
             }
-            if let Err(e) = file.write(b"\r\n") {
+            debug!("Total exported keys :{} in shard: {}", cache.len(), self.id);
+            return Ok(cache.len() as u64);
+        }
+        let keys: Vec<Vec<u8>> = self.cache.lock().iter().map(|(key, _)| key.clone()).collect();
+        let mut buf = Vec::new();
+        for chunk in keys.chunks(chunk_size) {
+            buf.clear();
+            for key in chunk.iter() {
+                Lru::write_dump_key(&mut buf, key, config)?;
+            }
+            if let Err(e) = file.write_all(&buf) {
                 error!("export keys: Failed to write to the file.");
                 return Err(e.to_string());
             }
-            total += 1;
         }
-        debug!("Total exported keys :{} in shard: {}", total, self.id);
-        Ok(total)
+        debug!("Total exported keys :{} in shard: {}", keys.len(), self.id);
+        Ok(keys.len() as u64)
     }
 }
 
@@ -162,6 +625,8 @@ mod tests {
 
     //use crate::tests::rand::Rng;
     use super::*;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicBool, Ordering};
 
     #[test]
     fn test_lrucache_put_and_get_large() {
@@ -189,6 +654,173 @@ mod tests {
             assert_eq!(*val, String::from_utf8_lossy(&cache_val.unwrap()));
         }
     }
+
+    #[test]
+    fn test_lfu_keeps_frequently_accessed_key() {
+        let lfu = Lru::with_eviction_policy(0, 3, "lfu");
+        let lru = Lru::with_eviction_policy(0, 3, "lru");
+        for cache in &[&lfu, &lru] {
+            cache.put(b"hot", b"val").unwrap();
+            cache.put(b"cold_1", b"val").unwrap();
+            cache.put(b"cold_2", b"val").unwrap();
+            for _ in 0..5 {
+                assert!(cache.get(b"hot").is_some());
+            }
+            cache.put(b"new_1", b"val").unwrap();
+            cache.put(b"new_2", b"val").unwrap();
+            cache.put(b"new_3", b"val").unwrap();
+            assert_eq!(cache.len(), 3);
+        }
+        assert!(lfu.get(b"hot").is_some());
+        assert!(lru.get(b"hot").is_none());
+    }
+
+    #[test]
+    fn test_peek_does_not_promote() {
+        let capacity = 10;
+        let cache = Lru::new(0, capacity);
+        for i in 0..capacity {
+            let key = format!("peek_key_{}", i);
+            cache.put(key.as_bytes(), b"val").unwrap();
+        }
+        for _ in 0..5 {
+            assert_eq!(cache.peek(b"peek_key_0"), Some(b"val".to_vec()));
+        }
+        cache.put(b"new_key", b"val").unwrap();
+        assert_eq!(cache.peek(b"peek_key_0"), None);
+        assert_eq!(cache.peek(b"peek_key_1"), Some(b"val".to_vec()));
+    }
+
+    #[test]
+    fn test_checksum_mismatch_is_a_miss() {
+        let mut config = CacheConfig::default();
+        config.cache_checksum = true;
+        let cache = Lru::with_config(0, 100, &config);
+        cache.put(b"checksum_key", b"checksum_val").unwrap();
+        assert_eq!(cache.get(b"checksum_key"), Some(b"checksum_val".to_vec()));
+
+        {
+            let mut guard = cache.cache.lock();
+            let mut entry = guard.pop(b"checksum_key").unwrap();
+            entry.val[0] ^= 0xff;
+            guard.put(b"checksum_key".to_vec(), entry);
+        }
+        assert_eq!(cache.get(b"checksum_key"), None);
+    }
+
+    #[test]
+    fn test_export_keys_in_chunks_with_concurrent_gets() {
+        let total = 50_000;
+        let cache = Lru::new(0, total);
+        for i in 0..total {
+            let key = format!("export_key_{}", i);
+            cache.put(key.as_bytes(), b"val").unwrap();
+        }
+        let done = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let cache = cache.clone();
+            let done = done.clone();
+            std::thread::spawn(move || {
+                let mut i = 0;
+                while !done.load(Ordering::SeqCst) {
+                    let key = format!("export_key_{}", i % total);
+                    assert!(cache.get(key.as_bytes()).is_some());
+                    i += 7;
+                }
+            })
+        };
+        let path = "/tmp/bhatho_test/export_keys_chunks.dump";
+        std::fs::create_dir_all("/tmp/bhatho_test").unwrap();
+        let mut file = File::create(path).unwrap();
+        let mut config = CacheConfig::default();
+        config.export_chunk_size = 1_000;
+        let count = cache.export_keys(&mut file, &config).unwrap();
+        done.store(true, Ordering::SeqCst);
+        reader.join().unwrap();
+
+        assert_eq!(count, total as u64);
+        let dump = std::fs::read_to_string(path).unwrap();
+        let keys: HashSet<&str> = dump.split("\r\n").filter(|key| !key.is_empty()).collect();
+        assert_eq!(keys.len(), total);
+    }
+
+    #[test]
+    fn test_pinned_key_is_never_evicted() {
+        let capacity = 10;
+        let cache = Lru::new(0, capacity);
+        cache.pin(b"config_key", b"config_val");
+        for i in 0..capacity * 100 {
+            let key = format!("pin_key_{}", i);
+            cache.put(key.as_bytes(), b"val").unwrap();
+        }
+        assert_eq!(cache.len(), capacity);
+        assert_eq!(cache.get(b"config_key"), Some(b"config_val".to_vec()));
+
+        cache.put(b"config_key", b"new_val").unwrap();
+        assert_eq!(cache.get(b"config_key"), Some(b"new_val".to_vec()));
+
+        assert!(cache.unpin(b"config_key"));
+        assert!(!cache.unpin(b"config_key"));
+        for i in 0..capacity {
+            let key = format!("unpin_key_{}", i);
+            cache.put(key.as_bytes(), b"val").unwrap();
+        }
+        assert_eq!(cache.get(b"config_key"), None);
+    }
+
+    #[test]
+    fn test_on_evict_callback() {
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let mut cache = Lru::new(0, 2);
+        {
+            let evicted = evicted.clone();
+            cache.on_evict(Box::new(move |key, val| {
+                evicted.lock().push((key.to_vec(), val.to_vec()));
+            }));
+        }
+        cache.put(b"evict_1", b"val_1").unwrap();
+        cache.put(b"evict_2", b"val_2").unwrap();
+        cache.put(b"evict_2", b"val_2_new").unwrap();
+        assert!(evicted.lock().is_empty());
+        cache.put(b"evict_3", b"val_3").unwrap();
+        assert_eq!(*evicted.lock(), vec![(b"evict_1".to_vec(), b"val_1".to_vec())]);
+        assert_eq!(cache.get(b"evict_1"), None);
+    }
+
+    #[test]
+    fn test_per_key_ttl() {
+        let mut config = CacheConfig::default();
+        config.ttl_secs = 3600;
+        let cache = Lru::with_config(0, 100, &config);
+        cache.put_with_ttl(b"short_key", b"val", Some(Duration::from_millis(50))).unwrap();
+        cache.put_with_ttl(b"long_key", b"val", Some(Duration::from_millis(500))).unwrap();
+        cache.batch_put(&[KeyVal::new_with_ttl(b"batch_key", b"val", 1)]).unwrap();
+        cache.put(b"default_key", b"val").unwrap();
+
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(cache.get(b"short_key"), None);
+        assert_eq!(cache.get(b"long_key"), Some(b"val".to_vec()));
+        std::thread::sleep(Duration::from_millis(500));
+        assert_eq!(cache.get(b"long_key"), None);
+        assert_eq!(cache.get(b"default_key"), Some(b"val".to_vec()));
+        std::thread::sleep(Duration::from_millis(500));
+        assert_eq!(cache.get(b"batch_key"), None);
+    }
+
+    #[test]
+    fn test_lz4_value_compression() {
+        let mut config = CacheConfig::default();
+        config.cache_value_compression = "lz4".to_string();
+        let cache = Lru::with_config(0, 100, &config);
+        let val = "{\"name\":\"bhatho\",\"type\":\"cache\"}".repeat(100);
+        cache.put(b"json_key", val.as_bytes()).unwrap();
+        cache.put(b"small_key", b"x").unwrap();
+
+        let stored_len = cache.cache.lock().get(b"json_key").unwrap().val.len();
+        assert!(stored_len < val.len());
+        assert_eq!(cache.get(b"json_key"), Some(val.into_bytes()));
+        assert_eq!(cache.get(b"small_key"), Some(b"x".to_vec()));
+    }
 }
 /*
 #[cfg(test)]
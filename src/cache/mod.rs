@@ -8,4 +8,6 @@
 **************************************************/
 pub mod config;
 pub mod lru_cache;
+pub mod policy;
+pub mod router;
 pub mod sharded_cache;
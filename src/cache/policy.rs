@@ -0,0 +1,202 @@
+/************************************************
+
+   File Name: bhatho:cache::policy
+   Author: Rohit Joshi <rohit.c.joshi@gmail.com>
+   Date: 2019-02-17:15:15
+   License: Apache 2.0
+
+**************************************************/
+use lru::LruCache;
+use std::collections::{BTreeSet, HashMap};
+use std::time::Instant;
+
+/// value stored in the cache along with its expiry time
+pub struct CacheEntry {
+    pub val: Vec<u8>,
+    pub expires_at: Option<Instant>,
+}
+
+impl CacheEntry {
+    #[inline(always)]
+    pub fn is_expired(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => expires_at <= Instant::now(),
+            None => false,
+        }
+    }
+}
+
+/// Eviction policy of a cache shard
+pub trait CachePolicy: Send {
+    /// get the value and record the access
+    fn get(&mut self, key: &[u8]) -> Option<&CacheEntry>;
+
+    /// get the value without recording the access
+    fn peek(&self, key: &[u8]) -> Option<&CacheEntry>;
+
+    /// insert the key val pair, evicting an entry if the cache is full.
+    /// returns the evicted entry
+    fn put(&mut self, key: Vec<u8>, val: CacheEntry) -> Option<(Vec<u8>, CacheEntry)>;
+
+    /// remove the key and return its value
+    fn pop(&mut self, key: &[u8]) -> Option<CacheEntry>;
+
+    fn len(&self) -> usize;
+
+    /// change the capacity, evicting entries if the cache holds more than the new capacity
+    fn resize(&mut self, cap: usize);
+
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a Vec<u8>, &'a CacheEntry)> + 'a>;
+}
+
+impl CachePolicy for LruCache<Vec<u8>, CacheEntry> {
+    #[inline(always)]
+    fn get(&mut self, key: &[u8]) -> Option<&CacheEntry> {
+        LruCache::get(self, &key.to_vec())
+    }
+
+    #[inline(always)]
+    fn peek(&self, key: &[u8]) -> Option<&CacheEntry> {
+        LruCache::peek(self, &key.to_vec())
+    }
+
+    #[inline(always)]
+    fn put(&mut self, key: Vec<u8>, val: CacheEntry) -> Option<(Vec<u8>, CacheEntry)> {
+        let mut evicted = None;
+        if LruCache::len(self) >= LruCache::cap(self) && !LruCache::contains(self, &key) {
+            evicted = LruCache::pop_lru(self);
+        }
+        LruCache::put(self, key, val);
+        evicted
+    }
+
+    #[inline(always)]
+    fn pop(&mut self, key: &[u8]) -> Option<CacheEntry> {
+        LruCache::pop(self, &key.to_vec())
+    }
+
+    #[inline(always)]
+    fn len(&self) -> usize {
+        LruCache::len(self)
+    }
+
+    #[inline(always)]
+    fn resize(&mut self, cap: usize) {
+        LruCache::resize(self, cap);
+    }
+
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a Vec<u8>, &'a CacheEntry)> + 'a> {
+        Box::new(LruCache::iter(self))
+    }
+}
+
+struct LfuEntry {
+    val: CacheEntry,
+    freq: u64,
+    tick: u64,
+}
+
+/// Least frequently used cache.
+/// Ties on access frequency are broken by evicting the least recently used entry
+pub struct LfuCache {
+    map: HashMap<Vec<u8>, LfuEntry>,
+    //ordered by (frequency, tick) so the first entry is the eviction candidate
+    order: BTreeSet<(u64, u64, Vec<u8>)>,
+    cap: usize,
+    tick: u64,
+}
+
+impl LfuCache {
+    pub fn new(cap: usize) -> LfuCache {
+        LfuCache {
+            map: HashMap::with_capacity(cap),
+            order: BTreeSet::new(),
+            cap,
+            tick: 0,
+        }
+    }
+
+    #[inline(always)]
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn evict(&mut self) -> Option<(Vec<u8>, CacheEntry)> {
+        let victim = match self.order.iter().next() {
+            Some(victim) => victim.clone(),
+            None => return None,
+        };
+        self.order.remove(&victim);
+        self.map.remove(&victim.2).map(|entry| (victim.2, entry.val))
+    }
+}
+
+impl CachePolicy for LfuCache {
+    fn get(&mut self, key: &[u8]) -> Option<&CacheEntry> {
+        let tick = self.next_tick();
+        match self.map.get_mut(key) {
+            Some(entry) => {
+                self.order.remove(&(entry.freq, entry.tick, key.to_vec()));
+                entry.freq += 1;
+                entry.tick = tick;
+                self.order.insert((entry.freq, entry.tick, key.to_vec()));
+                Some(&entry.val)
+            }
+            None => None,
+        }
+    }
+
+    #[inline(always)]
+    fn peek(&self, key: &[u8]) -> Option<&CacheEntry> {
+        self.map.get(key).map(|entry| &entry.val)
+    }
+
+    fn put(&mut self, key: Vec<u8>, val: CacheEntry) -> Option<(Vec<u8>, CacheEntry)> {
+        let tick = self.next_tick();
+        if let Some(entry) = self.map.get_mut(&key) {
+            self.order.remove(&(entry.freq, entry.tick, key.clone()));
+            entry.val = val;
+            entry.freq += 1;
+            entry.tick = tick;
+            self.order.insert((entry.freq, entry.tick, key));
+            return None;
+        }
+        if self.cap == 0 {
+            return None;
+        }
+        let mut evicted = None;
+        if self.map.len() >= self.cap {
+            evicted = self.evict();
+        }
+        self.order.insert((1, tick, key.clone()));
+        self.map.insert(key, LfuEntry { val, freq: 1, tick });
+        evicted
+    }
+
+    fn pop(&mut self, key: &[u8]) -> Option<CacheEntry> {
+        match self.map.remove(key) {
+            Some(entry) => {
+                self.order.remove(&(entry.freq, entry.tick, key.to_vec()));
+                Some(entry.val)
+            }
+            None => None,
+        }
+    }
+
+    #[inline(always)]
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    fn resize(&mut self, cap: usize) {
+        self.cap = cap;
+        while self.map.len() > self.cap {
+            self.evict();
+        }
+    }
+
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a Vec<u8>, &'a CacheEntry)> + 'a> {
+        Box::new(self.map.iter().map(|(key, entry)| (key, &entry.val)))
+    }
+}
@@ -0,0 +1,61 @@
+/************************************************
+
+   File Name: bhatho:cache::router
+   Author: Rohit Joshi <rohit.c.joshi@gmail.com>
+   Date: 2019-02-17:15:15
+   License: Apache 2.0
+
+**************************************************/
+use crate::keyval::KeyVal;
+
+/// Routes a key to one of the cache shards
+pub trait ShardRouter: Send + Sync {
+    /// shard index in 0..num_shards for a given key
+    fn shard(&self, key: &[u8], num_shards: usize) -> usize;
+
+    /// shard index for a given KeyVal. routers can override it to reuse the key hash
+    #[inline(always)]
+    fn shard_key_val(&self, kv: &KeyVal, num_shards: usize) -> usize {
+        self.shard(&kv.key, num_shards)
+    }
+}
+
+/// jump consistent hash of the key hash. It is the default router
+pub struct JumpHashRouter {
+    pub seed: u64,
+}
+
+impl ShardRouter for JumpHashRouter {
+    #[inline(always)]
+    fn shard(&self, key: &[u8], num_shards: usize) -> usize {
+        KeyVal::key_slot_with_seed(key, num_shards, self.seed) as usize
+    }
+
+    #[inline(always)]
+    fn shard_key_val(&self, kv: &KeyVal, num_shards: usize) -> usize {
+        if self.seed != 0 {
+            return self.shard(&kv.key, num_shards);
+        }
+        kv.slot(num_shards) as usize
+    }
+}
+
+/// mod of the key hash with number of shards
+pub struct ModuloRouter {
+    pub seed: u64,
+}
+
+impl ShardRouter for ModuloRouter {
+    #[inline(always)]
+    fn shard(&self, key: &[u8], num_shards: usize) -> usize {
+        (KeyVal::get_hash_code_with_seed(key, self.seed) % num_shards as u64) as usize
+    }
+
+    #[inline(always)]
+    fn shard_key_val(&self, kv: &KeyVal, num_shards: usize) -> usize {
+        if self.seed != 0 {
+            return self.shard(&kv.key, num_shards);
+        }
+        (kv.hash % num_shards as u64) as usize
+    }
+}
@@ -9,17 +9,27 @@
 
 use std::fs::OpenOptions;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::cache::config::CacheConfig;
-use crate::cache::lru_cache::Lru;
+use crate::cache::lru_cache::{CacheGuard, EvictCallback, Lru};
+use crate::cache::router::{JumpHashRouter, ModuloRouter, ShardRouter};
 use crate::keyval::KeyVal;
 use std::fs;
 use std::path::Path;
 
+/// seed of the secondary hash selecting a sub shard within a shard
+const SUB_SHARD_HASH_SEED: u64 = 0x5eed_5b5a;
+
 pub struct ShardedCache {
+    //with sub_shards_per_shard, shard i uses the buckets i * sub_shards..(i + 1) * sub_shards
     pub shards: Arc<Vec<Lru>>,
     config: CacheConfig,
     enabled: bool,
+    sub_shards: usize,
+    router: Arc<dyn ShardRouter>,
 }
 
 //unsafe impl Send for ShardedCache {}
@@ -32,23 +42,51 @@ impl Clone for ShardedCache {
             shards: self.shards.clone(),
             config: self.config.clone(),
             enabled: self.enabled,
+            sub_shards: self.sub_shards,
+            router: self.router.clone(),
         }
     }
 }
 
 impl ShardedCache {
     ///
-    /// get shard logic is simple. mod of hash code with number of db instances.
-    /// in future, we can improve by different criteria
-    /// e.g Key suffix or prefix
+    /// get shard using the configured ShardRouter
     #[inline(always)]
     fn get_shard(&self, key: &[u8]) -> usize {
-        KeyVal::key_slot(key, self.config.num_shards) as usize
+        let shard = self.router.shard(&key, self.config.num_shards);
+        self.get_sub_shard(shard, &key)
+    }
+
+    /// value to store. shadow_mode only tracks the keys
+    #[inline(always)]
+    fn stored_value<'a>(&self, val: &'a [u8]) -> &'a [u8] {
+        if self.config.shadow_mode {
+            &[]
+        } else {
+            val
+        }
+    }
+
+    /// true if the value is too large to be cached
+    #[inline(always)]
+    fn is_oversized(&self, val: &[u8]) -> bool {
+        self.config.cache_max_value_size > 0 && val.len() > self.config.cache_max_value_size
     }
 
     #[inline(always)]
     fn get_shard_key_val(&self, kv: &KeyVal) -> usize {
-        kv.slot(self.config.num_shards) as usize
+        let shard = self.router.shard_key_val(&kv, self.config.num_shards);
+        self.get_sub_shard(shard, &kv.key)
+    }
+
+    /// index of the lru bucket within a shard chosen by a secondary hash of the key
+    #[inline(always)]
+    fn get_sub_shard(&self, shard: usize, key: &[u8]) -> usize {
+        if self.sub_shards <= 1 {
+            return shard;
+        }
+        let hash = KeyVal::get_hash_code_with_seed(key, SUB_SHARD_HASH_SEED);
+        shard * self.sub_shards + (hash % self.sub_shards as u64) as usize
     }
 
     #[inline(always)]
@@ -58,31 +96,152 @@ impl ShardedCache {
 
     /// create a new object
     /// make sure path is valid
-    pub fn new(config: &CacheConfig) -> ShardedCache {
-        assert!(config.num_shards > 0);
+    pub fn new(config: &CacheConfig) -> Result<ShardedCache, String> {
+        let router: Box<dyn ShardRouter> = match config.shard_router.as_str() {
+            "jumphash" => Box::new(JumpHashRouter {
+                seed: config.hash_seed,
+            }),
+            "modulo" => Box::new(ModuloRouter {
+                seed: config.hash_seed,
+            }),
+            other => {
+                error!("Invalid shard_router: {}", other);
+                return Err(format!("unknown shard_router: {}", other));
+            }
+        };
+        ShardedCache::with_router(config, router)
+    }
+
+    /// create a new object routing keys to shards with a custom ShardRouter
+    pub fn with_router(
+        config: &CacheConfig,
+        router: Box<dyn ShardRouter>,
+    ) -> Result<ShardedCache, String> {
+        let mut config = config.clone();
+        config.num_shards = config.effective_num_shards();
+        if config.num_shards == 0 {
+            return Err("num_shards must be > 0".to_string());
+        }
+        if config.enabled && config.cache_capacity < config.num_shards {
+            error!(
+                "cache_capacity: {} is less than num_shards: {}",
+                config.cache_capacity, config.num_shards
+            );
+            return Err(format!(
+                "cache_capacity ({}) must be >= num_shards ({})",
+                config.cache_capacity, config.num_shards
+            ));
+        }
+        let sub_shards = std::cmp::max(config.sub_shards_per_shard, 1);
+        if config.enabled && config.cache_capacity < config.num_shards * sub_shards {
+            error!(
+                "cache_capacity: {} is less than num_shards * sub_shards_per_shard: {}",
+                config.cache_capacity,
+                config.num_shards * sub_shards
+            );
+            return Err(format!(
+                "cache_capacity ({}) must be >= num_shards * sub_shards_per_shard ({})",
+                config.cache_capacity,
+                config.num_shards * sub_shards
+            ));
+        }
         let adjust = config.cache_capacity % config.num_shards as usize;
         let shard_capacity = (config.cache_capacity + adjust) / config.num_shards as usize;
 
-        assert!(shard_capacity > 0);
-        let mut shards: Vec<Lru> = Vec::with_capacity(config.num_shards as usize);
+        let sub_shard_capacity = std::cmp::max(shard_capacity / sub_shards, 1);
+        let mut shards: Vec<Lru> = Vec::with_capacity(config.num_shards * sub_shards);
         if config.enabled {
             info!(
-                "cache_capacity:{}, num_shards:{}, shard_capacity: {}",
-                config.cache_capacity, config.num_shards, shard_capacity
+                "cache_capacity:{}, num_shards:{}, shard_capacity: {}, sub_shards_per_shard: {}",
+                config.cache_capacity, config.num_shards, shard_capacity, sub_shards
             );
-            for i in 0..config.num_shards {
-                let lru = Lru::new(i, shard_capacity);
+            for i in 0..config.num_shards * sub_shards {
+                let lru = Lru::with_config(i, sub_shard_capacity, &config);
                 shards.push(lru);
             }
         } else {
             warn!("LruCache not enabled");
         }
 
-        ShardedCache {
+        Ok(ShardedCache {
             shards: Arc::new(shards),
-            config: config.clone(),
             enabled: config.enabled,
+            config,
+            sub_shards,
+            router: Arc::from(router),
+        })
+    }
+
+    /// start a background thread removing expired entries every cache_sweep_interval_secs.
+    /// each shard is locked only while it is being swept
+    pub fn start_sweeper(&self, shutdown: Arc<AtomicBool>) {
+        if !self.enabled || self.config.ttl_secs == 0 || self.config.cache_sweep_interval_secs == 0 {
+            debug!("Cache sweeper not enabled");
+            return;
         }
+        let shards = self.shards.clone();
+        let interval = Duration::from_secs(self.config.cache_sweep_interval_secs);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            if shutdown.load(Ordering::SeqCst) {
+                info!("Shutdown received. Exiting cache sweeper");
+                return;
+            }
+            let mut total = 0u64;
+            for shard in shards.iter() {
+                total += shard.remove_expired();
+            }
+            debug!("Cache sweeper removed {} expired entries", total);
+        });
+    }
+
+    #[inline]
+    pub fn num_shards(&self) -> usize {
+        self.config.num_shards
+    }
+
+    /// change the total capacity, divided across the shards.
+    /// shrinking evicts the excess entries of each shard immediately
+    pub fn resize(&self, new_total_capacity: usize) -> Result<(), String> {
+        if !self.enabled {
+            debug!("Cache is not enabled");
+            return Ok(());
+        }
+        let num_buckets = self.shards.len();
+        if new_total_capacity < num_buckets {
+            return Err(format!(
+                "cache_capacity ({}) must be >= number of shards ({})",
+                new_total_capacity, num_buckets
+            ));
+        }
+        let capacity = new_total_capacity / num_buckets;
+        let remainder = new_total_capacity % num_buckets;
+        info!("Resizing cache to capacity: {}", new_total_capacity);
+        for (i, shard) in self.shards.iter().enumerate() {
+            if i < remainder {
+                shard.resize(capacity + 1);
+            } else {
+                shard.resize(capacity);
+            }
+        }
+        Ok(())
+    }
+
+    /// number of entries across all the shards
+    pub fn total_len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().len()).sum()
+    }
+
+    /// number of entries of each shard, e.g. to detect a load imbalance.
+    /// each shard is locked only while its length is read.
+    /// with sub_shards_per_shard, there is a length per sub shard
+    pub fn shard_lengths(&self) -> Vec<usize> {
+        self.shards.iter().map(|shard| shard.len()).collect()
+    }
+
+    /// approximate bytes used by the keys and values across all the shards
+    pub fn estimated_bytes(&self) -> usize {
+        self.shards.iter().map(|shard| shard.estimated_bytes()).sum()
     }
 
     #[inline]
@@ -97,21 +256,68 @@ impl ShardedCache {
             debug!("Cache is not enabled");
             return Ok(());
         }
+        if self.config.shadow_mode {
+            for kv in data.iter() {
+                self.put_key_val(kv, &kv.val)?;
+            }
+            return Ok(());
+        }
+        //group by shard so each shard is locked once
+        let mut by_shard: Vec<(usize, &KeyVal)> = Vec::with_capacity(data.len());
         for kv in data.iter() {
             let shard = self.get_shard_key_val(&kv);
-            if let Err(e) = self.shards[shard].put(&kv.key, &kv.val) {
-                debug!(
-                    "Insert failed for the key: {}. Error: {:?}",
-                    String::from_utf8_lossy(&kv.key),
-                    e
-                );
+            if self.is_oversized(&kv.val) {
+                //drop the previous value, it is stale
+                self.shards[shard].delete(&kv.key)?;
+                continue;
+            }
+            by_shard.push((shard, kv));
+        }
+        by_shard.sort_by_key(|(shard, _)| *shard);
+        let mut start = 0;
+        while start < by_shard.len() {
+            let shard = by_shard[start].0;
+            let mut end = start;
+            while end < by_shard.len() && by_shard[end].0 == shard {
+                end += 1;
             }
+            if let Err(e) = self.shards[shard].put_iter(by_shard[start..end].iter().map(|(_, kv)| *kv)) {
+                debug!("Batch insert failed for the shard: {}. Error: {:?}", shard, e);
+            }
+            start = end;
         }
         Ok(())
     }
+    /// true if the cache records hits and misses without serving values
+    #[inline(always)]
+    pub fn shadow_mode(&self) -> bool {
+        self.enabled && self.config.shadow_mode
+    }
+
+    /// true if the key is cached, updating its recency as a get would.
+    /// used to record hits and misses in shadow_mode
+    #[inline]
+    pub fn touch(&self, key: &[u8]) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let shard = self.get_shard(&key);
+        self.shards[shard].get(&key).is_some()
+    }
+
     #[inline]
-    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+    pub fn touch_key_val(&self, kv: &KeyVal) -> bool {
         if !self.enabled {
+            return false;
+        }
+        let shard = self.get_shard_key_val(&kv);
+        self.shards[shard].get(&kv.key).is_some()
+    }
+
+    /// get the value. always None in shadow_mode
+    #[inline]
+    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if !self.enabled || self.config.shadow_mode {
             debug!("Cache is not enabled");
             return None;
         }
@@ -119,9 +325,40 @@ impl ShardedCache {
         self.shards[shard].get(&key)
     }
 
+    /// get the value without updating its recency
     #[inline]
-    pub fn get_key_val(&self, kv: &KeyVal) -> Option<Vec<u8>> {
+    pub fn peek(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if !self.enabled || self.config.shadow_mode {
+            debug!("Cache is not enabled");
+            return None;
+        }
+        let shard = self.get_shard(&key);
+        self.shards[shard].peek(&key)
+    }
+
+    /// true if the key is cached. doesn't update its recency
+    #[inline]
+    pub fn contains(&self, key: &[u8]) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let shard = self.get_shard(&key);
+        self.shards[shard].contains(&key)
+    }
+
+    /// true if the key is cached. doesn't update its recency
+    #[inline]
+    pub fn contains_key_val(&self, kv: &KeyVal) -> bool {
         if !self.enabled {
+            return false;
+        }
+        let shard = self.get_shard_key_val(&kv);
+        self.shards[shard].contains(&kv.key)
+    }
+
+    #[inline]
+    pub fn get_key_val(&self, kv: &KeyVal) -> Option<Vec<u8>> {
+        if !self.enabled || self.config.shadow_mode {
             debug!("Cache is not enabled");
             return None;
         }
@@ -131,12 +368,31 @@ impl ShardedCache {
 
     #[inline]
     pub fn put(&self, key: &[u8], val: &[u8]) -> Result<(), String> {
+        self.put_with_ttl(key, val, None).map(|_| ())
+    }
+
+    /// put the key val pair expiring after ttl_secs. None uses the ttl of the config.
+    /// returns false if the value is not cached: cache disabled, value too large or
+    /// shadow_mode only tracking the key
+    #[inline]
+    pub fn put_with_ttl(
+        &self,
+        key: &[u8],
+        val: &[u8],
+        ttl_secs: Option<u64>,
+    ) -> Result<bool, String> {
         if !self.enabled {
             debug!("Cache is not enabled");
-            return Ok(());
+            return Ok(false);
         }
         let shard = self.get_shard(&key);
-        self.shards[shard].put(&key, &val)
+        if self.is_oversized(&val) {
+            debug!("Value of {} bytes is too large to cache", val.len());
+            return self.shards[shard].delete(&key).map(|_| false);
+        }
+        let val = self.stored_value(val);
+        self.shards[shard].put_with_ttl(&key, val, ttl_secs.map(Duration::from_secs))?;
+        Ok(!self.config.shadow_mode)
     }
 
     #[inline]
@@ -146,7 +402,12 @@ impl ShardedCache {
             return Ok(());
         }
         let shard = self.get_shard_key_val(&kv);
-        self.shards[shard].put(&kv.key, &val)
+        if self.is_oversized(&val) {
+            debug!("Value of {} bytes is too large to cache", val.len());
+            return self.shards[shard].delete(&kv.key);
+        }
+        let val = self.stored_value(val);
+        self.shards[shard].put_with_ttl(&kv.key, val, kv.ttl_secs.map(Duration::from_secs))
     }
 
     #[inline]
@@ -159,6 +420,140 @@ impl ShardedCache {
         self.shards[shard].delete(&key)
     }
 
+    /// register a callback invoked with the key and value of each entry evicted from any shard.
+    /// It must be registered before the cache is cloned
+    pub fn on_evict(&mut self, f: Box<dyn Fn(&[u8], &[u8]) + Send + Sync>) -> Result<(), String> {
+        let on_evict: EvictCallback = Arc::from(f);
+        match Arc::get_mut(&mut self.shards) {
+            Some(shards) => {
+                for shard in shards.iter_mut() {
+                    shard.set_evict_callback(on_evict.clone());
+                }
+                Ok(())
+            }
+            None => Err("on_evict must be registered before the cache is cloned".to_string()),
+        }
+    }
+
+    /// copy all the entries into another cache, e.g. to warm a new instance.
+    /// keys are rehashed into the shard layout of the other cache, entries keep
+    /// their remaining ttl and pinned entries stay pinned. This cache is left intact.
+    /// returns number of entries copied
+    pub fn drain_into(&self, other: &ShardedCache) -> u64 {
+        //shadow_mode doesn't store the values
+        if !self.enabled || !other.enabled || self.config.shadow_mode {
+            debug!("Cache is not enabled");
+            return 0;
+        }
+        let mut total = 0;
+        for shard in self.shards.iter() {
+            let (entries, pinned) = shard.snapshot();
+            let now = Instant::now();
+            for (key, val, expires_at) in entries {
+                let ttl = match expires_at {
+                    Some(expires_at) if expires_at <= now => continue,
+                    Some(expires_at) => Some(expires_at - now),
+                    None => None,
+                };
+                let target = other.get_shard(&key);
+                if other.is_oversized(&val) {
+                    continue;
+                }
+                if other.shards[target].put_with_ttl(&key, &val, ttl).is_ok() {
+                    total += 1;
+                }
+            }
+            for (key, val) in pinned {
+                other.shards[other.get_shard(&key)].pin(&key, &val);
+                total += 1;
+            }
+        }
+        total
+    }
+
+    /// pin the key val pair in its shard so it is never evicted.
+    /// pinned entries don't count against the cache capacity
+    pub fn pin_key(&self, key: &[u8], val: &[u8]) -> Result<(), String> {
+        if !self.enabled {
+            debug!("Cache is not enabled");
+            return Err("cache is not enabled".to_string());
+        }
+        let shard = self.get_shard(&key);
+        self.shards[shard].pin(&key, &val);
+        Ok(())
+    }
+
+    /// unpin the key, making it subject to eviction again. returns false if the key is not pinned
+    pub fn unpin_key(&self, key: &[u8]) -> bool {
+        if !self.enabled {
+            debug!("Cache is not enabled");
+            return false;
+        }
+        let shard = self.get_shard(&key);
+        self.shards[shard].unpin(&key)
+    }
+
+    /// remove all the entries with the key starting with prefix from every shard.
+    /// keys are not ordered in the cache, so each shard is fully scanned.
+    /// returns number of entries removed
+    pub fn invalidate_prefix(&self, prefix: &[u8]) -> u64 {
+        if !self.enabled {
+            debug!("Cache is not enabled");
+            return 0;
+        }
+        self.shards.iter().map(|shard| shard.remove_prefix(prefix)).sum()
+    }
+
+    /// remove all the entries of every shard, including the pinned ones.
+    /// returns number of entries removed
+    pub fn clear(&self) -> u64 {
+        if !self.enabled {
+            debug!("Cache is not enabled");
+            return 0;
+        }
+        self.shards.iter().map(|shard| shard.clear()).sum()
+    }
+
+    /// lock two shards always in ascending index order to avoid deadlocks.
+    /// guards are returned in the order of the arguments. If both are the same shard,
+    /// only a single guard is returned
+    fn lock_two_shards(&self, a: usize, b: usize) -> (CacheGuard, Option<CacheGuard>) {
+        if a == b {
+            return (self.shards[a].lock(), None);
+        }
+        if a < b {
+            let guard_a = self.shards[a].lock();
+            let guard_b = self.shards[b].lock();
+            (guard_a, Some(guard_b))
+        } else {
+            let guard_b = self.shards[b].lock();
+            let guard_a = self.shards[a].lock();
+            (guard_a, Some(guard_b))
+        }
+    }
+
+    /// move the entry from one key to another atomically.
+    /// returns false if the source key is not found
+    pub fn rename(&self, from: &[u8], to: &[u8]) -> bool {
+        if !self.enabled {
+            debug!("Cache is not enabled");
+            return false;
+        }
+        let (from_shard, to_shard) = (self.get_shard(&from), self.get_shard(&to));
+        let (mut from_guard, to_guard) = self.lock_two_shards(from_shard, to_shard);
+        let entry = match from_guard.pop(&from) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        let evicted = match to_guard {
+            Some(mut to_guard) => to_guard.put(to.to_vec(), entry),
+            None => from_guard.put(to.to_vec(), entry),
+        };
+        drop(from_guard);
+        self.shards[to_shard].notify_evicted(evicted);
+        true
+    }
+
     pub fn export_keys(&self) -> Result<u64, String> {
         if !self.enabled {
             debug!("Cache is not enabled");
@@ -173,7 +568,9 @@ impl ShardedCache {
             return Ok(0);
         }
 
-        warn!("This is a blocking operation");
+        if self.config.export_chunk_size == 0 {
+            warn!("This is a blocking operation");
+        }
         info!("Exporting keys from the cache");
 
         let path = Path::new(&self.config.keys_dump_file);
@@ -198,6 +595,7 @@ impl ShardedCache {
         let mut file = match OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(true)
             .open(self.config.keys_dump_file.as_str())
         {
             Err(e) => {
@@ -217,7 +615,7 @@ impl ShardedCache {
         };
         let mut total = 0u64;
         for i in 0..self.shards.len() {
-            let count = self.shards[i].export_keys(&mut file)?;
+            let count = self.shards[i].export_keys(&mut file, &self.config)?;
             info!(
                 "LRU Shard:{} Exported {} keys to file {}",
                 i, count, self.config.keys_dump_file
@@ -234,6 +632,21 @@ impl ShardedCache {
         );
         Ok(total)
     }
+
+    /// read the keys from keys_dump_file written by export_keys
+    pub fn import_keys(&self) -> Result<Vec<Vec<u8>>, String> {
+        let data = match fs::read(&self.config.keys_dump_file) {
+            Ok(data) => data,
+            Err(e) => {
+                error!(
+                    "Failed to read file: {} for importing keys. Error:{:?}",
+                    self.config.keys_dump_file, e
+                );
+                return Err(e.to_string());
+            }
+        };
+        Lru::read_dump_keys(&data, &self.config)
+    }
 }
 
 #[cfg(test)]
@@ -242,9 +655,10 @@ mod tests {
     extern crate scoped_threadpool;
 
     use rand::distributions::Alphanumeric;
+    use parking_lot::Mutex;
     use rand::{thread_rng, Rng};
     use scoped_threadpool::Pool;
-    use std::collections::HashMap;
+    use std::collections::{HashMap, HashSet};
 
     use super::*;
 
@@ -359,7 +773,7 @@ mod tests {
         cache_config.num_shards = num_shards;
         for i in 6..32 {
             println!("Test started for key length: {}", i);
-            let shared_cache = ShardedCache::new(&cache_config);
+            let shared_cache = ShardedCache::new(&cache_config).unwrap();
             let mut data = HashMap::with_capacity(capacity);
             let mut pool = Pool::new(8);
             let shards_ref = &shared_cache;
@@ -488,4 +902,317 @@ mod tests {
             assert_eq!(val, cache_val.unwrap());
         }
     }
+
+    #[test]
+    fn test_sharded_cache_auto_shards() {
+        let mut cache_config = CacheConfig::default();
+        cache_config.num_shards = 3;
+        cache_config.auto_shards = true;
+        let cache = ShardedCache::new(&cache_config).unwrap();
+        let num_shards = cache.num_shards();
+        assert!(num_shards.is_power_of_two());
+        assert!(num_shards >= num_cpus::get() * crate::cache::config::AUTO_SHARDS_PER_CPU);
+        assert_eq!(cache.shards.len(), num_shards);
+
+        cache.put(b"auto_key", b"auto_val").unwrap();
+        assert_eq!(cache.get(b"auto_key"), Some(b"auto_val".to_vec()));
+    }
+
+    #[test]
+    fn test_sharded_cache_ttl_sweeper() {
+        let mut cache_config = CacheConfig::default();
+        cache_config.cache_capacity = 1_000;
+        cache_config.num_shards = 8;
+        cache_config.ttl_secs = 1;
+        cache_config.cache_sweep_interval_secs = 1;
+        let cache = ShardedCache::new(&cache_config).unwrap();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        cache.start_sweeper(shutdown.clone());
+        for i in 0..100 {
+            let key = format!("ttl_key_{}", i);
+            cache.put(key.as_bytes(), b"val").unwrap();
+        }
+        let len = |cache: &ShardedCache| cache.shards.iter().map(|shard| shard.len()).sum::<usize>();
+        assert_eq!(len(&cache), 100);
+        thread::sleep(Duration::from_secs(3));
+        assert_eq!(len(&cache), 0);
+        shutdown.store(true, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_sharded_cache_total_len_and_estimated_bytes() {
+        let mut cache_config = CacheConfig::default();
+        cache_config.cache_capacity = 1_000;
+        cache_config.num_shards = 8;
+        let cache = ShardedCache::new(&cache_config).unwrap();
+        let mut expected = 0;
+        for i in 0..100 {
+            let key = format!("bytes_key_{:03}", i);
+            let val = vec![b'v'; i];
+            cache.put(key.as_bytes(), &val).unwrap();
+            expected += key.len() + val.len();
+        }
+        assert_eq!(cache.total_len(), 100);
+        assert_eq!(cache.estimated_bytes(), expected);
+    }
+
+    #[test]
+    fn test_sharded_cache_invalidate_prefix() {
+        let mut cache_config = CacheConfig::default();
+        cache_config.cache_capacity = 1_000;
+        cache_config.num_shards = 8;
+        let cache = ShardedCache::new(&cache_config).unwrap();
+        for i in 0..50 {
+            cache.put(format!("tenant:42:{}", i).as_bytes(), b"val").unwrap();
+            cache.put(format!("tenant:7:{}", i).as_bytes(), b"val").unwrap();
+        }
+        assert_eq!(cache.invalidate_prefix(b"tenant:42:"), 50);
+        assert_eq!(cache.total_len(), 50);
+        for i in 0..50 {
+            assert_eq!(cache.get(format!("tenant:42:{}", i).as_bytes()), None);
+            assert_eq!(cache.get(format!("tenant:7:{}", i).as_bytes()), Some(b"val".to_vec()));
+        }
+        assert_eq!(cache.invalidate_prefix(b"tenant:42:"), 0);
+    }
+
+    #[test]
+    fn test_sharded_cache_resize() {
+        for policy in &["lru", "lfu"] {
+            let mut cache_config = CacheConfig::default();
+            cache_config.cache_capacity = 1_000;
+            cache_config.num_shards = 4;
+            cache_config.eviction_policy = policy.to_string();
+            let cache = ShardedCache::new(&cache_config).unwrap();
+            for i in 0..10_000 {
+                cache.put(format!("resize_key_{}", i).as_bytes(), b"val").unwrap();
+            }
+            assert_eq!(cache.total_len(), 1_000);
+
+            cache.resize(102).unwrap();
+            assert_eq!(cache.total_len(), 102);
+
+            cache.resize(2_000).unwrap();
+            for i in 0..10_000 {
+                cache.put(format!("resize_key_{}", i).as_bytes(), b"val").unwrap();
+            }
+            assert_eq!(cache.total_len(), 2_000);
+            assert!(cache.resize(2).is_err());
+        }
+    }
+
+    #[test]
+    fn test_sharded_cache_export_import_binary_keys() {
+        for binary in &[true, false] {
+            let mut cache_config = CacheConfig::default();
+            cache_config.cache_capacity = 1_000;
+            cache_config.num_shards = 4;
+            cache_config.keys_dump_file =
+                format!("/tmp/bhatho_test/keys_dump_binary_{}.dump", binary);
+            cache_config.keys_dump_binary = *binary;
+            cache_config.keys_dump_separator = "|".to_string();
+            let cache = ShardedCache::new(&cache_config).unwrap();
+            let mut keys = HashSet::new();
+            for i in 0..100 {
+                let key = if *binary {
+                    format!("dump\r\nkey\x00{}", i)
+                } else {
+                    format!("dump\r\nkey_{}", i)
+                };
+                cache.put(key.as_bytes(), b"val").unwrap();
+                keys.insert(key.into_bytes());
+            }
+            assert_eq!(cache.export_keys().unwrap(), 100);
+            let imported: HashSet<Vec<u8>> = cache.import_keys().unwrap().into_iter().collect();
+            assert_eq!(imported, keys);
+        }
+    }
+
+    #[test]
+    fn test_sharded_cache_custom_router() {
+        struct FirstShardRouter;
+        impl ShardRouter for FirstShardRouter {
+            fn shard(&self, _key: &[u8], _num_shards: usize) -> usize {
+                0
+            }
+        }
+
+        let mut cache_config = CacheConfig::default();
+        cache_config.cache_capacity = 1_000;
+        cache_config.num_shards = 8;
+        let cache = ShardedCache::with_router(&cache_config, Box::new(FirstShardRouter)).unwrap();
+        for i in 0..100 {
+            let key = format!("router_key_{}", i);
+            cache.put(key.as_bytes(), b"val").unwrap();
+            let kv = KeyVal::new_with_key(key.as_bytes());
+            assert_eq!(cache.get_key_val(&kv), Some(b"val".to_vec()));
+        }
+        assert_eq!(cache.shards[0].lock().len(), 100);
+        assert_eq!(cache.total_len(), 100);
+    }
+
+    #[test]
+    fn test_sharded_cache_capacity_less_than_shards() {
+        let mut cache_config = CacheConfig::default();
+        cache_config.cache_capacity = 10;
+        cache_config.num_shards = 1024;
+        match ShardedCache::new(&cache_config) {
+            Err(e) => assert_eq!(e, "cache_capacity (10) must be >= num_shards (1024)"),
+            Ok(_) => panic!("expected an error for cache_capacity < num_shards"),
+        }
+    }
+
+    #[test]
+    fn test_sharded_cache_sub_shards_mt() {
+        let capacity = 100_000;
+        let mut cache_config = CacheConfig::default();
+        cache_config.cache_capacity = capacity;
+        cache_config.num_shards = 4;
+        cache_config.sub_shards_per_shard = 8;
+        let cache = ShardedCache::new(&cache_config).unwrap();
+        assert_eq!(cache.shards.len(), 32);
+        assert_eq!(cache.num_shards(), 4);
+
+        let mut small_config = cache_config.clone();
+        small_config.cache_capacity = 16;
+        assert!(ShardedCache::new(&small_config).is_err());
+
+        let mut pool = Pool::new(8);
+        let cache_ref = &cache;
+        pool.scoped(|scoped| {
+            for t in 0..8 {
+                scoped.execute(move || {
+                    for i in 0..capacity / 8 {
+                        let key = format!("sub_shard_{}_{}", t, i);
+                        cache_ref.put(key.as_bytes(), key.as_bytes()).unwrap();
+                    }
+                });
+            }
+        });
+        let total: usize = cache.shards.iter().map(|shard| shard.lock().len()).sum();
+        assert!(total > capacity * 9 / 10);
+
+        pool.scoped(|scoped| {
+            for t in 0..8 {
+                scoped.execute(move || {
+                    for i in (0..capacity / 8).step_by(100) {
+                        let key = format!("sub_shard_{}_{}", t, i);
+                        let kv = KeyVal::new_with_key(key.as_bytes());
+                        if let Some(val) = cache_ref.get(key.as_bytes()) {
+                            assert_eq!(val, key.as_bytes().to_vec());
+                            assert_eq!(cache_ref.get_key_val(&kv), Some(val));
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_sharded_cache_concurrent_cross_shard_rename() {
+        use std::sync::mpsc;
+
+        let mut cache_config = CacheConfig::default();
+        cache_config.cache_capacity = 1_000;
+        cache_config.num_shards = 4;
+        let cache = Arc::new(ShardedCache::new(&cache_config).unwrap());
+        let num_pairs = 50;
+        for i in 0..num_pairs {
+            cache.put(format!("left_{}", i).as_bytes(), b"val").unwrap();
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let workers = cache.clone();
+        thread::spawn(move || {
+            let mut pool = Pool::new(8);
+            pool.scoped(|scoped| {
+                for t in 0..8 {
+                    let cache = &workers;
+                    scoped.execute(move || {
+                        for round in 0..1_000 {
+                            let i = (t * 7 + round) % num_pairs;
+                            let (left, right) = (format!("left_{}", i), format!("right_{}", i));
+                            if round % 2 == 0 {
+                                cache.rename(left.as_bytes(), right.as_bytes());
+                            } else {
+                                cache.rename(right.as_bytes(), left.as_bytes());
+                            }
+                        }
+                    });
+                }
+            });
+            tx.send(()).unwrap();
+        });
+        assert!(rx.recv_timeout(Duration::from_secs(60)).is_ok(), "rename deadlocked");
+
+        for i in 0..num_pairs {
+            let left = cache.get(format!("left_{}", i).as_bytes());
+            let right = cache.get(format!("right_{}", i).as_bytes());
+            assert!(left.is_some() ^ right.is_some());
+        }
+    }
+
+    #[test]
+    fn test_sharded_cache_shard_lengths() {
+        let mut cache_config = CacheConfig::default();
+        cache_config.cache_capacity = 10_000;
+        cache_config.num_shards = 8;
+        let cache = ShardedCache::new(&cache_config).unwrap();
+        let total = 1_000;
+        for i in 0..total {
+            cache.put(format!("occupancy_{}", i).as_bytes(), b"val").unwrap();
+        }
+        let lengths = cache.shard_lengths();
+        assert_eq!(lengths.len(), 8);
+        assert_eq!(lengths.iter().sum::<usize>(), total);
+        assert!(lengths.iter().all(|len| *len > 0));
+    }
+
+    #[test]
+    fn test_sharded_cache_drain_into_different_num_shards() {
+        let mut cache_config = CacheConfig::default();
+        cache_config.cache_capacity = 10_000;
+        cache_config.num_shards = 4;
+        let source = ShardedCache::new(&cache_config).unwrap();
+        cache_config.num_shards = 16;
+        let target = ShardedCache::new(&cache_config).unwrap();
+        let total = 1_000;
+        for i in 0..total {
+            let key = format!("transfer_{}", i);
+            source.put(key.as_bytes(), key.as_bytes()).unwrap();
+        }
+        source.pin_key(b"pinned_key", b"pinned_val").unwrap();
+
+        assert_eq!(source.drain_into(&target), total as u64 + 1);
+        for i in 0..total {
+            let key = format!("transfer_{}", i);
+            assert_eq!(target.get(key.as_bytes()), Some(key.as_bytes().to_vec()));
+        }
+        assert_eq!(target.get(b"pinned_key"), Some(b"pinned_val".to_vec()));
+        assert!(target.unpin_key(b"pinned_key"));
+        assert_eq!(source.total_len(), total);
+    }
+
+    #[test]
+    fn test_sharded_cache_on_evict() {
+        let mut cache_config = CacheConfig::default();
+        cache_config.cache_capacity = 100;
+        cache_config.num_shards = 4;
+        let mut cache = ShardedCache::new(&cache_config).unwrap();
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        {
+            let evicted = evicted.clone();
+            cache
+                .on_evict(Box::new(move |key, _| evicted.lock().push(key.to_vec())))
+                .unwrap();
+        }
+        for i in 0..1_000 {
+            cache.put(format!("evict_{}", i).as_bytes(), b"val").unwrap();
+        }
+        let evicted = evicted.lock();
+        assert_eq!(evicted.len() + cache.total_len(), 1_000);
+        assert!(evicted.iter().all(|key| cache.get(key).is_none()));
+
+        let _clone = cache.clone();
+        assert!(cache.on_evict(Box::new(|_, _| {})).is_err());
+    }
 }
@@ -0,0 +1,74 @@
+/************************************************
+
+   File Name: bhatho:db::cipher
+   Author: Rohit Joshi <rohit.c.joshi@gmail.com>
+   Date: 2019-02-17:15:15
+   License: Apache 2.0
+
+**************************************************/
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::Aes256Gcm;
+use rand::Rng;
+
+use crate::db::codec::ValueCodec;
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+/// AES-256-GCM encryption of the values stored in the db.
+/// every value is encrypted with a random nonce, stored as a prefix of the ciphertext
+pub struct ValueCipher {
+    cipher: Aes256Gcm,
+}
+
+impl ValueCipher {
+    /// create a cipher from a hex encoded 32 bytes key
+    pub fn from_hex_key(hex_key: &str) -> Result<ValueCipher, String> {
+        let key = ValueCipher::decode_hex(hex_key.trim())?;
+        if key.len() != KEY_LEN {
+            return Err(format!("encryption_key must be {} bytes hex encoded", KEY_LEN));
+        }
+        Ok(ValueCipher {
+            cipher: Aes256Gcm::new(GenericArray::clone_from_slice(&key)),
+        })
+    }
+
+    fn decode_hex(hex: &str) -> Result<Vec<u8>, String> {
+        if hex.len() % 2 != 0 {
+            return Err("encryption_key is not valid hex".to_string());
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| {
+                u8::from_str_radix(&hex[i..i + 2], 16)
+                    .map_err(|_| "encryption_key is not valid hex".to_string())
+            })
+            .collect()
+    }
+}
+
+impl ValueCodec for ValueCipher {
+    fn encode(&self, raw: &[u8]) -> Result<Vec<u8>, String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill(&mut nonce);
+        let ciphertext = self
+            .cipher
+            .encrypt(GenericArray::from_slice(&nonce), raw)
+            .map_err(|_| "failed to encrypt the value".to_string())?;
+        let mut stored = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        stored.extend_from_slice(&nonce);
+        stored.extend_from_slice(&ciphertext);
+        Ok(stored)
+    }
+
+    fn decode(&self, stored: &[u8]) -> Result<Vec<u8>, String> {
+        if stored.len() < NONCE_LEN {
+            return Err("encrypted value is too short".to_string());
+        }
+        let (nonce, ciphertext) = stored.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(GenericArray::from_slice(nonce), ciphertext)
+            .map_err(|_| "failed to decrypt the value".to_string())
+    }
+}
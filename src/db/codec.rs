@@ -0,0 +1,33 @@
+/************************************************
+
+   File Name: bhatho:db::codec
+   Author: Rohit Joshi <rohit.c.joshi@gmail.com>
+   Date: 2019-02-17:15:15
+   License: Apache 2.0
+
+**************************************************/
+
+/// Transforms the values written to and read from the db and the cache,
+/// e.g. to validate, compress or encrypt them
+pub trait ValueCodec: Send + Sync {
+    /// value to store for a given value
+    fn encode(&self, raw: &[u8]) -> Result<Vec<u8>, String>;
+
+    /// value to return for a stored value
+    fn decode(&self, stored: &[u8]) -> Result<Vec<u8>, String>;
+}
+
+/// stores the values as they are. It is the default codec
+pub struct IdentityCodec;
+
+impl ValueCodec for IdentityCodec {
+    #[inline(always)]
+    fn encode(&self, raw: &[u8]) -> Result<Vec<u8>, String> {
+        Ok(raw.to_vec())
+    }
+
+    #[inline(always)]
+    fn decode(&self, stored: &[u8]) -> Result<Vec<u8>, String> {
+        Ok(stored.to_vec())
+    }
+}
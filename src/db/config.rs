@@ -11,10 +11,23 @@ use std::str;
 use crate::cache::config::CacheConfig;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct DbManagerConfig {
     pub name: String,
     pub rocks_db_config: RocksDbConfig,
     pub cache_config: CacheConfig,
+    /// serve from cache only if the db fails to open instead of failing the startup
+    pub degrade_to_cache_on_db_error: bool,
+    /// prepended to every key stored in the db and the cache, so multiple logical tables
+    /// can share a db. callers use the keys without the prefix
+    pub key_prefix: String,
+    /// hex encoded 32 bytes key. when set, values are encrypted with AES-256-GCM
+    /// before they are written to the db. keys and the cache stay in plaintext
+    #[serde(default)]
+    pub encryption_key: Option<String>,
+    /// puts of keys not matching the regex are rejected. reads are not checked
+    #[serde(default)]
+    pub key_validation_regex: Option<String>,
 }
 
 impl Default for DbManagerConfig {
@@ -25,17 +38,29 @@ impl Default for DbManagerConfig {
             name: "".to_string(),
             rocks_db_config,
             cache_config,
+            degrade_to_cache_on_db_error: false,
+            key_prefix: "".to_string(),
+            encryption_key: None,
+            key_validation_regex: None,
         }
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct RocksDbConfig {
     pub enabled: bool,
     pub async_write: bool,
+    /// puts block once async_write_queue_length KeyVals are waiting for the writers
     pub async_write_queue_length: usize,
+    /// never block the puts, ignoring async_write_queue_length. if the writers fall behind,
+    /// the queue grows without bound and can run the process out of memory
+    pub async_write_unbounded: bool,
     pub num_async_writer_threads: usize,
+    /// async writers back off exponentially from async_writer_min_sleep_ms
+    /// to async_writer_threads_sleep_ms while the queue stays empty
     pub async_writer_threads_sleep_ms: u64,
+    pub async_writer_min_sleep_ms: u64,
     pub db_path: String,
     pub wal_dir: String,
     pub backup_path: String,
@@ -55,12 +80,87 @@ pub struct RocksDbConfig {
     pub num_shard_bits: i32,
     pub disable_wal: bool,
     pub bloom_filter: bool,
+    pub bloom_filter_bits_per_key: i32,
+    pub bloom_filter_block_based: bool,
     pub enable_statistics: bool,
     pub restore_from_backup_at_startup: bool,
     pub keep_log_file_while_restore: bool,
+    /// block cache size in MB for optimize_for_point_lookup. 0 skips it.
+    /// It tunes the db for gets at the cost of range scans. Its block based table
+    /// (hash index, bloom filter, block cache) is used only with use_default_block_config,
+    /// otherwise the configured block options replace it
     pub point_lookup_block_size_mb: u64,
     pub use_default_config: bool,
     pub use_default_block_config: bool,
+    pub scan_readahead_size_mb: usize,
+    pub column_families: Vec<String>,
+    /// max key size in bytes. 0 means unlimited
+    pub max_key_size: usize,
+    /// max value size in bytes. 0 means unlimited
+    pub max_value_size: usize,
+    /// log async writes to a file so writes still queued are recovered after a crash
+    pub durable_async_queue: bool,
+    /// path of the durable async queue file. defaults to async_queue.log under db_path
+    pub durable_async_queue_path: String,
+    /// fsync the durable async queue at most every durable_async_queue_sync_ms.
+    /// 0 syncs on every write. otherwise writes of the last interval can be lost on power failure
+    pub durable_async_queue_sync_ms: u64,
+    /// restore every new backup into a scratch path to make sure it is usable.
+    /// a backup failing the verification is deleted
+    pub verify_backup_on_create: bool,
+    /// merge operator registered with the db. "append" concatenates the merged values.
+    /// empty disables merge
+    pub merge_operator: String,
+    /// number of times to retry opening the db if it is locked by another process
+    pub open_retry_count: usize,
+    pub open_retry_sleep_ms: u64,
+    /// open an existing db for reads only. writes, deletes and backups return an error
+    pub open_read_only: bool,
+    /// block index type when use_default_block_config is false.
+    /// "hash_search", "binary_search" or "two_level". hash_search is faster for point lookups,
+    /// binary_search and two_level are better for range scans
+    pub index_type: String,
+    /// WAL files are archived once obsolete. archived WAL files older than wal_ttl_secs
+    /// are purged, and oldest ones are purged once archive exceeds wal_size_limit_mb.
+    /// 0 disables the limit
+    pub wal_size_limit_mb: u64,
+    pub wal_ttl_secs: u64,
+    /// max number of KeyVals an async writer drains from the queue per batch write.
+    /// more pending KeyVals are written in the next batches. 0 means unbounded
+    pub max_write_batch_size: usize,
+    /// how to recover the WAL after a crash. "tolerate_corrupted_tail", "absolute_consistency",
+    /// "point_in_time" or "skip_any_corrupted". point_in_time and skip_any_corrupted open a db
+    /// with a corrupted WAL at the cost of losing the writes after the corruption
+    pub wal_recovery_mode: String,
+    /// take a backup every auto_backup_interval_secs, keeping the latest num_backups_to_keep.
+    /// 0 disables scheduled backups
+    pub auto_backup_interval_secs: u64,
+    pub num_backups_to_keep: usize,
+    /// memtable representation. "skiplist", "hash_skiplist" or "vector".
+    /// hash_skiplist requires index_type hash_search with use_default_block_config false.
+    /// hash_skiplist and vector disable concurrent memtable writes
+    pub memtable_factory: String,
+    /// shard bits of the block cache of lru_cache_size_mb, separate from num_shard_bits
+    /// of the table cache. -1 keeps the rocksdb default
+    pub block_cache_shard_bits: i32,
+    /// "level", "universal" or "fifo" when use_default_config is false.
+    /// universal trades space for lower write amplification, fifo drops the oldest files
+    /// once their total size exceeds fifo_max_table_files_size_mb
+    pub compaction_style: String,
+    pub fifo_max_table_files_size_mb: u64,
+    /// level 0 file counts starting a compaction, slowing down and stopping the writes
+    /// when use_default_config is false. -1 keeps the rocksdb default.
+    /// a high stop trigger absorbs write bursts but lets level 0 grow without bound,
+    /// slowing down reads and the compactions catching up
+    pub level0_file_num_compaction_trigger: i32,
+    pub level0_slowdown_writes_trigger: i32,
+    pub level0_stop_writes_trigger: i32,
+    /// keep the filter and index blocks of level 0 files pinned in the block cache
+    /// when use_default_block_config is false. avoids re-reading them on point lookups
+    pub pin_l0_filter_and_index_blocks: bool,
+    /// insert the filter and index blocks in the high priority pool of the block cache,
+    /// so data blocks are evicted first
+    pub cache_index_and_filter_blocks_with_high_priority: bool,
 }
 
 impl Default for RocksDbConfig {
@@ -69,8 +169,10 @@ impl Default for RocksDbConfig {
             enabled: true,
             async_write: true,
             async_write_queue_length: 5_000_000,
+            async_write_unbounded: false,
             num_async_writer_threads: 1,
             async_writer_threads_sleep_ms: 250,
+            async_writer_min_sleep_ms: 1,
             db_path: "/tmp/kanudo_db".to_string(),
             wal_dir: "/tmp/kanudo_db/wal".to_string(),
             backup_path: "/tmp/kanudo_db_bkup".to_string(),
@@ -90,12 +192,56 @@ impl Default for RocksDbConfig {
             num_shard_bits: 12,
             disable_wal: false,
             bloom_filter: false,
+            bloom_filter_bits_per_key: 10,
+            bloom_filter_block_based: true,
             enable_statistics: true,
             restore_from_backup_at_startup: true,
             keep_log_file_while_restore: true,
-            point_lookup_block_size_mb: 10240,
+            point_lookup_block_size_mb: 0,
             use_default_config: true,
             use_default_block_config: true,
+            scan_readahead_size_mb: 0,
+            column_families: vec![],
+            max_key_size: 0,
+            max_value_size: 0,
+            durable_async_queue: false,
+            durable_async_queue_path: "".to_string(),
+            durable_async_queue_sync_ms: 0,
+            verify_backup_on_create: false,
+            merge_operator: "".to_string(),
+            open_retry_count: 0,
+            open_retry_sleep_ms: 1000,
+            open_read_only: false,
+            index_type: "hash_search".to_string(),
+            wal_size_limit_mb: 0,
+            wal_ttl_secs: 0,
+            max_write_batch_size: 0,
+            wal_recovery_mode: "tolerate_corrupted_tail".to_string(),
+            auto_backup_interval_secs: 0,
+            num_backups_to_keep: 5,
+            memtable_factory: "skiplist".to_string(),
+            block_cache_shard_bits: -1,
+            compaction_style: "level".to_string(),
+            fifo_max_table_files_size_mb: 1024,
+            level0_file_num_compaction_trigger: -1,
+            level0_slowdown_writes_trigger: -1,
+            level0_stop_writes_trigger: -1,
+            pin_l0_filter_and_index_blocks: false,
+            cache_index_and_filter_blocks_with_high_priority: false,
         }
     }
 }
+
+impl RocksDbConfig {
+    /// check key and value sizes against max_key_size and max_value_size
+    #[inline]
+    pub fn validate_size(&self, key: &[u8], val: &[u8]) -> Result<(), String> {
+        if self.max_key_size > 0 && key.len() > self.max_key_size {
+            return Err("key exceeds max_key_size".to_string());
+        }
+        if self.max_value_size > 0 && val.len() > self.max_value_size {
+            return Err("value exceeds max_value_size".to_string());
+        }
+        Ok(())
+    }
+}
@@ -6,14 +6,69 @@
    License: Apache 2.0
 
 **************************************************/
+use parking_lot::{Mutex, MutexGuard, RwLock};
+use regex::bytes::Regex;
+use std::borrow::Cow;
+use std::cell::RefCell;
 use std::sync::Arc;
-use std::sync::atomic::AtomicBool;
+use std::thread;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::cache::sharded_cache::ShardedCache;
+use crate::db::cipher::ValueCipher;
+use crate::db::codec::ValueCodec;
 use crate::db::config::DbManagerConfig;
-use crate::db::rocks_db::RocksDb;
+use crate::db::rocks_db::{BackupInfo, LevelStat, RocksDb};
+use crate::db::transaction::Txn;
 use crate::keyval::KeyVal;
 
+thread_local! {
+    //reused by put_with, so building a value doesn't allocate once the buffer has grown
+    static PUT_BUFFER: RefCell<Vec<u8>> = RefCell::new(Vec::new());
+}
+
+/// iterator over the key-val pairs of a db. a value failing to decode is an error item
+pub type KeyValIter<'a> = Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>), String>> + 'a>;
+
+/// where a put was stored. persisted is true once the db accepted the write,
+/// with async_write it is still queued for the writer threads
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PutOutcome {
+    pub persisted: bool,
+    pub cached: bool,
+}
+
+/// snapshot of the cache and db stats of a DbManager
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DbMetrics {
+    pub name: String,
+    pub cache_len: usize,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub disk_reads: u64,
+    pub write_queue_len: usize,
+    pub estimate_num_keys: u64,
+}
+
+/// approximate memory used by each component of a DbManager in bytes
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MemoryBreakdown {
+    pub name: String,
+    pub cache_bytes: u64,
+    pub block_cache_bytes: u64,
+    pub memtable_bytes: u64,
+    pub write_queue_bytes: u64,
+}
+
+/// read counters shared by the clones of a DbManager
+#[derive(Default)]
+struct ReadCounters {
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    disk_reads: AtomicU64,
+}
+
 /// DbManager
 /// It is a wrapper around multiple database instances
 pub struct DbManager {
@@ -21,6 +76,14 @@ pub struct DbManager {
     db: Option<Arc<RocksDb>>,
     cache: Arc<ShardedCache>,
     config: DbManagerConfig,
+    shard_locks: Arc<Vec<Mutex<()>>>,
+    read_through_buffer: Arc<Mutex<Vec<KeyVal>>>,
+    //incremented by swap_db. read-through updates of an older epoch are dropped
+    swap_epoch: Arc<RwLock<u64>>,
+    degraded: bool,
+    counters: Arc<ReadCounters>,
+    codec: Option<Arc<dyn ValueCodec>>,
+    key_validator: Option<Regex>,
 }
 
 unsafe impl Send for DbManager {}
@@ -36,6 +99,13 @@ impl Clone for DbManager {
             db: self.db.clone(),
             cache: self.cache.clone(),
             config: self.config.clone(),
+            shard_locks: self.shard_locks.clone(),
+            read_through_buffer: self.read_through_buffer.clone(),
+            swap_epoch: self.swap_epoch.clone(),
+            degraded: self.degraded,
+            counters: self.counters.clone(),
+            codec: self.codec.clone(),
+            key_validator: self.key_validator.clone(),
         }
     }
 }
@@ -45,41 +115,327 @@ impl DbManager {
     pub fn new(config: &DbManagerConfig, shutdown: Arc<AtomicBool>) -> Result<DbManager, String> {
         //RocksDbConfig
 
+        let cipher = match config.encryption_key.as_ref() {
+            Some(key) => Some(Arc::new(ValueCipher::from_hex_key(key)?)),
+            None => None,
+        };
+        let key_validator = match config.key_validation_regex.as_ref() {
+            Some(re) => match Regex::new(re) {
+                Ok(re) => Some(re),
+                Err(e) => return Err(format!("invalid key_validation_regex: {}. Error:{}", re, e)),
+            },
+            None => None,
+        };
+        let mut degraded = false;
         let db = if config.rocks_db_config.enabled {
-            let rocks_db = RocksDb::new(&config.rocks_db_config, shutdown)?;
-            Some(Arc::new(rocks_db))
+            match RocksDb::new(&config.rocks_db_config, shutdown.clone()) {
+                Ok(mut rocks_db) => {
+                    if let Some(cipher) = cipher {
+                        rocks_db.set_cipher(cipher);
+                    }
+                    Some(Arc::new(rocks_db))
+                }
+                Err(e) if config.degrade_to_cache_on_db_error => {
+                    error!(
+                        "Failed to open db: {}. Serving from cache only. Error:{}",
+                        config.name, e
+                    );
+                    degraded = true;
+                    None
+                }
+                Err(e) => return Err(e),
+            }
         } else {
             None
         };
-        let cache = ShardedCache::new(&config.cache_config);
+        let cache = Arc::new(ShardedCache::new(&config.cache_config)?);
+        cache.start_sweeper(shutdown);
+        if let Some(db) = db.as_ref() {
+            if db.restored_from_backup() && config.cache_config.warm_cache_keys > 0 {
+                let num_keys = config.cache_config.warm_cache_keys;
+                let prefix = config.key_prefix.as_bytes().to_vec();
+                DbManager::warm_cache(db.clone(), cache.clone(), prefix, num_keys);
+            }
+        }
 
+        let num_locks = std::cmp::max(cache.num_shards(), 1);
+        let mut shard_locks = Vec::with_capacity(num_locks);
+        for _ in 0..num_locks {
+            shard_locks.push(Mutex::new(()));
+        }
 
         Ok(DbManager {
             name: config.name.clone(),
             db,
-            cache: Arc::new(cache),
+            cache,
             config: config.clone(),
+            shard_locks: Arc::new(shard_locks),
+            read_through_buffer: Arc::new(Mutex::new(Vec::with_capacity(
+                config.cache_config.read_through_batch_size,
+            ))),
+            swap_epoch: Arc::new(RwLock::new(0)),
+            degraded,
+            counters: Arc::new(ReadCounters::default()),
+            codec: None,
+            key_validator,
         })
     }
 
+    /// create a DbManager instance encoding the values with a given codec.
+    /// values are encoded before they are written to the db or the cache and
+    /// decoded when they are read. append is not supported with a codec
+    pub fn with_value_codec(
+        config: &DbManagerConfig,
+        shutdown: Arc<AtomicBool>,
+        codec: Box<dyn ValueCodec>,
+    ) -> Result<DbManager, String> {
+        let mut db_manager = DbManager::new(config, shutdown)?;
+        db_manager.codec = Some(Arc::from(codec));
+        Ok(db_manager)
+    }
+
+    /// value as stored with the codec
+    #[inline(always)]
+    fn encode_value<'a>(&self, val: &'a [u8]) -> Result<Cow<'a, [u8]>, String> {
+        match self.codec.as_ref() {
+            Some(codec) => Ok(Cow::Owned(codec.encode(val)?)),
+            None => Ok(Cow::Borrowed(val)),
+        }
+    }
+
+    /// KeyVals with the values encoded with the codec
+    fn encode_key_vals<'a>(&self, data: &'a [KeyVal]) -> Result<Cow<'a, [KeyVal]>, String> {
+        let codec = match self.codec.as_ref() {
+            Some(codec) => codec,
+            None => return Ok(Cow::Borrowed(data)),
+        };
+        let mut encoded = Vec::with_capacity(data.len());
+        for kv in data.iter() {
+            let mut kv = kv.clone();
+            kv.val = codec.encode(&kv.val)?;
+            encoded.push(kv);
+        }
+        Ok(Cow::Owned(encoded))
+    }
+
+    /// value decoded with the codec
+    #[inline(always)]
+    fn decode_value(
+        &self,
+        stored: Option<(Vec<u8>, bool)>,
+    ) -> Result<Option<(Vec<u8>, bool)>, String> {
+        match (self.codec.as_ref(), stored) {
+            (Some(codec), Some((val, from_cache))) => Ok(Some((codec.decode(&val)?, from_cache))),
+            (_, stored) => Ok(stored),
+        }
+    }
+
+    /// error if the key doesn't match key_validation_regex
+    #[inline(always)]
+    fn validate_key(&self, key: &[u8]) -> Result<(), String> {
+        validate_key(self.key_validator.as_ref(), key)
+    }
+
+    /// key-val pairs with the values decoded with the codec
+    fn decode_pairs(
+        &self,
+        data: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, String> {
+        let codec = match self.codec.as_ref() {
+            Some(codec) => codec,
+            None => return Ok(data),
+        };
+        data.into_iter().map(|(key, val)| Ok((key, codec.decode(&val)?))).collect()
+    }
+
+    /// load the first num_keys key-val pairs of the db with the key prefix into the cache
+    /// on a background thread
+    fn warm_cache(db: Arc<RocksDb>, cache: Arc<ShardedCache>, prefix: Vec<u8>, num_keys: usize) {
+        thread::spawn(move || {
+            info!("Warming cache with up to {} keys", num_keys);
+            let iter = match db.iter_all() {
+                Ok(iter) => iter,
+                Err(e) => {
+                    error!("Failed to warm cache. Error:{}", e);
+                    return;
+                }
+            };
+            let mut total = 0;
+            for item in iter {
+                let (key, val) = match item {
+                    Ok(kv) => kv,
+                    Err(e) => {
+                        error!("Failed to warm cache. Error:{}", e);
+                        return;
+                    }
+                };
+                if total >= num_keys {
+                    break;
+                }
+                if !key.starts_with(&prefix) {
+                    continue;
+                }
+                if let Err(e) = cache.put(&key, &val) {
+                    error!("Failed to warm cache. Error:{}", e);
+                    return;
+                }
+                total += 1;
+            }
+            info!("Warmed cache with {} keys", total);
+        });
+    }
+
+    /// true if the db failed to open and requests are served from cache only
+    #[inline]
+    pub fn is_degraded(&self) -> bool {
+        self.degraded
+    }
+
+    /// key with the key_prefix of the config prepended
+    #[inline(always)]
+    fn prefix_key<'a>(&self, key: &'a [u8]) -> Cow<'a, [u8]> {
+        let prefix = self.config.key_prefix.as_bytes();
+        if prefix.is_empty() {
+            return Cow::Borrowed(key);
+        }
+        let mut prefixed = Vec::with_capacity(prefix.len() + key.len());
+        prefixed.extend_from_slice(prefix);
+        prefixed.extend_from_slice(key);
+        Cow::Owned(prefixed)
+    }
+
+    /// KeyVal with the key_prefix of the config prepended to the key
+    #[inline(always)]
+    fn prefix_key_val<'a>(&self, kv: &'a KeyVal) -> Cow<'a, KeyVal> {
+        if self.config.key_prefix.is_empty() {
+            return Cow::Borrowed(kv);
+        }
+        let mut prefixed = KeyVal::new(&self.prefix_key(&kv.key), &kv.val);
+        prefixed.db_name = kv.db_name.clone();
+        prefixed.skip_db = kv.skip_db;
+        prefixed.skip_cache = kv.skip_cache;
+        prefixed.ttl_secs = kv.ttl_secs;
+        Cow::Owned(prefixed)
+    }
+
+    /// stored key without the key_prefix of the config
+    #[inline(always)]
+    fn strip_key_prefix(&self, mut key: Vec<u8>) -> Vec<u8> {
+        let len = self.config.key_prefix.len();
+        if len > 0 && key.starts_with(self.config.key_prefix.as_bytes()) {
+            key.drain(..len);
+        }
+        key
+    }
+
+    /// lock the shard for a given key.
+    /// used by read-modify-write operations which need atomicity
+    #[inline]
+    fn lock_shard(&self, kv: &KeyVal) -> MutexGuard<()> {
+        let shard = kv.slot(self.shard_locks.len()) as usize;
+        self.shard_locks[shard].lock()
+    }
+
+    /// update the cache with a value read from db in the swap epoch.
+    /// with read_through_batch_size, updates are buffered and written as a batch.
+    /// the value is dropped if the db was swapped since it was read
+    #[inline]
+    fn cache_read_through(&self, kv: KeyVal, epoch: u64) {
+        let batch_size = self.config.cache_config.read_through_batch_size;
+        //held until the cache is updated, so swap_db clears the cache after the update
+        let current_epoch = self.swap_epoch.read();
+        if *current_epoch != epoch {
+            debug!("db swapped since the read, not caching the value");
+            return;
+        }
+        if batch_size == 0 {
+            let _ = self.cache.put_key_val(&kv, &kv.val);
+            return;
+        }
+        let pending = {
+            let mut buffer = self.read_through_buffer.lock();
+            buffer.push(kv);
+            if buffer.len() < batch_size {
+                return;
+            }
+            std::mem::replace(&mut *buffer, Vec::with_capacity(batch_size))
+        };
+        let _ = self.cache.batch_put(&pending);
+    }
+
+    /// flush the buffered read-through cache updates
+    pub fn flush_read_through(&self) -> Result<(), String> {
+        let _epoch = self.swap_epoch.read();
+        let pending = std::mem::replace(&mut *self.read_through_buffer.lock(), Vec::new());
+        self.cache.batch_put(&pending)
+    }
+
+    /// encode and write the key val pair synchronously to db and keep the cache in sync
+    #[inline]
+    fn put_sync(&self, key: &[u8], val: &[u8]) -> Result<(), String> {
+        let val: &[u8] = &self.encode_value(val)?;
+        if self.db.is_some() {
+            self.db.as_ref().unwrap().put_sync(&key, &val)?;
+        }
+        if self.cache_on_write(&key) {
+            self.cache.put(&key, &val)
+        } else {
+            self.cache.delete(&key)
+        }
+    }
+
+    /// true if a write of the key updates the cache
+    #[inline]
+    fn cache_on_write(&self, key: &[u8]) -> bool {
+        let cache_config = &self.config.cache_config;
+        cache_config.cache_update_on_db_write
+            && (!cache_config.cache_update_on_write_only_if_present || self.cache.contains(key))
+    }
+
+    /// record the hit or miss the cache would have had in shadow_mode.
+    /// the value is read from the db either way
+    #[inline(always)]
+    fn record_shadow_lookup(&self, hit: bool) {
+        if hit {
+            self.counters.cache_hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.counters.cache_misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
     /// get key as str
     #[inline]
     pub fn get(&self, key: &[u8]) -> Result<Option<(Vec<u8>, bool)>, String> {
+        let stored = self.get_raw(key)?;
+        self.decode_value(stored)
+    }
+
+    /// get the value as stored
+    fn get_raw(&self, key: &[u8]) -> Result<Option<(Vec<u8>, bool)>, String> {
         debug!("db_manager:get()");
-        if let Some(val) = self.cache.get(&key) {
+        let key = self.prefix_key(key);
+        let key: &[u8] = &key;
+        if self.cache.shadow_mode() {
+            self.record_shadow_lookup(self.cache.touch(&key));
+        } else if let Some(val) = self.cache.get(&key) {
             debug!("db_manager:get value received from cache");
+            self.counters.cache_hits.fetch_add(1, Ordering::Relaxed);
             return Ok(Some((val, true)));
+        } else {
+            debug!("db_manager:get_key_val not found in cache");
+            self.counters.cache_misses.fetch_add(1, Ordering::Relaxed);
         }
-        debug!("db_manager:get_key_val not found in cache");
         if self.db.is_none() {
             return Ok(None);
         }
+        self.counters.disk_reads.fetch_add(1, Ordering::Relaxed);
+        let epoch = *self.swap_epoch.read();
         match self.db.as_ref().unwrap().get(key) {
             Ok(Some(value)) => {
                 debug!("db_manager:get value received from db");
                 if self.config.cache_config.cache_update_on_db_read {
                     debug!("db_manager:get value received from db and updating cache");
-                    let _ = self.cache.put(&key, &value);
+                    self.cache_read_through(KeyVal::new(&key, &value), epoch);
                 }
                 Ok(Some((value, false)))
             }
@@ -94,25 +450,44 @@ impl DbManager {
         }
     }
 
+    /// true if the key is cached. never reads the db
+    #[inline]
+    pub fn in_cache(&self, kv: &KeyVal) -> bool {
+        self.cache.contains_key_val(&self.prefix_key_val(kv))
+    }
+
     /// get key as str
     #[inline]
     pub fn get_key_val(&self, kv: &KeyVal) -> Result<Option<(Vec<u8>, bool)>, String> {
+        let stored = self.get_stored(&self.prefix_key_val(kv))?;
+        self.decode_value(stored)
+    }
+
+    /// get the value of a KeyVal with the key as stored, i.e. prefixed
+    fn get_stored(&self, kv: &KeyVal) -> Result<Option<(Vec<u8>, bool)>, String> {
         debug!("db_manager:get_key_val()");
-        if let Some(val) = self.cache.get_key_val(&kv) {
+        if self.cache.shadow_mode() {
+            self.record_shadow_lookup(self.cache.touch_key_val(&kv));
+        } else if let Some(val) = self.cache.get_key_val(&kv) {
             debug!("db_manager:get_key_val value received from cache");
+            self.counters.cache_hits.fetch_add(1, Ordering::Relaxed);
             return Ok(Some((val, true)));
+        } else {
+            debug!("db_manager:get_key_val not found in cache");
+            self.counters.cache_misses.fetch_add(1, Ordering::Relaxed);
         }
-
-        debug!("db_manager:get_key_val not found in cache");
         if self.db.is_none() {
             return Ok(None);
         }
+        self.counters.disk_reads.fetch_add(1, Ordering::Relaxed);
+        let epoch = *self.swap_epoch.read();
         match self.db.as_ref().unwrap().get(&kv.key) {
             Ok(Some(value)) => {
                 debug!("db_manager:get_key_val value received from db");
                 if self.config.cache_config.cache_update_on_db_read {
                     debug!("db_manager:get_key_val value received from db and updating cache");
-                    let _ = self.cache.put_key_val(&kv, &value);
+                    let kv = KeyVal::new_with_hash(kv.hash, &kv.key, &value);
+                    self.cache_read_through(kv, epoch);
                 }
                 Ok(Some((value, false)))
             }
@@ -127,57 +502,629 @@ impl DbManager {
         }
     }
 
+    /// get the value from db skipping the cache lookup.
+    /// cache is refreshed with the db value if cache_update_on_db_read is enabled
+    #[inline]
+    pub fn get_consistent(&self, kv: &KeyVal) -> Result<Option<(Vec<u8>, bool)>, String> {
+        let stored = self.get_consistent_stored(kv)?;
+        self.decode_value(stored)
+    }
+
+    fn get_consistent_stored(&self, kv: &KeyVal) -> Result<Option<(Vec<u8>, bool)>, String> {
+        debug!("db_manager:get_consistent()");
+        let kv = self.prefix_key_val(kv);
+        if self.db.is_none() {
+            return Ok(None);
+        }
+        self.counters.disk_reads.fetch_add(1, Ordering::Relaxed);
+        let epoch = *self.swap_epoch.read();
+        match self.db.as_ref().unwrap().get(&kv.key) {
+            Ok(Some(value)) => {
+                debug!("db_manager:get_consistent value received from db");
+                if self.config.cache_config.cache_update_on_db_read {
+                    debug!("db_manager:get_consistent value received from db and updating cache");
+                    let current_epoch = self.swap_epoch.read();
+                    if *current_epoch == epoch {
+                        let _ = self.cache.put_key_val(&kv, &value);
+                    }
+                }
+                Ok(Some((value, false)))
+            }
+            Ok(None) => {
+                debug!("db_manager:get_consistent value not found from db");
+                Ok(None)
+            }
+            Err(e) => {
+                debug!("db_manager:get_consistent from db error: {:?}", e);
+                Err(e.to_string())
+            }
+        }
+    }
+
     /// put the key val pair into database
     #[inline]
     pub fn put(&self, key: &[u8], val: &[u8]) -> Result<(), String> {
         debug!("db_manager:put");
+        self.validate_key(key)?;
+        self.config.rocks_db_config.validate_size(&key, &val)?;
+        let key = self.prefix_key(key);
+        let val = self.encode_value(val)?;
         if self.db.is_some() {
             self.db.as_ref().unwrap().put(&key, &val)?;
         }
         debug!("db_manager:put success");
 
-        if self.config.cache_config.cache_update_on_db_write {
+        if self.cache_on_write(&key) {
             debug!("db_manager:put success. updating cache");
             self.cache.put(&key, &val)?;
         }
         Ok(())
     }
 
+    /// cached and db values of the key, to audit the cache consistency.
+    /// the cache is peeked, so neither the cache nor the db is modified
+    pub fn get_audit(&self, kv: &KeyVal) -> Result<(Option<Vec<u8>>, Option<Vec<u8>>), String> {
+        debug!("db_manager:get_audit");
+        let kv = self.prefix_key_val(kv);
+        let cached = self.decode_value(self.cache.peek(&kv.key).map(|val| (val, true)))?;
+        let stored = match self.db.as_ref() {
+            Some(db) => self.decode_value(db.get(&kv.key)?.map(|val| (val, false)))?,
+            None => None,
+        };
+        Ok((cached.map(|(val, _)| val), stored.map(|(val, _)| val)))
+    }
+
+    /// put the value built by the closure into database.
+    /// the closure writes the value into an empty buffer reused by the calls on a thread
+    pub fn put_with<F>(&self, key: &[u8], f: F) -> Result<(), String>
+    where
+        F: FnOnce(&mut Vec<u8>),
+    {
+        debug!("db_manager:put_with");
+        //take the buffer out, so a put_with called from the closure gets a new one
+        let mut buf = PUT_BUFFER.with(|buf| std::mem::replace(&mut *buf.borrow_mut(), Vec::new()));
+        buf.clear();
+        f(&mut buf);
+        let res = self.put(key, &buf);
+        PUT_BUFFER.with(|cell| *cell.borrow_mut() = buf);
+        res
+    }
+
     /// put the key val pair into database
     #[inline]
     pub fn put_key_val(&self, kv: &KeyVal) -> Result<(), String> {
+        self.put_key_val_with_outcome(&kv).map(|_| ())
+    }
+
+    /// put the key val pair into database and return whether it was persisted and cached.
+    /// with the db disabled, the value is only cached
+    pub fn put_key_val_with_outcome(&self, kv: &KeyVal) -> Result<PutOutcome, String> {
         debug!("db_manager:put_key_val");
-        if self.db.is_some() {
-            self.db.as_ref().unwrap().put(&kv.key, &kv.val)?;
+        self.validate_key(&kv.key)?;
+        self.config.rocks_db_config.validate_size(&kv.key, &kv.val)?;
+        let kv = self.prefix_key_val(kv);
+        let val = self.encode_value(&kv.val)?;
+        let mut outcome = PutOutcome::default();
+        if let Some(db) = self.db.as_ref() {
+            db.put(&kv.key, &val)?;
+            outcome.persisted = db.enabled;
         }
         debug!("db_manager:put_key_val success");
-        if self.config.cache_config.cache_update_on_db_write {
+        if self.cache_on_write(&kv.key) {
             debug!("db_manager:put_key_val success. updating cache");
-            self.cache.put(&kv.key, &kv.val)?;
+            outcome.cached = self.cache.put_with_ttl(&kv.key, &val, kv.ttl_secs)?;
+        }
+        Ok(outcome)
+    }
+
+    /// put the key val pair into the cache only. It is never written to db
+    #[inline]
+    pub fn put_cache_only(&self, kv: &KeyVal) -> Result<(), String> {
+        debug!("db_manager:put_cache_only");
+        self.validate_key(&kv.key)?;
+        self.config.rocks_db_config.validate_size(&kv.key, &kv.val)?;
+        let kv = self.prefix_key_val(kv);
+        let val = self.encode_value(&kv.val)?;
+        self.cache.put_key_val(&kv, &val)
+    }
+
+    /// remove all the cached entries with the key starting with prefix. db is not modified
+    pub fn invalidate_cache_prefix(&self, prefix: &[u8]) -> u64 {
+        debug!("db_manager:invalidate_cache_prefix");
+        self.cache.invalidate_prefix(&self.prefix_key(prefix))
+    }
+
+    /// pin the key val pair in the cache so it is always served from memory. db is not modified
+    pub fn pin_key(&self, key: &[u8], val: &[u8]) -> Result<(), String> {
+        debug!("db_manager:pin_key");
+        self.validate_key(key)?;
+        let val = self.encode_value(val)?;
+        self.cache.pin_key(&self.prefix_key(key), &val)
+    }
+
+    /// unpin the key, making it subject to eviction again. returns false if the key is not pinned
+    pub fn unpin_key(&self, key: &[u8]) -> bool {
+        debug!("db_manager:unpin_key");
+        self.cache.unpin_key(&self.prefix_key(key))
+    }
+
+    /// load up to limit key-val pairs of the db with the key starting with prefix into the cache.
+    /// returns number of entries loaded
+    pub fn warm_prefix(&self, prefix: &[u8], limit: usize) -> Result<u64, String> {
+        debug!("db_manager:warm_prefix");
+        let db = match self.db.as_ref() {
+            Some(db) => db,
+            None => return Ok(0),
+        };
+        let mut total = 0u64;
+        if limit == 0 {
+            return Ok(total);
+        }
+        let mut result = Ok(());
+        db.scan_prefix_with(&self.prefix_key(prefix), false, |key, val| {
+            if let Err(e) = self.cache.put(key, val) {
+                result = Err(e);
+                return false;
+            }
+            total += 1;
+            total < limit as u64
+        })?;
+        result.map(|_| total)
+    }
+
+    /// copy all the cache entries into the cache of another DbManager.
+    /// keys are copied with the key_prefix of this DbManager. returns number of entries copied
+    pub fn transfer_cache(&self, target: &DbManager) -> u64 {
+        debug!("db_manager:transfer_cache");
+        self.cache.drain_into(&target.cache)
+    }
+
+    /// number of entries of each cache shard
+    pub fn cache_shard_lengths(&self) -> Vec<usize> {
+        self.cache.shard_lengths()
+    }
+
+    /// change the total capacity of the cache
+    pub fn resize_cache(&self, new_total_capacity: usize) -> Result<(), String> {
+        self.cache.resize(new_total_capacity)
+    }
+
+    /// put all the key val pairs as a single db batch write
+    pub fn batch_put(&self, data: &[KeyVal]) -> Result<(), String> {
+        debug!("db_manager:batch_put");
+        for kv in data.iter() {
+            self.validate_key(&kv.key)?;
+            self.config.rocks_db_config.validate_size(&kv.key, &kv.val)?;
+        }
+        let data = self.prefix_key_vals(data);
+        let data = self.encode_key_vals(&data)?;
+        if self.db.is_some() {
+            self.db.as_ref().unwrap().batch_put(&data)?;
+        }
+        if self.config.cache_config.cache_update_on_write_only_if_present {
+            let cached: Vec<KeyVal> =
+                data.iter().filter(|kv| self.cache_on_write(&kv.key)).cloned().collect();
+            self.cache.batch_put(&cached)?;
+        } else if self.config.cache_config.cache_update_on_db_write {
+            debug!("db_manager:batch_put success. updating cache");
+            self.cache.batch_put(&data)?;
         }
         Ok(())
     }
 
+    /// put the key val pair only if the value differs from the stored value.
+    /// returns true if the value was written
+    pub fn put_if_changed(&self, kv: &KeyVal) -> Result<bool, String> {
+        debug!("db_manager:put_if_changed");
+        self.validate_key(&kv.key)?;
+        self.config.rocks_db_config.validate_size(&kv.key, &kv.val)?;
+        let kv = self.prefix_key_val(kv);
+        let _guard = self.lock_shard(&kv);
+        if let Some((val, _)) = self.decode_value(self.get_stored(&kv)?)? {
+            if val == kv.val {
+                debug!("db_manager:put_if_changed value unchanged. skipping write");
+                return Ok(false);
+            }
+        }
+        self.put_sync(&kv.key, &kv.val)?;
+        Ok(true)
+    }
+
+    /// put the key val pair only if the key does not exist in the cache or the db.
+    /// It is a synchronous write even when async_write is enabled, so a racing
+    /// put_if_absent never misses a write still in the async write queue.
+    /// returns true if the value was written
+    pub fn put_if_absent(&self, kv: &KeyVal) -> Result<bool, String> {
+        debug!("db_manager:put_if_absent");
+        self.validate_key(&kv.key)?;
+        self.config.rocks_db_config.validate_size(&kv.key, &kv.val)?;
+        let kv = self.prefix_key_val(kv);
+        let _guard = self.lock_shard(&kv);
+        if self.get_stored(&kv)?.is_some() {
+            debug!("db_manager:put_if_absent key exists. skipping write");
+            return Ok(false);
+        }
+        self.put_sync(&kv.key, &kv.val)?;
+        Ok(true)
+    }
+
+    /// put the key val pair and return the previous value.
+    /// It is a synchronous write even when async_write is enabled
+    pub fn put_and_get_prev(&self, kv: &KeyVal) -> Result<Option<Vec<u8>>, String> {
+        debug!("db_manager:put_and_get_prev");
+        self.validate_key(&kv.key)?;
+        self.config.rocks_db_config.validate_size(&kv.key, &kv.val)?;
+        let kv = self.prefix_key_val(kv);
+        let _guard = self.lock_shard(&kv);
+        let prev = self.decode_value(self.get_stored(&kv)?)?.map(|(val, _)| val);
+        self.put_sync(&kv.key, &kv.val)?;
+        Ok(prev)
+    }
+
+    /// delete the key and return the value it had.
+    /// the key is deleted from RocksDB right away, even with async_write
+    pub fn delete_and_get_prev(&self, kv: &KeyVal) -> Result<Option<Vec<u8>>, String> {
+        debug!("db_manager:delete_and_get_prev");
+        let kv = self.prefix_key_val(kv);
+        let _guard = self.lock_shard(&kv);
+        let prev = self.decode_value(self.get_stored(&kv)?)?.map(|(val, _)| val);
+        self.delete_stored(&kv.key)?;
+        Ok(prev)
+    }
+
+    /// append the value to the existing value of the key using the "append" merge_operator.
+    /// the cached value of the key is invalidated
+    pub fn append(&self, kv: &KeyVal) -> Result<(), String> {
+        debug!("db_manager:append");
+        self.validate_key(&kv.key)?;
+        self.config.rocks_db_config.validate_size(&kv.key, &kv.val)?;
+        if self.config.rocks_db_config.merge_operator != "append" {
+            return Err("append requires merge_operator: append".to_string());
+        }
+        //concatenated encoded values can't be decoded
+        if self.codec.is_some() {
+            return Err("append not supported with a value codec".to_string());
+        }
+        let kv = self.prefix_key_val(kv);
+        match self.db.as_ref() {
+            Some(db) => db.merge(&kv.key, &kv.val)?,
+            None => return Err("db not enabled".to_string()),
+        }
+        self.cache.delete(&kv.key)
+    }
+
+    /// put the value of a field for a given key.
+    /// field selects the column family configured in rocks_db_config.column_families.
+    /// Fields are not cached
+    #[inline]
+    pub fn put_field(&self, key: &[u8], field: &str, val: &[u8]) -> Result<(), String> {
+        debug!("db_manager:put_field");
+        self.validate_key(key)?;
+        match self.db.as_ref() {
+            Some(db) => db.put_cf(field, &self.prefix_key(key), &self.encode_value(val)?),
+            None => Err("db not enabled".to_string()),
+        }
+    }
+
+    /// get the value of a field for a given key
+    #[inline]
+    pub fn get_field(&self, key: &[u8], field: &str) -> Result<Option<Vec<u8>>, String> {
+        debug!("db_manager:get_field");
+        let stored = match self.db.as_ref() {
+            Some(db) => db.get_cf(field, &self.prefix_key(key))?,
+            None => return Ok(None),
+        };
+        match (self.codec.as_ref(), stored) {
+            (Some(codec), Some(val)) => Ok(Some(codec.decode(&val)?)),
+            (_, stored) => Ok(stored),
+        }
+    }
+
     /// delete they key in the db if found
     #[inline]
     pub fn delete(&self, key: &[u8]) -> Result<(), String> {
-        let _ = self.cache.delete(&key);
+        self.delete_stored(&self.prefix_key(key))
+    }
+
+    /// delete they key in the db if found
+    #[inline]
+    pub fn delete_key_val(&self, kv: &KeyVal) -> Result<(), String> {
+        self.delete_stored(&self.prefix_key(&kv.key))
+    }
+
+    /// delete the key as stored, i.e. prefixed
+    #[inline]
+    fn delete_stored(&self, key: &[u8]) -> Result<(), String> {
+        let cache_res = self.cache.delete(&key);
         if self.db.is_some() {
             return self.db.as_ref().unwrap().delete(key);
         }
+        //without db, cache is the only state so report its result
+        cache_res
+    }
+
+    /// start a multi-key transaction. It bypasses the async write queue
+    pub fn transaction(&self) -> Txn {
+        Txn::new(
+            self.db.clone(),
+            self.cache.clone(),
+            self.config.key_prefix.as_bytes().to_vec(),
+            self.codec.clone(),
+            self.key_validator.clone(),
+        )
+    }
+
+    /// delete all the keys from the cache and as a single db batch write
+    pub fn delete_many(&self, data: &[KeyVal]) -> Result<(), String> {
+        let data = self.prefix_key_vals(data);
+        for kv in data.iter() {
+            let _ = self.cache.delete(&kv.key);
+        }
+        if self.db.is_some() {
+            return self.db.as_ref().unwrap().batch_delete(&data);
+        }
         Ok(())
     }
 
-    /// delete they key in the db if found
-    #[inline]
-    pub fn delete_key_val(&self, kv: &KeyVal) -> Result<(), String> {
-        let _ = self.cache.delete(&kv.key);
+    /// KeyVals with the key_prefix of the config prepended to the keys
+    fn prefix_key_vals<'a>(&self, data: &'a [KeyVal]) -> Cow<'a, [KeyVal]> {
+        if self.config.key_prefix.is_empty() {
+            return Cow::Borrowed(data);
+        }
+        Cow::Owned(data.iter().map(|kv| self.prefix_key_val(kv).into_owned()).collect())
+    }
+
+    /// get all the key-val pairs from the db where key starts with a given prefix
+    /// It bypasses the cache. consistent scan iterates over a point-in-time snapshot
+    pub fn scan_prefix(
+        &self,
+        prefix: &[u8],
+        consistent: bool,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, String> {
         if self.db.is_some() {
-            return self.db.as_ref().unwrap().delete(&kv.key);
+            let data = self
+                .db
+                .as_ref()
+                .unwrap()
+                .scan_prefix(&self.prefix_key(prefix), consistent)?;
+            let data = self.decode_pairs(data)?;
+            if self.config.key_prefix.is_empty() {
+                return Ok(data);
+            }
+            return Ok(data
+                .into_iter()
+                .map(|(key, val)| (self.strip_key_prefix(key), val))
+                .collect());
+        }
+        Ok(vec![])
+    }
+
+    /// number of keys in the db starting with a given prefix. It bypasses the cache
+    pub fn count_prefix(&self, prefix: &[u8]) -> Result<u64, String> {
+        match self.db.as_ref() {
+            Some(db) => db.count_prefix(&self.prefix_key(prefix)),
+            None => Ok(0),
         }
+    }
+
+    /// get up to limit key-val pairs from the db with start <= key < end. It bypasses the cache.
+    /// None start/end scans from the first key/to the last key.
+    /// reverse returns the pairs in descending key order starting from end.
+    /// with key_prefix, the range is limited to the keys with the prefix
+    pub fn scan_range(
+        &self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        limit: usize,
+        reverse: bool,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, String> {
+        let db = match self.db.as_ref() {
+            Some(db) => db,
+            None => return Ok(vec![]),
+        };
+        if self.config.key_prefix.is_empty() {
+            return self.decode_pairs(db.scan_range(start, end, limit, reverse)?);
+        }
+        let start = self.prefix_key(start.unwrap_or(b""));
+        let end = match end {
+            Some(end) => Some(self.prefix_key(end).into_owned()),
+            None => prefix_upper_bound(self.config.key_prefix.as_bytes()),
+        };
+        let end = end.as_ref().map(|e| e.as_slice());
+        let data = self.decode_pairs(db.scan_range(Some(&start), end, limit, reverse)?)?;
+        Ok(data
+            .into_iter()
+            .map(|(key, val)| (self.strip_key_prefix(key), val))
+            .collect())
+    }
+
+    /// iterate over all the key-val pairs in the db. It bypasses the cache.
+    /// with key_prefix, only the keys with the prefix are returned, without the prefix.
+    /// a value failing to decode is returned as an error
+    pub fn iter_all(&self) -> Result<KeyValIter<'_>, String> {
+        let iter = self.iter_all_stored()?;
+        match self.codec.as_ref() {
+            Some(codec) => Ok(Box::new(iter.map(move |item| {
+                let (key, val) = item?;
+                Ok((key, codec.decode(&val)?))
+            }))),
+            None => Ok(iter),
+        }
+    }
+
+    /// iterate over all the key-val pairs in the db with the values as stored
+    fn iter_all_stored(&self) -> Result<KeyValIter<'_>, String> {
+        let db = match self.db.as_ref() {
+            Some(db) => db,
+            None => return Ok(Box::new(std::iter::empty())),
+        };
+        let iter = match db.iter_all() {
+            Ok(iter) => iter,
+            Err(e) => {
+                error!("Failed to iterate over db: {}. Error:{}", self.name, e);
+                return Err(e);
+            }
+        };
+        if self.config.key_prefix.is_empty() {
+            return Ok(Box::new(iter));
+        }
+        let prefix = self.config.key_prefix.as_bytes();
+        Ok(Box::new(
+            iter.filter(move |item| match item {
+                Ok((key, _)) => key.starts_with(prefix),
+                Err(_) => true,
+            })
+            .map(move |item| item.map(|(key, val)| (self.strip_key_prefix(key), val))),
+        ))
+    }
+
+    /// number of pending writes in the async write queue
+    pub fn write_queue_len(&self) -> usize {
+        match self.db.as_ref() {
+            Some(db) => db.write_queue_len(),
+            None => 0,
+        }
+    }
+
+    /// capacity of the async write queue. None if the queue is unbounded or db is not enabled
+    pub fn write_queue_capacity(&self) -> Option<usize> {
+        match self.db.as_ref() {
+            Some(db) => db.write_queue_capacity(),
+            None => None,
+        }
+    }
+
+    /// estimated number of keys in the db. 0 if db is not enabled
+    pub fn estimated_num_keys(&self) -> u64 {
+        match self.db.as_ref() {
+            Some(db) => db.estimated_num_keys(),
+            None => 0,
+        }
+    }
+
+    /// snapshot of the cache and db stats
+    pub fn metrics(&self) -> DbMetrics {
+        DbMetrics {
+            name: self.name.clone(),
+            cache_len: self.cache.total_len(),
+            cache_hits: self.counters.cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.counters.cache_misses.load(Ordering::Relaxed),
+            disk_reads: self.counters.disk_reads.load(Ordering::Relaxed),
+            write_queue_len: self.write_queue_len(),
+            estimate_num_keys: self.estimated_num_keys(),
+        }
+    }
+
+    /// approximate memory used by the cache, block cache, memtables and async write queue
+    pub fn memory_breakdown(&self) -> MemoryBreakdown {
+        let mut breakdown = MemoryBreakdown {
+            name: self.name.clone(),
+            cache_bytes: self.cache.estimated_bytes() as u64,
+            ..MemoryBreakdown::default()
+        };
+        if let Some(db) = self.db.as_ref() {
+            breakdown.block_cache_bytes = db.block_cache_usage();
+            breakdown.memtable_bytes = db.memtable_size();
+            breakdown.write_queue_bytes = db.write_queue_bytes();
+        }
+        breakdown
+    }
+
+    /// underlying rocksdb handle for operations not wrapped by the crate.
+    /// writes through it bypass the cache and the key_prefix
+    #[cfg(feature = "raw-access")]
+    pub fn raw_db(&self) -> Option<Arc<rocksdb::DB>> {
+        self.db.as_ref().and_then(|db| db.raw_db())
+    }
+
+    /// close and reopen the db in place, keeping the cache
+    pub fn reopen(&self) -> Result<(), String> {
+        match self.db.as_ref() {
+            Some(db) => db.reopen(),
+            None => Ok(()),
+        }
+    }
+
+    /// swap the db with the db at new_db_path and clear the cache, so no value
+    /// of the old db is served
+    pub fn swap_db(&self, new_db_path: &str) -> Result<(), String> {
+        let db = match self.db.as_ref() {
+            Some(db) => db,
+            None => return Err("db not enabled".to_string()),
+        };
+        //buffered read-through updates hold values of the old db, discard them
+        let mut buffer = self.read_through_buffer.lock();
+        db.swap_db(new_db_path)?;
+        buffer.clear();
+        self.cache.clear();
         Ok(())
     }
 
+    /// check the db is responsive. a degraded db is reported as unhealthy
+    pub fn health_check(&self) -> Result<(), String> {
+        if self.degraded {
+            return Err("db failed to open, serving from cache only".to_string());
+        }
+        match self.db.as_ref() {
+            Some(db) => db.health_check(),
+            None => Ok(()),
+        }
+    }
+
+    /// write and sync the db WAL to disk
+    pub fn sync_wal(&self) -> Result<(), String> {
+        match self.db.as_ref() {
+            Some(db) => db.sync_wal(),
+            None => Ok(()),
+        }
+    }
+
+    /// flush the db memtables to disk
+    pub fn flush(&self) -> Result<(), String> {
+        match self.db.as_ref() {
+            Some(db) => db.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// drop the keys starting with any of the prefixes at compaction time, replacing the
+    /// previous prefixes. the cached keys are invalidated, but the db still returns
+    /// the keys until the compactions remove them
+    pub fn set_drop_prefixes(&self, prefixes: Vec<Vec<u8>>) -> Result<(), String> {
+        let db = match self.db.as_ref() {
+            Some(db) => db,
+            None => return Err("db not enabled".to_string()),
+        };
+        let prefixes: Vec<Vec<u8>> = prefixes.iter().map(|p| self.prefix_key(p)).collect();
+        for prefix in prefixes.iter() {
+            self.cache.invalidate_prefix(prefix);
+        }
+        db.set_drop_prefixes(prefixes);
+        Ok(())
+    }
+
+    /// compact the whole db. the keys of the drop prefixes cached by reads
+    /// while they were still in the db are invalidated
+    pub fn compact(&self) -> Result<(), String> {
+        let db = match self.db.as_ref() {
+            Some(db) => db,
+            None => return Ok(()),
+        };
+        db.compact()?;
+        for prefix in db.drop_prefixes().iter() {
+            self.cache.invalidate_prefix(prefix);
+        }
+        Ok(())
+    }
+
+    /// number of files and size per LSM level of the db
+    pub fn levels_stats(&self) -> Vec<LevelStat> {
+        match self.db.as_ref() {
+            Some(db) => db.levels_stats(),
+            None => vec![],
+        }
+    }
+
     pub fn backup_db(&self) -> Result<(), String> {
         if self.db.is_some() {
             return self.db.as_ref().unwrap().backup_db();
@@ -185,7 +1132,711 @@ impl DbManager {
         Ok(())
     }
 
+    /// backups of the db, oldest first
+    pub fn list_backups(&self) -> Result<Vec<BackupInfo>, String> {
+        match self.db.as_ref() {
+            Some(db) => db.list_backups(),
+            None => Err("db not enabled".to_string()),
+        }
+    }
+
     pub fn export_lru_keys(&self) -> Result<u64, String> {
         self.cache.export_keys()
     }
+
+    /// load the values of the keys exported by export_lru_keys from the db into the cache.
+    /// returns number of keys loaded
+    pub fn import_lru_keys(&self) -> Result<u64, String> {
+        let db = match self.db.as_ref() {
+            Some(db) => db,
+            None => return Ok(0),
+        };
+        let mut total = 0u64;
+        for key in self.cache.import_keys()? {
+            if let Some(val) = db.get(&key)? {
+                self.cache.put(&key, &val)?;
+                total += 1;
+            }
+        }
+        info!("Imported {} keys into the cache of db: {}", total, self.name);
+        Ok(total)
+    }
+
+    /// increment a counter for the window of window_ms.
+    /// counter resets once the window expires.
+    /// returns the current count and whether it exceeded the limit
+    pub fn sliding_window_incr(
+        &self,
+        key: &[u8],
+        window_ms: u64,
+        limit: u64,
+    ) -> Result<(u64, bool), String> {
+        self.validate_key(key)?;
+        let kv = KeyVal::new_with_key(&self.prefix_key(key));
+        let _guard = self.lock_shard(&kv);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| e.to_string())?
+            .as_millis() as u64;
+        let (mut window_start, mut count) = (now, 0u64);
+        if let Some((val, _)) = self.decode_value(self.get_stored(&kv)?)? {
+            if val.len() == 16 {
+                let mut buf = [0u8; 8];
+                buf.copy_from_slice(&val[0..8]);
+                let start = u64::from_be_bytes(buf);
+                buf.copy_from_slice(&val[8..16]);
+                if now.saturating_sub(start) < window_ms {
+                    window_start = start;
+                    count = u64::from_be_bytes(buf);
+                }
+            }
+        }
+        count += 1;
+        let mut val = Vec::with_capacity(16);
+        val.extend_from_slice(&window_start.to_be_bytes());
+        val.extend_from_slice(&count.to_be_bytes());
+        self.put_sync(&kv.key, &val)?;
+        Ok((count, count > limit))
+    }
+}
+
+/// error if the key doesn't match the key validation regex
+#[inline]
+pub(crate) fn validate_key(key_validator: Option<&Regex>, key: &[u8]) -> Result<(), String> {
+    match key_validator {
+        Some(re) if !re.is_match(key) => Err("key failed validation".to_string()),
+        _ => Ok(()),
+    }
+}
+
+/// smallest key greater than all the keys starting with a given prefix.
+/// None when there is no such key, i.e. the prefix is all 0xff
+fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut bound = prefix.to_vec();
+    while let Some(last) = bound.pop() {
+        if last < 0xff {
+            bound.push(last + 1);
+            return Some(bound);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::test_util::db_manager_test_config as test_config;
+
+    #[test]
+    fn test_config_missing_fields_take_defaults() {
+        let json = r#"{
+            "name": "legacy",
+            "rocks_db_config": {"enabled": true, "async_write": false, "db_path": "/tmp/legacy"},
+            "cache_config": {"enabled": true, "cache_capacity": 100, "num_shards": 4}
+        }"#;
+        let config: DbManagerConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(config.name, "legacy");
+        assert_eq!(config.rocks_db_config.db_path, "/tmp/legacy");
+        assert_eq!(config.rocks_db_config.compaction_style, "level");
+        assert_eq!(config.rocks_db_config.scan_readahead_size_mb, 0);
+        assert_eq!(config.cache_config.cache_capacity, 100);
+        assert_eq!(config.cache_config.hash_seed, 0);
+        assert_eq!(config.key_prefix, "");
+    }
+
+    #[test]
+    fn test_sliding_window_incr() {
+        let config = test_config("sliding_window_incr");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::new(&config, shutdown).unwrap();
+        let limit = 5;
+        for i in 1..=limit {
+            let (count, exceeded) = db.sliding_window_incr(b"rate_key", 60_000, limit).unwrap();
+            assert_eq!(count, i);
+            assert!(!exceeded);
+        }
+        let (count, exceeded) = db.sliding_window_incr(b"rate_key", 60_000, limit).unwrap();
+        assert_eq!(count, limit + 1);
+        assert!(exceeded);
+    }
+
+    #[test]
+    fn test_get_consistent_skips_stale_cache() {
+        let config = test_config("get_consistent");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::new(&config, shutdown).unwrap();
+        let kv = KeyVal::new(b"consistent_key", b"fresh");
+        db.put_key_val(&kv).unwrap();
+        db.cache.put(&kv.key, b"stale").unwrap();
+
+        let (val, from_cache) = db.get_key_val(&kv).unwrap().unwrap();
+        assert_eq!(val, b"stale".to_vec());
+        assert!(from_cache);
+
+        let (val, from_cache) = db.get_consistent(&kv).unwrap().unwrap();
+        assert_eq!(val, b"fresh".to_vec());
+        assert!(!from_cache);
+
+        let (val, from_cache) = db.get_key_val(&kv).unwrap().unwrap();
+        assert_eq!(val, b"fresh".to_vec());
+        assert!(from_cache);
+    }
+
+    #[test]
+    fn test_put_and_get_field() {
+        let mut config = test_config("put_get_field");
+        config.rocks_db_config.column_families = vec!["name".to_string(), "email".to_string()];
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::new(&config, shutdown).unwrap();
+        db.put_field(b"user_1", "name", b"rohit").unwrap();
+        db.put_field(b"user_1", "email", b"rohit@example.com").unwrap();
+
+        assert_eq!(db.get_field(b"user_1", "name").unwrap(), Some(b"rohit".to_vec()));
+        assert_eq!(
+            db.get_field(b"user_1", "email").unwrap(),
+            Some(b"rohit@example.com".to_vec())
+        );
+        assert_eq!(db.get_field(b"user_2", "name").unwrap(), None);
+        assert!(db.get_field(b"user_1", "phone").is_err());
+    }
+
+    #[test]
+    fn test_warm_cache_after_restore() {
+        let config = test_config("warm_cache_source");
+        let _ = fs::remove_dir_all(&config.rocks_db_config.backup_path);
+        let shutdown = Arc::new(AtomicBool::new(false));
+        {
+            let db = DbManager::new(&config, shutdown.clone()).unwrap();
+            for i in 0..100 {
+                let key = format!("warm_key_{}", i);
+                db.put(key.as_bytes(), b"val").unwrap();
+            }
+            db.backup_db().unwrap();
+        }
+
+        let mut restored = test_config("warm_cache_restored");
+        restored.rocks_db_config.backup_path = config.rocks_db_config.backup_path.clone();
+        restored.rocks_db_config.restore_from_backup_at_startup = true;
+        restored.cache_config.warm_cache_keys = 50;
+        let db = DbManager::new(&restored, shutdown).unwrap();
+        for _ in 0..100 {
+            if db.cache.total_len() >= 50 {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(50));
+        }
+        assert_eq!(db.cache.total_len(), 50);
+        assert_eq!(db.iter_all().unwrap().count(), 100);
+    }
+
+    #[test]
+    fn test_memory_breakdown() {
+        let mut config = test_config("memory_breakdown");
+        config.rocks_db_config.async_write = true;
+        //no writer threads, so async writes stay in the queue
+        config.rocks_db_config.num_async_writer_threads = 0;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::new(&config, shutdown).unwrap();
+        for i in 0..1_000 {
+            let key = format!("memory_key_{}", i);
+            db.put_sync(key.as_bytes(), &[b'v'; 100]).unwrap();
+        }
+        db.flush().unwrap();
+        for i in 0..1_000 {
+            let key = format!("memory_key_{}", i);
+            db.get_consistent(&KeyVal::new_with_key(key.as_bytes())).unwrap();
+        }
+        db.put(b"queued_key", &[b'v'; 100]).unwrap();
+
+        let breakdown = db.memory_breakdown();
+        assert!(breakdown.cache_bytes >= 1_000 * 100);
+        assert!(breakdown.cache_bytes < 1_000 * 1_000);
+        assert!(breakdown.block_cache_bytes > 0);
+        assert!(breakdown.memtable_bytes > 0);
+        assert_eq!(breakdown.write_queue_bytes, (b"queued_key".len() + 100) as u64);
+    }
+
+    #[test]
+    fn test_put_cache_only() {
+        let mut config = test_config("put_cache_only");
+        config.cache_config.cache_update_on_db_write = false;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::new(&config, shutdown).unwrap();
+        let kv = KeyVal::new(b"cache_only_key", b"derived");
+        db.put_cache_only(&kv).unwrap();
+        assert_eq!(db.get_key_val(&kv).unwrap(), Some((b"derived".to_vec(), true)));
+
+        db.cache.delete(&kv.key).unwrap();
+        assert_eq!(db.get_key_val(&kv).unwrap(), None);
+    }
+
+    #[test]
+    fn test_put_outcome() {
+        let mut config = test_config("put_outcome");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::new(&config, shutdown.clone()).unwrap();
+        let kv = KeyVal::new(b"outcome_key", b"val");
+        let outcome = db.put_key_val_with_outcome(&kv).unwrap();
+        assert_eq!(outcome, PutOutcome { persisted: true, cached: true });
+        drop(db);
+
+        config.rocks_db_config.enabled = false;
+        let db = DbManager::new(&config, shutdown).unwrap();
+        let outcome = db.put_key_val_with_outcome(&kv).unwrap();
+        assert_eq!(outcome, PutOutcome { persisted: false, cached: true });
+        assert_eq!(db.get_key_val(&kv).unwrap(), Some((b"val".to_vec(), true)));
+        drop(db);
+
+        config.rocks_db_config.enabled = true;
+        config.cache_config.cache_max_value_size = 4;
+        let db = DbManager::new(&config, shutdown.clone()).unwrap();
+        let large = KeyVal::new(b"large_key", b"too large to cache");
+        let outcome = db.put_key_val_with_outcome(&large).unwrap();
+        assert_eq!(outcome, PutOutcome { persisted: true, cached: false });
+        drop(db);
+
+        config.cache_config.cache_max_value_size = 0;
+        config.cache_config.shadow_mode = true;
+        let db = DbManager::new(&config, shutdown).unwrap();
+        let outcome = db.put_key_val_with_outcome(&kv).unwrap();
+        assert_eq!(outcome, PutOutcome { persisted: true, cached: false });
+    }
+
+    #[test]
+    fn test_key_prefix() {
+        let mut users = test_config("key_prefix");
+        users.key_prefix = "users:".to_string();
+        let mut orders = users.clone();
+        orders.key_prefix = "orders:".to_string();
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        //both share the db path, so only one can be open at a time
+        let db = DbManager::new(&users, shutdown.clone()).unwrap();
+        db.put(b"42", b"alice").unwrap();
+        drop(db);
+        let db = DbManager::new(&orders, shutdown.clone()).unwrap();
+        assert_eq!(db.get(b"42").unwrap(), None);
+        db.put(b"42", b"order_1").unwrap();
+        db.put(b"43", b"order_2").unwrap();
+        assert_eq!(db.get(b"42").unwrap(), Some((b"order_1".to_vec(), true)));
+        assert_eq!(db.iter_all().unwrap().count(), 2);
+        drop(db);
+
+        let db = DbManager::new(&users, shutdown).unwrap();
+        assert_eq!(db.get(b"42").unwrap(), Some((b"alice".to_vec(), false)));
+        assert_eq!(db.get(b"43").unwrap(), None);
+        assert_eq!(
+            db.scan_prefix(b"4", false).unwrap(),
+            vec![(b"42".to_vec(), b"alice".to_vec())]
+        );
+        let all: Result<Vec<(Vec<u8>, Vec<u8>)>, String> = db.iter_all().unwrap().collect();
+        assert_eq!(all.unwrap(), vec![(b"42".to_vec(), b"alice".to_vec())]);
+        db.delete(b"42").unwrap();
+        assert_eq!(db.get(b"42").unwrap(), None);
+    }
+
+    #[cfg(feature = "raw-access")]
+    #[test]
+    fn test_raw_db() {
+        let config = test_config("raw_db");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::new(&config, shutdown).unwrap();
+        db.put(b"raw_key", b"raw_val").unwrap();
+        let raw = db.raw_db().unwrap();
+        assert_eq!(raw.get(b"raw_key").unwrap().unwrap().to_vec(), b"raw_val".to_vec());
+    }
+
+    struct XorCodec;
+
+    impl ValueCodec for XorCodec {
+        fn encode(&self, raw: &[u8]) -> Result<Vec<u8>, String> {
+            Ok(raw.iter().map(|b| b ^ 0x5a).collect())
+        }
+
+        fn decode(&self, stored: &[u8]) -> Result<Vec<u8>, String> {
+            self.encode(stored)
+        }
+    }
+
+    struct CorruptCodec;
+
+    impl ValueCodec for CorruptCodec {
+        fn encode(&self, raw: &[u8]) -> Result<Vec<u8>, String> {
+            Ok(raw.to_vec())
+        }
+
+        fn decode(&self, _stored: &[u8]) -> Result<Vec<u8>, String> {
+            Err("corrupt value".to_string())
+        }
+    }
+
+    #[test]
+    fn test_iter_all_returns_decode_errors() {
+        let config = test_config("iter_all_decode_error");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::with_value_codec(&config, shutdown, Box::new(CorruptCodec)).unwrap();
+        db.put(b"corrupt_key", b"val").unwrap();
+        let items: Vec<_> = db.iter_all().unwrap().collect();
+        assert_eq!(items, vec![Err("corrupt value".to_string())]);
+    }
+
+    #[test]
+    fn test_value_codec() {
+        let config = test_config("value_codec");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::with_value_codec(&config, shutdown, Box::new(XorCodec)).unwrap();
+        let kv = KeyVal::new(b"codec_key", b"codec_val");
+        db.put_key_val(&kv).unwrap();
+
+        let stored = db.db.as_ref().unwrap().get(b"codec_key").unwrap().unwrap();
+        assert_ne!(stored, b"codec_val".to_vec());
+        assert_eq!(db.cache.get(b"codec_key"), Some(stored));
+        assert_eq!(db.get_key_val(&kv).unwrap(), Some((b"codec_val".to_vec(), true)));
+
+        db.invalidate_cache_prefix(b"codec_");
+        assert_eq!(db.get(b"codec_key").unwrap(), Some((b"codec_val".to_vec(), false)));
+        assert_eq!(db.get(b"codec_key").unwrap(), Some((b"codec_val".to_vec(), true)));
+
+        let absent = KeyVal::new(b"codec_absent", b"first");
+        assert!(db.put_if_absent(&absent).unwrap());
+        assert_eq!(db.get(b"codec_absent").unwrap(), Some((b"first".to_vec(), true)));
+        assert!(!db.put_if_changed(&absent).unwrap());
+
+        let next = KeyVal::new(b"codec_absent", b"second");
+        assert_eq!(db.put_and_get_prev(&next).unwrap(), Some(b"first".to_vec()));
+        assert_eq!(db.get(b"codec_absent").unwrap(), Some((b"second".to_vec(), true)));
+
+        let mut txn = db.transaction();
+        txn.put(b"codec_txn", b"txn_val").unwrap();
+        txn.commit().unwrap();
+        assert_eq!(db.get(b"codec_txn").unwrap(), Some((b"txn_val".to_vec(), false)));
+        assert_eq!(db.transaction().get(b"codec_txn").unwrap(), Some(b"txn_val".to_vec()));
+        assert_eq!(
+            db.scan_prefix(b"codec_txn", false).unwrap(),
+            vec![(b"codec_txn".to_vec(), b"txn_val".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_encryption_key() {
+        let mut config = test_config("encryption_key");
+        config.encryption_key = Some("2a".repeat(32));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::new(&config, shutdown.clone()).unwrap();
+        db.put(b"secret_key", b"secret_val").unwrap();
+        assert_eq!(db.get(b"secret_key").unwrap(), Some((b"secret_val".to_vec(), true)));
+        db.invalidate_cache_prefix(b"secret_");
+        assert_eq!(db.get(b"secret_key").unwrap(), Some((b"secret_val".to_vec(), false)));
+        drop(db);
+
+        //without the key, the db returns the values as stored
+        config.encryption_key = None;
+        let db = DbManager::new(&config, shutdown.clone()).unwrap();
+        let (stored, _) = db.get(b"secret_key").unwrap().unwrap();
+        assert_ne!(stored, b"secret_val".to_vec());
+        assert!(!stored.windows(10).any(|w| w == b"secret_val"));
+        drop(db);
+
+        config.encryption_key = Some("not hex".to_string());
+        assert!(DbManager::new(&config, shutdown).is_err());
+    }
+
+    #[test]
+    fn test_transaction_commit_and_rollback() {
+        let config = test_config("transaction");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::new(&config, shutdown).unwrap();
+        db.put(b"txn_key_1", b"old").unwrap();
+
+        let mut txn = db.transaction();
+        txn.put(b"txn_key_1", b"new").unwrap();
+        txn.put(b"txn_key_2", b"new").unwrap();
+        assert_eq!(txn.get(b"txn_key_1").unwrap(), Some(b"new".to_vec()));
+        txn.rollback();
+        assert_eq!(db.get(b"txn_key_1").unwrap().map(|(val, _)| val), Some(b"old".to_vec()));
+        assert_eq!(db.get(b"txn_key_2").unwrap(), None);
+
+        let mut txn = db.transaction();
+        txn.put(b"txn_key_1", b"new").unwrap();
+        txn.put(b"txn_key_2", b"new").unwrap();
+        txn.commit().unwrap();
+        assert_eq!(db.get(b"txn_key_1").unwrap().map(|(val, _)| val), Some(b"new".to_vec()));
+        assert_eq!(db.get(b"txn_key_2").unwrap().map(|(val, _)| val), Some(b"new".to_vec()));
+    }
+
+    #[test]
+    fn test_append() {
+        let mut config = test_config("append");
+        config.rocks_db_config.merge_operator = "append".to_string();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::new(&config, shutdown).unwrap();
+        for val in &["a", "b", "c"] {
+            db.append(&KeyVal::new(b"append_key", val.as_bytes())).unwrap();
+        }
+        let (val, _) = db.get(b"append_key").unwrap().unwrap();
+        assert_eq!(val, b"abc".to_vec());
+    }
+
+    #[test]
+    fn test_is_degraded() {
+        let config = test_config("not_degraded");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::new(&config, shutdown.clone()).unwrap();
+        assert!(!db.is_degraded());
+
+        //db path under a regular file can't be created
+        let mut config = test_config("degraded");
+        let file_path = crate::test_util::test_dir("degraded_file");
+        fs::create_dir_all(std::path::Path::new(&file_path).parent().unwrap()).unwrap();
+        fs::write(&file_path, b"").unwrap();
+        config.rocks_db_config.db_path = format!("{}/db", file_path);
+        config.rocks_db_config.wal_dir = "".to_string();
+        assert!(DbManager::new(&config, shutdown.clone()).is_err());
+
+        config.degrade_to_cache_on_db_error = true;
+        let db = DbManager::new(&config, shutdown).unwrap();
+        assert!(db.is_degraded());
+        db.put(b"degraded_key", b"val").unwrap();
+        assert_eq!(db.get(b"degraded_key").unwrap(), Some((b"val".to_vec(), true)));
+    }
+
+    #[test]
+    fn test_delete_and_get_prev() {
+        let config = test_config("delete_and_get_prev");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::new(&config, shutdown).unwrap();
+        let kv = KeyVal::new(b"delete_prev_key", b"val_1");
+        db.put_key_val(&kv).unwrap();
+
+        assert_eq!(db.delete_and_get_prev(&kv).unwrap(), Some(b"val_1".to_vec()));
+        assert_eq!(db.get_key_val(&kv).unwrap(), None);
+        assert_eq!(db.delete_and_get_prev(&kv).unwrap(), None);
+    }
+
+    #[test]
+    fn test_put_if_changed() {
+        let config = test_config("put_if_changed");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::new(&config, shutdown).unwrap();
+        let kv = KeyVal::new(b"changed_key", b"val_1");
+        assert!(db.put_if_changed(&kv).unwrap());
+        assert!(!db.put_if_changed(&kv).unwrap());
+
+        let kv = KeyVal::new(b"changed_key", b"val_2");
+        assert!(db.put_if_changed(&kv).unwrap());
+        let (val, _) = db.get_key_val(&kv).unwrap().unwrap();
+        assert_eq!(val, b"val_2".to_vec());
+    }
+
+    #[test]
+    fn test_put_if_absent() {
+        let mut config = test_config("put_if_absent");
+        config.rocks_db_config.async_write = true;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Arc::new(DbManager::new(&config, shutdown).unwrap());
+        let handles: Vec<_> = (0..16)
+            .map(|i| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    let kv = KeyVal::new(b"lock_key", format!("owner_{}", i).as_bytes());
+                    db.put_if_absent(&kv).unwrap()
+                })
+            })
+            .collect();
+        let winners = handles.into_iter().map(|h| h.join().unwrap()).filter(|won| *won).count();
+        assert_eq!(winners, 1);
+        assert!(!db.put_if_absent(&KeyVal::new(b"lock_key", b"late")).unwrap());
+    }
+
+    #[test]
+    fn test_cache_update_on_write_only_if_present() {
+        let mut config = test_config("cache_update_only_if_present");
+        config.cache_config.cache_update_on_write_only_if_present = true;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::new(&config, shutdown).unwrap();
+        db.put(b"refresh_key", b"val_1").unwrap();
+        assert_eq!(db.get(b"refresh_key").unwrap(), Some((b"val_1".to_vec(), false)));
+        assert_eq!(db.get(b"refresh_key").unwrap(), Some((b"val_1".to_vec(), true)));
+        db.put(b"refresh_key", b"val_2").unwrap();
+        assert_eq!(db.get(b"refresh_key").unwrap(), Some((b"val_2".to_vec(), true)));
+    }
+
+    #[test]
+    fn test_put_with() {
+        let mut config = test_config("put_with");
+        config.rocks_db_config.async_write = true;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::new(&config, shutdown).unwrap();
+        for i in 0..3 {
+            let key = format!("built_key_{}", i);
+            db.put_with(key.as_bytes(), |buf| {
+                buf.extend_from_slice(b"header:");
+                for part in 0..=i {
+                    buf.extend_from_slice(format!("part_{};", part).as_bytes());
+                }
+            })
+            .unwrap();
+        }
+        assert_eq!(
+            db.get(b"built_key_2").unwrap(),
+            Some((b"header:part_0;part_1;part_2;".to_vec(), true))
+        );
+        db.invalidate_cache_prefix(b"built_key_");
+        thread::sleep(std::time::Duration::from_millis(500));
+        assert_eq!(
+            db.get(b"built_key_0").unwrap(),
+            Some((b"header:part_0;".to_vec(), false))
+        );
+    }
+
+    #[test]
+    fn test_key_validation_regex() {
+        let mut config = test_config("key_validation");
+        config.key_validation_regex = Some(r"^tenant:\d+:.*".to_string());
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::new(&config, shutdown.clone()).unwrap();
+        db.put(b"tenant:42:profile", b"val").unwrap();
+        db.put_key_val(&KeyVal::new(b"tenant:7:", b"val")).unwrap();
+        assert_eq!(
+            db.put(b"tenant:abc:profile", b"val"),
+            Err("key failed validation".to_string())
+        );
+        assert_eq!(
+            db.put_key_val(&KeyVal::new(b"profile", b"val")),
+            Err("key failed validation".to_string())
+        );
+        let rejected = KeyVal::new(b"tenant:x:lock", b"val");
+        assert_eq!(db.put_if_absent(&rejected), Err("key failed validation".to_string()));
+        assert_eq!(db.put_and_get_prev(&rejected), Err("key failed validation".to_string()));
+        assert!(db.sliding_window_incr(b"rate", 1_000, 10).is_err());
+        let mut txn = db.transaction();
+        assert_eq!(txn.put(b"profile", b"val"), Err("key failed validation".to_string()));
+        assert!(txn.put(b"tenant:1:txn", b"val").is_ok());
+        txn.commit().unwrap();
+        assert_eq!(db.get(b"profile").unwrap(), None);
+        assert_eq!(db.get(b"tenant:x:lock").unwrap(), None);
+        assert!(db.get(b"tenant:42:profile").unwrap().is_some());
+        drop(db);
+
+        config.key_validation_regex = Some("^(tenant".to_string());
+        assert!(DbManager::new(&config, shutdown).is_err());
+    }
+
+    #[test]
+    fn test_get_audit() {
+        let config = test_config("get_audit");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::new(&config, shutdown).unwrap();
+        let kv = KeyVal::new(b"audit_key", b"disk_val");
+        db.put_key_val(&kv).unwrap();
+        assert_eq!(
+            db.get_audit(&kv).unwrap(),
+            (Some(b"disk_val".to_vec()), Some(b"disk_val".to_vec()))
+        );
+
+        db.put_cache_only(&KeyVal::new(b"audit_key", b"stale_val")).unwrap();
+        assert_eq!(
+            db.get_audit(&kv).unwrap(),
+            (Some(b"stale_val".to_vec()), Some(b"disk_val".to_vec()))
+        );
+        db.invalidate_cache_prefix(b"audit_");
+        assert_eq!(db.get_audit(&kv).unwrap(), (None, Some(b"disk_val".to_vec())));
+        //the audit doesn't populate the cache
+        assert_eq!(db.get_audit(&kv).unwrap(), (None, Some(b"disk_val".to_vec())));
+        assert_eq!(db.get_audit(&KeyVal::new(b"audit_missing", b"")).unwrap(), (None, None));
+    }
+
+    #[test]
+    fn test_delete_with_db_disabled_clears_cache() {
+        let mut config = test_config("delete_db_disabled");
+        config.rocks_db_config.enabled = false;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::new(&config, shutdown).unwrap();
+        let kv = KeyVal::new(b"cache_only_key", b"cache_only_val");
+        db.put_key_val(&kv).unwrap();
+        let (val, from_cache) = db.get_key_val(&kv).unwrap().unwrap();
+        assert_eq!(val, b"cache_only_val".to_vec());
+        assert!(from_cache);
+
+        assert!(db.delete_key_val(&kv).is_ok());
+        assert_eq!(db.get_key_val(&kv).unwrap(), None);
+
+        db.put(b"cache_only_key", b"cache_only_val").unwrap();
+        assert!(db.delete(b"cache_only_key").is_ok());
+        assert_eq!(db.get(b"cache_only_key").unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_through_batch() {
+        let mut config = test_config("read_through_batch");
+        config.cache_config.read_through_batch_size = 10;
+        config.cache_config.cache_update_on_db_write = false;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::new(&config, shutdown).unwrap();
+        let total = 25;
+        for i in 0..total {
+            let key = format!("read_through_{}", i);
+            db.put(key.as_bytes(), b"val").unwrap();
+        }
+        for i in 0..total {
+            let key = format!("read_through_{}", i);
+            let (_, from_cache) = db.get(key.as_bytes()).unwrap().unwrap();
+            assert!(!from_cache);
+        }
+        //two full batches flushed, remaining are buffered
+        assert_eq!(db.cache.shards.iter().map(|shard| shard.len()).sum::<usize>(), 20);
+        db.flush_read_through().unwrap();
+        for i in 0..total {
+            let key = format!("read_through_{}", i);
+            let (_, from_cache) = db.get(key.as_bytes()).unwrap().unwrap();
+            assert!(from_cache);
+        }
+    }
+
+    #[test]
+    fn test_swap_db_drops_stale_read_through() {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let rebuilt_config = test_config("swap_stale_rebuilt");
+        let rebuilt = DbManager::new(&rebuilt_config, shutdown.clone()).unwrap();
+        rebuilt.put(b"swap_key", b"new_val").unwrap();
+        drop(rebuilt);
+
+        let mut config = test_config("swap_stale");
+        config.cache_config.read_through_batch_size = 10;
+        config.cache_config.cache_update_on_db_write = false;
+        let db = DbManager::new(&config, shutdown).unwrap();
+        db.put(b"swap_key", b"old_val").unwrap();
+        //buffered read-through of the old value
+        assert_eq!(db.get(b"swap_key").unwrap(), Some((b"old_val".to_vec(), false)));
+
+        db.swap_db(&rebuilt_config.rocks_db_config.db_path).unwrap();
+        //a read of the old db finishing after the swap
+        db.cache_read_through(KeyVal::new(b"swap_key", b"old_val"), 0);
+        db.flush_read_through().unwrap();
+        assert_eq!(db.get(b"swap_key").unwrap(), Some((b"new_val".to_vec(), false)));
+    }
+
+    #[test]
+    fn test_oversized_value_not_cached() {
+        let mut config = test_config("oversized_not_cached");
+        config.rocks_db_config.max_value_size = 4;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::new(&config, shutdown).unwrap();
+        assert!(db.put(b"big_key", b"big_value").is_err());
+        assert_eq!(db.cache.get(b"big_key"), None);
+        assert_eq!(db.get(b"big_key").unwrap(), None);
+        drop(db);
+
+        //the read-modify-write puts check the size with the db disabled too
+        config.rocks_db_config.enabled = false;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = DbManager::new(&config, shutdown).unwrap();
+        let big = KeyVal::new(b"big_key", b"big_value");
+        assert!(db.put_if_changed(&big).is_err());
+        assert!(db.put_if_absent(&big).is_err());
+        assert!(db.put_and_get_prev(&big).is_err());
+        assert_eq!(db.cache.get(b"big_key"), None);
+    }
 }
@@ -0,0 +1,312 @@
+/************************************************
+
+   File Name: bhatho:db::durable_queue
+   Author: Rohit Joshi <rohit.c.joshi@gmail.com>
+   Date: 2019-02-17:15:15
+   License: Apache 2.0
+
+**************************************************/
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::keyval::KeyVal;
+
+const TOMBSTONE_LEN: u32 = u32::max_value();
+
+/// the applied records are cut from the head of the file once they take this many bytes
+const ROTATE_BYTES: u64 = 16 * 1024 * 1024;
+
+/// File backed log of the KeyVals enqueued for async write.
+/// Every KeyVal is appended before it is sent to the writer channel, in the same order,
+/// so the n-th KeyVal taken from the channel is the n-th record of the file.
+/// the records written to the db are cut from the head of the file once every record
+/// before them is written too, so KeyVals still in the queue are recovered after a crash.
+/// Each record is a 4 byte big endian key length, key, 4 byte big endian value length, value.
+/// a delete is recorded with TOMBSTONE_LEN as the value length and no value
+pub struct DurableQueue {
+    path: String,
+    file: Mutex<QueueFile>,
+    //sequence number of the next KeyVal taken from the channel
+    next_received: Mutex<u64>,
+    applied: Mutex<Applied>,
+    //0 syncs every append
+    sync_interval: Duration,
+}
+
+struct QueueFile {
+    file: File,
+    len: u64,
+    //end offset of each record in the file
+    record_ends: VecDeque<u64>,
+    //sequence number of the first record in the file
+    first_seq: u64,
+    last_sync: Instant,
+}
+
+/// sequence numbers written to the db
+#[derive(Default)]
+struct Applied {
+    //every sequence number below is written
+    upto: u64,
+    //written ranges above upto, by start
+    ranges: BTreeMap<u64, u64>,
+}
+
+impl DurableQueue {
+    /// open the queue file, creating it if missing.
+    /// the file is synced at most every sync_interval_ms, 0 syncs on every append
+    pub fn open(path: &str, sync_interval_ms: u64) -> Result<DurableQueue, String> {
+        if let Some(parent) = Path::new(path).parent() {
+            if let Err(e) = fs::create_dir_all(parent) {
+                error!(
+                    "Failed to create a directory: {:?} for durable async queue. Error: {:?}",
+                    parent, e
+                );
+                return Err(e.to_string());
+            }
+        }
+        let file = DurableQueue::open_file(path)?;
+        let len = match file.metadata() {
+            Ok(metadata) => metadata.len(),
+            Err(e) => return Err(e.to_string()),
+        };
+        Ok(DurableQueue {
+            path: path.to_string(),
+            file: Mutex::new(QueueFile {
+                file,
+                len,
+                record_ends: VecDeque::new(),
+                first_seq: 0,
+                last_sync: Instant::now(),
+            }),
+            next_received: Mutex::new(0),
+            applied: Mutex::new(Applied::default()),
+            sync_interval: Duration::from_millis(sync_interval_ms),
+        })
+    }
+
+    fn open_file(path: &str) -> Result<File, String> {
+        match OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+        {
+            Ok(f) => Ok(f),
+            Err(e) => {
+                error!(
+                    "Failed to open durable async queue file: {}. Error:{:?}",
+                    path, e
+                );
+                Err(e.to_string())
+            }
+        }
+    }
+
+    /// read all the KeyVals left in the queue file.
+    /// a partially written record at the end is ignored
+    pub fn recover(path: &str) -> Result<Vec<KeyVal>, String> {
+        if !Path::new(path).exists() {
+            return Ok(vec![]);
+        }
+        let mut buf = Vec::new();
+        if let Err(e) = File::open(path).and_then(|mut f| f.read_to_end(&mut buf)) {
+            error!(
+                "Failed to read durable async queue file: {}. Error:{:?}",
+                path, e
+            );
+            return Err(e.to_string());
+        }
+        let mut data = Vec::new();
+        let mut pos = 0;
+        loop {
+            let key = match DurableQueue::read_field(&buf, &mut pos) {
+                Some(key) => key,
+                None => break,
+            };
+            if buf.len() >= pos + 4 && buf[pos..pos + 4] == TOMBSTONE_LEN.to_be_bytes() {
+                pos += 4;
+                data.push(KeyVal::new_tombstone(key));
+                continue;
+            }
+            let val = match DurableQueue::read_field(&buf, &mut pos) {
+                Some(val) => val,
+                None => break,
+            };
+            data.push(KeyVal::new(key, val));
+        }
+        if pos < buf.len() {
+            warn!(
+                "Ignoring {} bytes of partial record in durable async queue file: {}",
+                buf.len() - pos,
+                path
+            );
+        }
+        Ok(data)
+    }
+
+    #[inline]
+    fn read_field<'a>(buf: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+        if buf.len() < *pos + 4 {
+            return None;
+        }
+        let mut len = [0u8; 4];
+        len.copy_from_slice(&buf[*pos..*pos + 4]);
+        let len = u32::from_be_bytes(len) as usize;
+        if buf.len() < *pos + 4 + len {
+            return None;
+        }
+        let field = &buf[*pos + 4..*pos + 4 + len];
+        *pos += 4 + len;
+        Some(field)
+    }
+
+    /// append the KeyVal to the queue file and pass it to send while the file is locked,
+    /// so the KeyVals are sent in the order of the file
+    pub fn append_then<F, T>(&self, kv: KeyVal, send: F) -> Result<T, String>
+    where
+        F: FnOnce(KeyVal) -> T,
+    {
+        let mut record = Vec::with_capacity(8 + kv.key.len() + kv.val.len());
+        record.extend_from_slice(&(kv.key.len() as u32).to_be_bytes());
+        record.extend_from_slice(&kv.key);
+        if kv.deleted {
+            record.extend_from_slice(&TOMBSTONE_LEN.to_be_bytes());
+        } else {
+            record.extend_from_slice(&(kv.val.len() as u32).to_be_bytes());
+            record.extend_from_slice(&kv.val);
+        }
+
+        let mut queue_file = self.file.lock();
+        if let Err(e) = queue_file.file.write_all(&record) {
+            error!(
+                "Failed to append to durable async queue file: {}. Error:{:?}",
+                self.path, e
+            );
+            return Err(e.to_string());
+        }
+        queue_file.len += record.len() as u64;
+        let len = queue_file.len;
+        queue_file.record_ends.push_back(len);
+        if queue_file.last_sync.elapsed() >= self.sync_interval {
+            if let Err(e) = queue_file.file.sync_data() {
+                error!(
+                    "Failed to sync durable async queue file: {}. Error:{:?}",
+                    self.path, e
+                );
+                return Err(e.to_string());
+            }
+            queue_file.last_sync = Instant::now();
+        }
+        Ok(send(kv))
+    }
+
+    /// take KeyVals from the channel with drain and return them with their sequence numbers.
+    /// drains are serialized so the sequence numbers follow the channel order
+    pub fn receive<F>(&self, drain: F) -> (Vec<KeyVal>, Range<u64>)
+    where
+        F: FnOnce() -> Vec<KeyVal>,
+    {
+        let mut next_received = self.next_received.lock();
+        let data = drain();
+        let start = *next_received;
+        *next_received += data.len() as u64;
+        (data, start..*next_received)
+    }
+
+    /// record that the received KeyVals with the sequence numbers seqs are written to the db.
+    /// the records up to the first one not written yet are cut from the file after sync_db
+    /// made the writes durable. the file is left as is if it is locked by an append,
+    /// the next call cuts it
+    pub fn mark_applied<F>(&self, seqs: Range<u64>, sync_db: F)
+    where
+        F: FnOnce() -> Result<(), String>,
+    {
+        let upto = {
+            let mut applied = self.applied.lock();
+            if seqs.start < seqs.end {
+                applied.ranges.insert(seqs.start, seqs.end);
+            }
+            loop {
+                let upto = applied.upto;
+                match applied.ranges.remove(&upto) {
+                    Some(end) => applied.upto = end,
+                    None => break,
+                }
+            }
+            applied.upto
+        };
+        let mut queue_file = match self.file.try_lock() {
+            Some(queue_file) => queue_file,
+            None => return,
+        };
+        if upto <= queue_file.first_seq {
+            return;
+        }
+        let applied = (upto - queue_file.first_seq) as usize;
+        let offset = queue_file.record_ends[applied - 1];
+        if offset < queue_file.len && offset < ROTATE_BYTES {
+            return;
+        }
+        if let Err(e) = sync_db() {
+            error!("Failed to sync db before cutting durable async queue. Error:{}", e);
+            return;
+        }
+        if let Err(e) = self.cut_head(&mut queue_file, offset) {
+            error!(
+                "Failed to cut durable async queue file: {}. Error:{}",
+                self.path, e
+            );
+            return;
+        }
+        queue_file.record_ends.drain(..applied);
+        for end in queue_file.record_ends.iter_mut() {
+            *end -= offset;
+        }
+        queue_file.first_seq = upto;
+    }
+
+    /// remove the first offset bytes of the file. the rest is copied to a new file
+    /// replacing the queue file
+    fn cut_head(&self, queue_file: &mut QueueFile, offset: u64) -> Result<(), String> {
+        if offset == queue_file.len {
+            queue_file.file.set_len(0).map_err(|e| e.to_string())?;
+            queue_file.len = 0;
+            return Ok(());
+        }
+        let mut tail = Vec::with_capacity((queue_file.len - offset) as usize);
+        queue_file
+            .file
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| queue_file.file.read_to_end(&mut tail))
+            .map_err(|e| e.to_string())?;
+        let tmp_path = format!("{}.tmp", self.path);
+        File::create(&tmp_path)
+            .and_then(|mut tmp| tmp.write_all(&tail).and_then(|_| tmp.sync_data()))
+            .and_then(|_| fs::rename(&tmp_path, &self.path))
+            .map_err(|e| e.to_string())?;
+        queue_file.file = DurableQueue::open_file(&self.path)?;
+        queue_file.len = tail.len() as u64;
+        Ok(())
+    }
+
+    /// truncate the queue file
+    pub fn clear(&self) -> Result<(), String> {
+        let mut queue_file = self.file.lock();
+        if let Err(e) = queue_file.file.set_len(0) {
+            return Err(e.to_string());
+        }
+        queue_file.len = 0;
+        queue_file.record_ends.clear();
+        queue_file.first_seq = 0;
+        *self.next_received.lock() = 0;
+        *self.applied.lock() = Applied::default();
+        Ok(())
+    }
+}
@@ -6,6 +6,10 @@
    License: Apache 2.0
 
 **************************************************/
+pub mod cipher;
+pub mod codec;
 pub mod config;
 pub mod db_manager;
+pub mod durable_queue;
 pub mod rocks_db;
+pub mod transaction;
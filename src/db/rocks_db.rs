@@ -8,26 +8,140 @@
 **************************************************/
 use crossbeam_channel as mpsc;
 use rocksdb::{
-    BlockBasedIndexType, BlockBasedOptions, DB as rocks_db, DBCompressionType, SliceTransform,
-    WriteBatch,
+    BlockBasedIndexType, BlockBasedOptions, Cache, DB as rocks_db, DBCompactionStyle,
+    DBCompressionType, DBIterator, DBRecoveryMode, Direction, FifoCompactOptions, IteratorMode,
+    MemtableFactory, MergeOperands, ReadOptions, SliceTransform, WriteBatch, WriteOptions,
 };
 use rocksdb::backup::{BackupEngine, BackupEngineOptions};
+use rocksdb::compaction_filter::Decision;
 use rocksdb::Options as rocks_options;
+use std::borrow::Cow;
+use std::fs;
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::fmt;
 use std::sync::Arc;
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, AtomicU64};
 use std::sync::atomic::Ordering;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use crate::db::codec::ValueCodec;
 use crate::db::config::RocksDbConfig;
+use crate::db::durable_queue::DurableQueue;
 use crate::keyval::KeyVal;
 
+/// shared db handle. None only while the db is being reopened or if reopen failed
+type DbHandle = Arc<RwLock<Option<Arc<rocks_db>>>>;
+
+/// key prefixes dropped by the compaction filter. shared with the filter of the open db
+type DropPrefixes = Arc<DropPrefixList>;
+
+/// active is false while no prefix is set, so the filter keeps the keys without locking
+#[derive(Default)]
+struct DropPrefixList {
+    active: AtomicBool,
+    prefixes: RwLock<Vec<Vec<u8>>>,
+}
+
+/// max time reopen waits for in-flight operations and iterators to release the db
+const REOPEN_WAIT: Duration = Duration::from_secs(5);
+
+/// reserved key read by the health check
+const HEALTH_CHECK_KEY: &[u8] = b"__bhatho_health_check__";
+
+/// min time without a writer heartbeat before the async writers are reported as stalled
+const WRITER_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// max time to wait for the background threads to exit when the db is dropped
+const WORKER_JOIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// counters updated by the async writer threads
+struct WriterStats {
+    //bytes of the KeyVals waiting in the async write queue
+    queued_bytes: AtomicU64,
+    //largest number of KeyVals drained by an async writer in a single batch
+    max_batch_len: AtomicU64,
+    //epoch millis of the last async writer loop iteration
+    heartbeat: AtomicU64,
+}
+
+/// background threads of a RocksDb. It is dropped with the last clone of the RocksDb,
+/// which stops the threads and joins them, waiting up to WORKER_JOIN_TIMEOUT.
+/// async writers drain the write queue before exiting
+struct Workers {
+    stop: Arc<AtomicBool>,
+    handles: Mutex<Vec<thread::JoinHandle<()>>>,
+    //each thread sends on exit, so joins never block past the timeout
+    done_tx: mpsc::Sender<()>,
+    done_rx: mpsc::Receiver<()>,
+}
+
+impl Workers {
+    fn new() -> Workers {
+        let (done_tx, done_rx) = mpsc::unbounded();
+        Workers {
+            stop: Arc::new(AtomicBool::new(false)),
+            handles: Mutex::new(Vec::new()),
+            done_tx,
+            done_rx,
+        }
+    }
+
+    fn spawn<F>(&self, name: &str, f: F) -> Result<(), String>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let done = self.done_tx.clone();
+        let handle = thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                f();
+                let _ = done.send(());
+            })
+            .map_err(|e| e.to_string())?;
+        self.handles.lock().push(handle);
+        Ok(())
+    }
+}
+
+impl Drop for Workers {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        let handles: Vec<thread::JoinHandle<()>> = self.handles.lock().drain(..).collect();
+        let deadline = Instant::now() + WORKER_JOIN_TIMEOUT;
+        for _ in 0..handles.len() {
+            let now = Instant::now();
+            if now >= deadline || self.done_rx.recv_timeout(deadline - now).is_err() {
+                warn!(
+                    "Background threads didn't exit within {:?}. Detaching them",
+                    WORKER_JOIN_TIMEOUT
+                );
+                return;
+            }
+        }
+        for handle in handles {
+            let _ = handle.join();
+        }
+    }
+}
+
 //TODO Add support for column family
 pub struct RocksDb {
     pub enabled: bool,
-    pub db: Arc<rocks_db>,
+    db: DbHandle,
     pub sender: mpsc::Sender<KeyVal>,
     pub config: RocksDbConfig,
+    durable_queue: Option<Arc<DurableQueue>>,
+    stats: Arc<WriterStats>,
+    //None only for the clone used by the backup scheduler, so it doesn't keep itself alive
+    workers: Option<Arc<Workers>>,
+    //set while a backup is being taken
+    backup_running: Arc<AtomicBool>,
+    restored_from_backup: bool,
+    //encrypts the values written to the db
+    cipher: Option<Arc<dyn ValueCodec>>,
+    drop_prefixes: DropPrefixes,
 }
 
 //using single thread loop , so it is safe
@@ -43,10 +157,90 @@ impl Clone for RocksDb {
             db: self.db.clone(),
             sender: self.sender.clone(),
             config: self.config.clone(),
+            durable_queue: self.durable_queue.clone(),
+            stats: self.stats.clone(),
+            workers: self.workers.clone(),
+            backup_running: self.backup_running.clone(),
+            restored_from_backup: self.restored_from_backup,
+            cipher: self.cipher.clone(),
+            drop_prefixes: self.drop_prefixes.clone(),
+        }
+    }
+}
+
+/// error of an async write
+#[derive(Debug)]
+pub enum AsyncWriteError {
+    /// async writer threads are gone (e.g. after shutdown). carries the KeyVal not written
+    Disconnected(KeyVal),
+    Failed(String),
+}
+
+impl fmt::Display for AsyncWriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsyncWriteError::Disconnected(kv) => write!(
+                f,
+                "async write queue disconnected. key not written: {}",
+                String::from_utf8_lossy(&kv.key)
+            ),
+            AsyncWriteError::Failed(e) => write!(f, "{}", e),
         }
     }
 }
 
+impl From<AsyncWriteError> for String {
+    fn from(e: AsyncWriteError) -> String {
+        e.to_string()
+    }
+}
+
+/// number of files and size of a LSM level
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LevelStat {
+    pub level: u32,
+    pub num_files: u64,
+    pub size_bytes: u64,
+}
+
+/// metadata of a backup. timestamp is in seconds since the epoch
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupInfo {
+    pub backup_id: u32,
+    pub timestamp: i64,
+    pub size: u64,
+    pub num_files: u32,
+}
+
+/// Iterator over all the key-val pairs of a db.
+/// It holds a reference to the db so the db remains open while iterating.
+/// a value failing to decrypt is returned as an error
+pub struct RocksDbIterator {
+    iter: DBIterator,
+    _db: Arc<rocks_db>,
+    cipher: Option<Arc<dyn ValueCodec>>,
+}
+
+impl Iterator for RocksDbIterator {
+    type Item = Result<(Vec<u8>, Vec<u8>), String>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Result<(Vec<u8>, Vec<u8>), String>> {
+        let (key, val) = self.iter.next()?;
+        let val = match self.cipher.as_ref() {
+            Some(cipher) => match cipher.decode(&val) {
+                Ok(val) => val,
+                Err(e) => {
+                    error!("Failed to decrypt a value while iterating. Error:{}", e);
+                    return Some(Err(e));
+                }
+            },
+            None => val.to_vec(),
+        };
+        Some(Ok((key.to_vec(), val)))
+    }
+}
+
 impl RocksDb {
     //1M max
     ///
@@ -68,8 +262,36 @@ impl RocksDb {
             opts.set_target_file_size_base(128 * 1024 * 1024);
             opts.set_min_write_buffer_number_to_merge(4);
             opts.set_level_zero_stop_writes_trigger(2000);
-            opts.set_level_zero_slowdown_writes_trigger(0);
-            opts.set_compaction_style(DBCompactionStyle::Universal);*/
+            opts.set_level_zero_slowdown_writes_trigger(0);*/
+            match rocks_config.compaction_style.as_str() {
+                "level" => opts.set_compaction_style(DBCompactionStyle::Level),
+                "universal" => opts.set_compaction_style(DBCompactionStyle::Universal),
+                "fifo" => {
+                    opts.set_compaction_style(DBCompactionStyle::Fifo);
+                    let mut fifo_opts = FifoCompactOptions::default();
+                    fifo_opts.set_max_table_files_size(
+                        rocks_config.fifo_max_table_files_size_mb * 1024 * 1024,
+                    );
+                    opts.set_fifo_compaction_options(&fifo_opts);
+                }
+                other => {
+                    error!("Invalid compaction_style: {}", other);
+                    return Err(format!("unknown compaction_style: {}", other));
+                }
+            }
+            if rocks_config.level0_file_num_compaction_trigger >= 0 {
+                opts.set_level_zero_file_num_compaction_trigger(
+                    rocks_config.level0_file_num_compaction_trigger,
+                );
+            }
+            if rocks_config.level0_slowdown_writes_trigger >= 0 {
+                opts.set_level_zero_slowdown_writes_trigger(
+                    rocks_config.level0_slowdown_writes_trigger,
+                );
+            }
+            if rocks_config.level0_stop_writes_trigger >= 0 {
+                opts.set_level_zero_stop_writes_trigger(rocks_config.level0_stop_writes_trigger);
+            }
 
             opts.set_max_open_files(rocks_config.max_open_files);
             opts.increase_parallelism(rocks_config.num_threads_parallelism);
@@ -90,81 +312,318 @@ impl RocksDb {
         }
 
         let mut block_opts = BlockBasedOptions::default();
+        let mut has_prefix_extractor = false;
 
         if !rocks_config.use_default_block_config {
             block_opts.set_block_size(rocks_config.block_size);
-            let prefix_extractor = SliceTransform::create_fixed_prefix(3);
-            opts.set_prefix_extractor(prefix_extractor);
-            block_opts.set_index_type(BlockBasedIndexType::HashSearch);
+            match rocks_config.index_type.as_str() {
+                "hash_search" => {
+                    //hash search index requires a prefix extractor
+                    let prefix_extractor = SliceTransform::create_fixed_prefix(3);
+                    opts.set_prefix_extractor(prefix_extractor);
+                    has_prefix_extractor = true;
+                    block_opts.set_index_type(BlockBasedIndexType::HashSearch);
+                }
+                "binary_search" => block_opts.set_index_type(BlockBasedIndexType::BinarySearch),
+                "two_level" => {
+                    block_opts.set_index_type(BlockBasedIndexType::TwoLevelIndexSearch)
+                }
+                other => {
+                    error!("Invalid index_type: {}", other);
+                    return Err(format!("unknown index_type: {}", other));
+                }
+            }
 
             block_opts.set_cache_index_and_filter_blocks(true);
+            if rocks_config.pin_l0_filter_and_index_blocks {
+                block_opts.set_pin_l0_filter_and_index_blocks_in_cache(true);
+            }
+            if rocks_config.cache_index_and_filter_blocks_with_high_priority {
+                block_opts.set_cache_index_and_filter_blocks_with_high_priority(true);
+            }
             if rocks_config.bloom_filter {
-                block_opts.set_bloom_filter(10, true);
+                if rocks_config.bloom_filter_bits_per_key <= 0 {
+                    error!(
+                        "Invalid bloom_filter_bits_per_key: {}",
+                        rocks_config.bloom_filter_bits_per_key
+                    );
+                    return Err("bloom_filter_bits_per_key must be positive".to_string());
+                }
+                block_opts.set_bloom_filter(
+                    rocks_config.bloom_filter_bits_per_key,
+                    rocks_config.bloom_filter_block_based,
+                );
             }
             if rocks_config.lru_cache_size_mb > 0 {
-                block_opts.set_lru_cache(rocks_config.lru_cache_size_mb * 1024 * 1024); //1GB:  In prod, it should be 64GB
+                //1GB:  In prod, it should be 64GB
+                let capacity = rocks_config.lru_cache_size_mb * 1024 * 1024;
+                if rocks_config.block_cache_shard_bits < 0 {
+                    block_opts.set_lru_cache(capacity);
+                } else {
+                    let cache =
+                        Cache::new_lru_cache(capacity, rocks_config.block_cache_shard_bits)
+                            .map_err(|e| e.to_string())?;
+                    block_opts.set_block_cache(&cache);
+                }
             }
+            opts.set_block_based_table_factory(&block_opts);
+        } else if rocks_config.point_lookup_block_size_mb == 0 {
+            opts.set_block_based_table_factory(&block_opts);
+        } else {
+            //keep the block based table of optimize_for_point_lookup
+            debug!("Using block based table config of optimize_for_point_lookup");
         }
-        opts.set_block_based_table_factory(&block_opts);
 
+        match rocks_config.memtable_factory.as_str() {
+            "skiplist" => {}
+            "hash_skiplist" => {
+                if !has_prefix_extractor {
+                    error!("memtable_factory hash_skiplist requires a prefix extractor");
+                    return Err("memtable_factory hash_skiplist requires index_type hash_search \
+                                with use_default_block_config false"
+                        .to_string());
+                }
+                //only skiplist supports concurrent memtable writes
+                opts.set_allow_concurrent_memtable_write(false);
+                opts.set_memtable_factory(MemtableFactory::HashSkipList {
+                    bucket_count: 1_000_000,
+                    height: 4,
+                    branching_factor: 4,
+                });
+            }
+            "vector" => {
+                opts.set_allow_concurrent_memtable_write(false);
+                opts.set_memtable_factory(MemtableFactory::Vector);
+            }
+            other => {
+                error!("Invalid memtable_factory: {}", other);
+                return Err(format!("unknown memtable_factory: {}", other));
+            }
+        }
 
         if !rocks_config.wal_dir.is_empty() {
             opts.set_wal_dir(&rocks_config.wal_dir);
         }
+        if rocks_config.wal_size_limit_mb > 0 {
+            opts.set_wal_size_limit_mb(rocks_config.wal_size_limit_mb);
+        }
+        if rocks_config.wal_ttl_secs > 0 {
+            opts.set_wal_ttl_seconds(rocks_config.wal_ttl_secs);
+        }
+        match rocks_config.wal_recovery_mode.as_str() {
+            "tolerate_corrupted_tail" => {
+                opts.set_wal_recovery_mode(DBRecoveryMode::TolerateCorruptedTailRecords)
+            }
+            "absolute_consistency" => {
+                opts.set_wal_recovery_mode(DBRecoveryMode::AbsoluteConsistency)
+            }
+            "point_in_time" => opts.set_wal_recovery_mode(DBRecoveryMode::PointInTime),
+            "skip_any_corrupted" => {
+                opts.set_wal_recovery_mode(DBRecoveryMode::SkipAnyCorruptedRecord)
+            }
+            other => {
+                error!("Invalid wal_recovery_mode: {}", other);
+                return Err(format!("unknown wal_recovery_mode: {}", other));
+            }
+        }
+
+        match rocks_config.merge_operator.as_str() {
+            "" => {}
+            "append" => opts.set_merge_operator("append", RocksDb::append_merge, None),
+            other => {
+                error!("Invalid merge_operator: {}", other);
+                return Err(format!("unknown merge_operator: {}", other));
+            }
+        }
 
         Ok(opts)
     }
+
+    /// merge operator concatenating the operands to the existing value
+    fn append_merge(
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &mut MergeOperands,
+    ) -> Option<Vec<u8>> {
+        let mut result = existing.map(|val| val.to_vec()).unwrap_or_default();
+        for op in operands {
+            result.extend_from_slice(op);
+        }
+        Some(result)
+    }
     /// initialize rocks db options and create a new db instance
-    fn init_rocks_db(rocks_config: &RocksDbConfig) -> Result<rocks_db, String> {
+    fn init_rocks_db(
+        rocks_config: &RocksDbConfig,
+        drop_prefixes: &DropPrefixes,
+    ) -> Result<rocks_db, String> {
         info!("Creating RocksDB instance");
 
-        let opts = RocksDb::create_rocks_db_options(&rocks_config)?;
-        match rocks_db::open(&opts, &rocks_config.db_path) {
-            Ok(db) => Ok(db),
-            Err(e) => {
+        let mut opts = RocksDb::create_rocks_db_options(&rocks_config)?;
+        let drop_prefixes = drop_prefixes.clone();
+        opts.set_compaction_filter("bhatho_drop_prefixes", move |_level, key, _val| {
+            if !drop_prefixes.active.load(Ordering::Relaxed) {
+                return Decision::Keep;
+            }
+            let prefixes = drop_prefixes.prefixes.read();
+            if prefixes.iter().any(|prefix| key.starts_with(prefix)) {
+                Decision::Remove
+            } else {
+                Decision::Keep
+            }
+        });
+        if rocks_config.open_read_only {
+            info!("Opening RocksDB read-only. DB Path: {}", rocks_config.db_path);
+        } else if !rocks_config.column_families.is_empty() {
+            opts.create_missing_column_families(true);
+        }
+        let cfs: Vec<&str> = rocks_config
+            .column_families
+            .iter()
+            .map(|cf| cf.as_str())
+            .collect();
+        let mut attempt = 0;
+        loop {
+            let res = match (rocks_config.open_read_only, cfs.is_empty()) {
+                (true, true) => rocks_db::open_for_read_only(&opts, &rocks_config.db_path, false),
+                (true, false) => {
+                    rocks_db::open_cf_for_read_only(&opts, &rocks_config.db_path, &cfs, false)
+                }
+                (false, true) => rocks_db::open(&opts, &rocks_config.db_path),
+                (false, false) => rocks_db::open_cf(&opts, &rocks_config.db_path, &cfs),
+            };
+            let e = match res {
+                Ok(db) => return Ok(db),
+                Err(e) => e.to_string(),
+            };
+            if !RocksDb::is_lock_error(&e) {
                 error!("Failed to open rockdb database. Error:{:?}", e);
-                Err(e.to_string())
+                return Err(e);
+            }
+            if attempt >= rocks_config.open_retry_count {
+                error!(
+                    "Failed to open rockdb database. It is locked by another process. Error:{:?}",
+                    e
+                );
+                return Err(format!(
+                    "db_path already locked by another process: {}",
+                    rocks_config.db_path
+                ));
             }
+            attempt += 1;
+            warn!(
+                "DB Path: {} is locked. Retrying open in {} ms. Attempt: {}/{}",
+                rocks_config.db_path,
+                rocks_config.open_retry_sleep_ms,
+                attempt,
+                rocks_config.open_retry_count
+            );
+            thread::sleep(Duration::from_millis(rocks_config.open_retry_sleep_ms));
         }
     }
 
+    /// true if db open failed as the LOCK file is held by another process or DB instance
+    #[inline]
+    fn is_lock_error(e: &str) -> bool {
+        e.contains("lock hold") || e.contains("While lock file")
+    }
+
     /// Write to database async
     /// It reads from the channel
     fn write_to_db(
         db_config: RocksDbConfig,
-        db: Arc<rocks_db>,
+        db_handle: DbHandle,
         receiver: mpsc::Receiver<KeyVal>,
         shutdown: Arc<AtomicBool>,
+        stop: Arc<AtomicBool>,
+        durable_queue: Option<Arc<DurableQueue>>,
+        stats: Arc<WriterStats>,
     ) {
+        let stopped = || shutdown.load(Ordering::SeqCst) || stop.load(Ordering::SeqCst);
+        let max_sleep_ms = db_config.async_writer_threads_sleep_ms;
+        let min_sleep_ms = std::cmp::max(db_config.async_writer_min_sleep_ms, 1);
+        let min_sleep_ms = std::cmp::min(min_sleep_ms, max_sleep_ms);
+        let mut sleep_ms = min_sleep_ms;
         loop {
-            let data: Vec<KeyVal> = receiver.try_iter().collect();
+            stats.heartbeat.store(RocksDb::now_millis(), Ordering::SeqCst);
+            //drain at most max_write_batch_size KeyVals, rest are written by the next iterations
+            let drain = || -> Vec<KeyVal> {
+                if db_config.max_write_batch_size > 0 {
+                    receiver.try_iter().take(db_config.max_write_batch_size).collect()
+                } else {
+                    receiver.try_iter().collect()
+                }
+            };
+            let (data, seqs) = match durable_queue.as_ref() {
+                Some(queue) => queue.receive(drain),
+                None => (drain(), 0..0),
+            };
 
-            //timeout, no data received. let's sleep
+            //timeout, no data received. let's sleep, longer on each consecutive empty poll
             if data.is_empty() {
-                if shutdown.load(Ordering::SeqCst) {
+                if stopped() {
                     info!("Shutdown received. Exiting while loop");
                     return;
                 }
+                thread::sleep(Duration::from_millis(sleep_ms));
+                sleep_ms = std::cmp::min(sleep_ms * 2, max_sleep_ms);
+                continue;
+            }
+            sleep_ms = min_sleep_ms;
+            let data_bytes: u64 = data.iter().map(|kv| (kv.key.len() + kv.val.len()) as u64).sum();
+            stats.queued_bytes.fetch_sub(data_bytes, Ordering::SeqCst);
+            let mut max = stats.max_batch_len.load(Ordering::SeqCst);
+            while data.len() as u64 > max {
+                match stats.max_batch_len.compare_exchange(
+                    max,
+                    data.len() as u64,
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                ) {
+                    Ok(_) => break,
+                    Err(current) => max = current,
+                }
+            }
+
+            //db is unavailable while it is being reopened
+            let db = loop {
+                if let Some(db) = db_handle.read().clone() {
+                    break db;
+                }
+                if stopped() {
+                    error!("Shutdown received while db is closed. Dropping {} writes", data.len());
+                    return;
+                }
                 thread::sleep(Duration::from_millis(
                     db_config.async_writer_threads_sleep_ms,
                 ));
-
-                continue;
-            }
+            };
 
             //we got data, write to db as a single record
             if data.len() < db_config.min_count_for_batch_write {
                 for kv in data.iter() {
-                    if let Err(e) = db.put(&kv.key, &kv.val) {
+                    let res = if kv.deleted {
+                        db.delete(&kv.key)
+                    } else {
+                        db.put(&kv.key, &kv.val)
+                    };
+                    if let Err(e) = res {
                         error!("Failed to batch write to RocksDB. Error:{:?}", e);
                     }
                 }
+                if let Some(queue) = durable_queue.as_ref() {
+                    queue.mark_applied(seqs, || RocksDb::sync_db_wal(&db));
+                }
                 continue;
             }
             // write data as batch
             let mut batch = WriteBatch::default();
             for kv in data.iter() {
-                if let Err(e) = batch.put(&kv.key, &kv.val) {
+                let res = if kv.deleted {
+                    batch.delete(&kv.key)
+                } else {
+                    batch.put(&kv.key, &kv.val)
+                };
+                if let Err(e) = res {
                     error!(
                         "Failed to add into the batch for writing to RocksDB. Error:{:?}",
                         e
@@ -179,12 +638,65 @@ impl RocksDb {
             } else if let Err(e) = db.write(batch) {
                 error!("Failed to batch write to RocksDB. Error:{:?}", e);
             }
+            if let Some(queue) = durable_queue.as_ref() {
+                queue.mark_applied(seqs, || RocksDb::sync_db_wal(&db));
+            }
+        }
+    }
+
+    /// sync the WAL so the writes are durable before they are cut from the durable queue
+    fn sync_db_wal(db: &rocks_db) -> Result<(), String> {
+        db.flush_wal(true).map_err(|e| e.to_string())
+    }
+
+    /// path of the durable async queue file
+    fn durable_queue_path(config: &RocksDbConfig) -> String {
+        if config.durable_async_queue_path.is_empty() {
+            format!("{}/async_queue.log", config.db_path)
+        } else {
+            config.durable_async_queue_path.clone()
+        }
+    }
+
+    /// write the KeyVals left in the durable async queue by a previous run and clear it
+    fn recover_durable_queue(
+        db: &rocks_db,
+        path: &str,
+        sync_interval_ms: u64,
+    ) -> Result<DurableQueue, String> {
+        let data = DurableQueue::recover(path)?;
+        if !data.is_empty() {
+            info!(
+                "Recovering {} queued writes from durable async queue: {}",
+                data.len(),
+                path
+            );
+            let mut batch = WriteBatch::default();
+            for kv in data.iter() {
+                let res = if kv.deleted {
+                    batch.delete(&kv.key)
+                } else {
+                    batch.put(&kv.key, &kv.val)
+                };
+                if let Err(e) = res {
+                    return Err(e.to_string());
+                }
+            }
+            if let Err(e) = db.write(batch) {
+                error!("Failed to write recovered async queue to RocksDB. Error:{:?}", e);
+                return Err(e.to_string());
+            }
+            RocksDb::sync_db_wal(db)?;
         }
+        let queue = DurableQueue::open(path, sync_interval_ms)?;
+        queue.clear()?;
+        Ok(queue)
     }
 
     /// create a RocksDB instance from the config
     pub fn new(config: &RocksDbConfig, shutdown: Arc<AtomicBool>) -> Result<RocksDb, String> {
-        if config.restore_from_backup_at_startup && config.enabled {
+        let mut restored_from_backup = false;
+        if config.restore_from_backup_at_startup && config.enabled && !config.open_read_only {
             if let Ok(mut backup_engine) = RocksDb::create_backup_engine(&config) {
                 let mut restore_option = rocksdb::backup::RestoreOptions::default();
                 restore_option.set_keep_log_files(config.keep_log_file_while_restore);
@@ -202,6 +714,7 @@ impl RocksDb {
                     return Err(e.to_string());
                 }
                 info!("Restoring DB from a backup path: {}", config.backup_path);
+                restored_from_backup = true;
             } else {}
         } else {
             info!("Initializing DB from a path: {}", config.db_path);
@@ -210,28 +723,119 @@ impl RocksDb {
             warn!("DB not enabled for DB Path: {}", config.db_path);
         }
 
-        let db = Arc::new(RocksDb::init_rocks_db(&config)?);
+        let drop_prefixes: DropPrefixes = Arc::new(DropPrefixList::default());
+        let db = RocksDb::init_rocks_db(&config, &drop_prefixes)?;
+
+        let async_write = config.async_write && config.enabled && !config.open_read_only;
+        let durable_queue = if config.durable_async_queue && async_write {
+            if config.disable_wal {
+                return Err("durable_async_queue requires the WAL, disable_wal must be false"
+                    .to_string());
+            }
+            let path = RocksDb::durable_queue_path(&config);
+            let sync_ms = config.durable_async_queue_sync_ms;
+            Some(Arc::new(RocksDb::recover_durable_queue(&db, &path, sync_ms)?))
+        } else {
+            None
+        };
 
-        //let (tx, rx) = mpsc::unbounded::<KeyVal>();
-        let (tx, rx) = mpsc::bounded::<KeyVal>(config.async_write_queue_length);
+        let (tx, rx) = if config.async_write_unbounded {
+            mpsc::unbounded::<KeyVal>()
+        } else {
+            mpsc::bounded::<KeyVal>(config.async_write_queue_length)
+        };
 
-        if config.async_write && config.enabled {
+        let db: DbHandle = Arc::new(RwLock::new(Some(Arc::new(db))));
+        let stats = Arc::new(WriterStats {
+            queued_bytes: AtomicU64::new(0),
+            max_batch_len: AtomicU64::new(0),
+            heartbeat: AtomicU64::new(RocksDb::now_millis()),
+        });
+        let workers = Arc::new(Workers::new());
+        if async_write {
             for _i in 0..config.num_async_writer_threads {
                 let config_clone = config.clone();
                 let db_clone = db.clone();
                 let rx = rx.clone();
                 let shutdown = shutdown.clone();
-                thread::spawn(move || {
-                    RocksDb::write_to_db(config_clone, db_clone, rx, shutdown);
-                });
+                let stop = workers.stop.clone();
+                let durable_queue = durable_queue.clone();
+                let stats = stats.clone();
+                workers.spawn("bhatho-writer", move || {
+                    RocksDb::write_to_db(
+                        config_clone,
+                        db_clone,
+                        rx,
+                        shutdown,
+                        stop,
+                        durable_queue,
+                        stats,
+                    );
+                })?;
             }
         }
 
-        Ok(RocksDb {
+        let rocks_db = RocksDb {
             enabled: config.enabled,
             db,
             sender: tx,
             config: config.clone(),
+            durable_queue,
+            stats,
+            workers: Some(workers),
+            backup_running: Arc::new(AtomicBool::new(false)),
+            restored_from_backup,
+            cipher: None,
+            drop_prefixes,
+        };
+        rocks_db.start_backup_scheduler(shutdown)?;
+        Ok(rocks_db)
+    }
+
+    /// start a background thread taking a backup every auto_backup_interval_secs
+    /// and purging all but the latest num_backups_to_keep backups
+    fn start_backup_scheduler(&self, shutdown: Arc<AtomicBool>) -> Result<(), String> {
+        let config = &self.config;
+        let workers = match self.workers.as_ref() {
+            Some(workers) => workers,
+            None => return Ok(()),
+        };
+        if config.auto_backup_interval_secs == 0
+            || !config.backup_enabled
+            || !config.enabled
+            || config.open_read_only
+        {
+            debug!("Backup scheduler not enabled for DB Path: {}", config.db_path);
+            return Ok(());
+        }
+        let mut db = self.clone();
+        db.workers = None;
+        let stop = workers.stop.clone();
+        let interval = Duration::from_secs(config.auto_backup_interval_secs);
+        workers.spawn("bhatho-backup", move || {
+            let mut last_backup = Instant::now();
+            loop {
+                //check the shutdown often so the db is released promptly
+                thread::sleep(Duration::from_millis(100));
+                if shutdown.load(Ordering::SeqCst) || stop.load(Ordering::SeqCst) {
+                    info!("Shutdown received. Exiting backup scheduler");
+                    return;
+                }
+                if last_backup.elapsed() < interval {
+                    continue;
+                }
+                last_backup = Instant::now();
+                if let Err(e) = db.backup_db() {
+                    error!(
+                        "Scheduled backup failed for DB Path: {}. Error:{}",
+                        db.config.db_path, e
+                    );
+                    continue;
+                }
+                if let Err(e) = db.purge_old_backup(db.config.num_backups_to_keep) {
+                    error!("Failed to purge old backups. Error:{}", e);
+                }
+            }
         })
     }
 
@@ -276,10 +880,10 @@ impl RocksDb {
             return Ok(None);
         }
         debug!("Get from db");
-        match self.db.get(key) {
+        match self.handle()?.get(key) {
             Ok(Some(value)) => {
                 debug!("Got value found from db");
-                Ok(Some(value.to_vec()))
+                Ok(Some(self.decrypt(&value)?))
             }
             Ok(None) => {
                 debug!("Get value not found from db");
@@ -292,126 +896,885 @@ impl RocksDb {
         }
     }
 
+    /// encrypt the values written to the db with a given cipher.
+    /// set before the db is shared, otherwise the clones write plaintext
+    pub(crate) fn set_cipher(&mut self, cipher: Arc<dyn ValueCodec>) {
+        self.cipher = Some(cipher);
+    }
+
+    /// value as written to the db
+    #[inline(always)]
+    fn encrypt<'a>(&self, val: &'a [u8]) -> Result<Cow<'a, [u8]>, String> {
+        match self.cipher.as_ref() {
+            Some(cipher) => Ok(Cow::Owned(cipher.encode(val)?)),
+            None => Ok(Cow::Borrowed(val)),
+        }
+    }
+
+    /// value as read from the db
+    #[inline(always)]
+    fn decrypt(&self, stored: &[u8]) -> Result<Vec<u8>, String> {
+        match self.cipher.as_ref() {
+            Some(cipher) => cipher.decode(stored),
+            None => Ok(stored.to_vec()),
+        }
+    }
+
+    /// current db handle
+    #[inline(always)]
+    fn handle(&self) -> Result<Arc<rocks_db>, String> {
+        match self.db.read().as_ref() {
+            Some(db) => Ok(db.clone()),
+            None => Err(format!("db is closed: {}", self.config.db_path)),
+        }
+    }
+
+    /// close and reopen the db in place. The async write queue is preserved.
+    /// reads and writes block while the db is reopened and fail if reopen fails.
+    /// it waits up to 5 seconds for in-flight operations and iterators to release the db
+    pub fn reopen(&self) -> Result<(), String> {
+        if !self.enabled {
+            debug!("DB not enabled for DB Path: {}", self.config.db_path);
+            return Ok(());
+        }
+        let mut guard = self.db.write();
+        if let Some(mut db) = guard.take() {
+            let start = Instant::now();
+            loop {
+                db = match Arc::try_unwrap(db) {
+                    Ok(db) => {
+                        //close the db before opening it again
+                        drop(db);
+                        break;
+                    }
+                    Err(db) => db,
+                };
+                if start.elapsed() > REOPEN_WAIT {
+                    error!("DB Path: {} is still in use. Not reopening", self.config.db_path);
+                    *guard = Some(db);
+                    return Err(format!("db is in use: {}", self.config.db_path));
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        info!("Reopening DB Path: {}", self.config.db_path);
+        let db = RocksDb::init_rocks_db(&self.config, &self.drop_prefixes)?;
+        *guard = Some(Arc::new(db));
+        Ok(())
+    }
+
+    /// open the db at new_db_path and swap it in place of the current db.
+    /// a wal_dir under db_path is looked up under new_db_path.
+    /// the old db is closed once in-flight operations and iterators release it.
+    /// writes still in the async write queue go to the new db.
+    /// reopen and backups keep using the configured db_path
+    pub fn swap_db(&self, new_db_path: &str) -> Result<(), String> {
+        if !self.enabled {
+            debug!("DB not enabled for DB Path: {}", self.config.db_path);
+            return Ok(());
+        }
+        let mut config = self.config.clone();
+        config.db_path = new_db_path.to_string();
+        if self.config.wal_dir.starts_with(&self.config.db_path) {
+            config.wal_dir = self.config.wal_dir.replacen(&self.config.db_path, new_db_path, 1);
+        }
+        info!("Swapping DB Path: {} with {}", self.config.db_path, new_db_path);
+        let db = RocksDb::init_rocks_db(&config, &self.drop_prefixes)?;
+        let old = self.db.write().replace(Arc::new(db));
+        drop(old);
+        Ok(())
+    }
+
+    /// true if the db was restored from a backup at startup
+    #[inline]
+    pub fn restored_from_backup(&self) -> bool {
+        self.restored_from_backup
+    }
+
+    /// error if the db is opened read-only
+    #[inline(always)]
+    fn check_writable(&self) -> Result<(), String> {
+        if self.config.open_read_only {
+            return Err("db opened read-only".to_string());
+        }
+        Ok(())
+    }
+
     #[inline]
     pub fn put(&self, key: &[u8], val: &[u8]) -> Result<(), String> {
         if !self.enabled {
             debug!("DB not enabled for DB Path: {}", self.config.db_path);
             return Ok(());
         }
+        self.check_writable()?;
+        self.config.validate_size(&key, &val)?;
         debug!("Put to db");
         if self.config.async_write {
             debug!("Put async to db");
-            self.put_async(&key, &val)
+            self.put_async(&key, &val).map_err(String::from)
         } else {
-            match self.db.put(key, val) {
+            match self.handle()?.put(key, self.encrypt(val)?) {
                 Ok(_) => Ok(()),
                 Err(e) => Err(e.to_string()),
             }
         }
     }
 
+    /// put the key val pair synchronously, bypassing the async writer
+    #[inline]
+    pub fn put_sync(&self, key: &[u8], val: &[u8]) -> Result<(), String> {
+        if !self.enabled {
+            debug!("DB not enabled for DB Path: {}", self.config.db_path);
+            return Ok(());
+        }
+        self.check_writable()?;
+        self.config.validate_size(&key, &val)?;
+        match self.handle()?.put(key, self.encrypt(val)?) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
     #[inline]
     pub fn put_key_val(&self, key_val: &KeyVal) -> Result<(), String> {
         if !self.enabled {
             debug!("DB not enabled for DB Path: {}", self.config.db_path);
             return Ok(());
         }
+        self.check_writable()?;
+        self.config.validate_size(&key_val.key, &key_val.val)?;
         debug!("Put put_key_val to db");
         if self.config.async_write {
             debug!("Put put_key_val async to db");
-            self.put_key_val_async(key_val)
+            self.put_key_val_async(key_val).map_err(String::from)
         } else {
-            match self.db.put(&key_val.key, &key_val.val) {
+            match self.handle()?.put(&key_val.key, self.encrypt(&key_val.val)?) {
                 Ok(_) => Ok(()),
                 Err(e) => Err(e.to_string()),
             }
         }
     }
 
+    /// put the key val pair synchronously with per call write options.
+    /// sync forces the write to be flushed to disk before returning
     #[inline]
-    fn put_key_val_async(&self, key_val: &KeyVal) -> Result<(), String> {
+    pub fn put_with_opts(&self, kv: &KeyVal, sync: bool, disable_wal: bool) -> Result<(), String> {
         if !self.enabled {
             debug!("DB not enabled for DB Path: {}", self.config.db_path);
             return Ok(());
         }
-        match self.sender.send(key_val.clone()) {
+        self.check_writable()?;
+        self.config.validate_size(&kv.key, &kv.val)?;
+        let mut write_opts = WriteOptions::default();
+        write_opts.set_sync(sync);
+        write_opts.disable_wal(disable_wal);
+        let mut batch = WriteBatch::default();
+        if let Err(e) = batch.put(&kv.key, self.encrypt(&kv.val)?) {
+            return Err(e.to_string());
+        }
+        match self.handle()?.write_opt(batch, &write_opts) {
             Ok(_) => Ok(()),
             Err(e) => Err(e.to_string()),
         }
     }
 
-    #[inline]
-    fn put_async(&self, key: &[u8], val: &[u8]) -> Result<(), String> {
+    /// put all the key val pairs as a single batch write, bypassing the async write queue
+    pub fn batch_put(&self, data: &[KeyVal]) -> Result<(), String> {
         if !self.enabled {
             debug!("DB not enabled for DB Path: {}", self.config.db_path);
             return Ok(());
         }
-        let key_val = KeyVal::new(&key, &val);
-        match self.sender.send(key_val) {
+        self.check_writable()?;
+        let mut batch = WriteBatch::default();
+        for kv in data.iter() {
+            self.config.validate_size(&kv.key, &kv.val)?;
+            if let Err(e) = batch.put(&kv.key, self.encrypt(&kv.val)?) {
+                return Err(e.to_string());
+            }
+        }
+        let db = self.handle()?;
+        let res = if self.config.disable_wal {
+            db.write_without_wal(batch)
+        } else {
+            db.write(batch)
+        };
+        match res {
             Ok(_) => Ok(()),
-            Err(e) => Err(e.to_string()),
+            Err(e) => {
+                error!("Failed to batch write to RocksDB. Error:{:?}", e);
+                Err(e.to_string())
+            }
         }
     }
 
-    #[inline]
-    pub fn delete(&self, key: &[u8]) -> Result<(), String> {
+    /// write the puts and deletes (None value) as a single atomic batch
+    pub fn write_all(&self, writes: &HashMap<Vec<u8>, Option<Vec<u8>>>) -> Result<(), String> {
         if !self.enabled {
             debug!("DB not enabled for DB Path: {}", self.config.db_path);
             return Ok(());
         }
-        match self.db.delete(key) {
+        self.check_writable()?;
+        let mut batch = WriteBatch::default();
+        for (key, val) in writes.iter() {
+            let res = match val {
+                Some(val) => batch.put(&key, self.encrypt(val)?),
+                None => batch.delete(&key),
+            };
+            if let Err(e) = res {
+                return Err(e.to_string());
+            }
+        }
+        match self.handle()?.write(batch) {
             Ok(_) => Ok(()),
-            Err(e) => Err(e.to_string()),
+            Err(e) => {
+                error!("Failed to write batch to RocksDB. Error:{:?}", e);
+                Err(e.to_string())
+            }
         }
     }
 
-    pub fn backup_db(&self) -> Result<(), String> {
+    /// delete all the keys as a single batch write
+    pub fn batch_delete(&self, data: &[KeyVal]) -> Result<(), String> {
         if !self.enabled {
             debug!("DB not enabled for DB Path: {}", self.config.db_path);
             return Ok(());
         }
-        if !self.config.backup_enabled {
-            info!("DB backup not enabled for DB Path: {}", self.config.db_path);
-            return Ok(());
-        }
-        if let Ok(mut backup_engine) = RocksDb::create_backup_engine(&self.config) {
-            if let Err(e) = backup_engine.create_new_backup(&self.db) {
-                error!(
-                    "Failed to purge old backups for DB with path: {}. Error:{:?}",
-                    self.config.backup_path, e
-                );
+        self.check_writable()?;
+        let mut batch = WriteBatch::default();
+        for kv in data.iter() {
+            if let Err(e) = batch.delete(&kv.key) {
                 return Err(e.to_string());
             }
-            info!(
-                "Purged old backup for DB Path: {},  Backup Path: {}.",
-                self.config.db_path, self.config.backup_path
-            );
-            Ok(())
-
-            /*let res = self.backup_engine.map(|mut be| {
-                if let Err(e) = be.create_new_backup(&self.db) {
-                    {
-                        error!("Failed to create a new backup using path: {}. Error:{:?}", self.config.backup_path, e);
-                        return Err(e.to_string());
-                    }
-                }else {
-                    info!("Backup completed. DB Path: {},  Backup Path: {}",
-                          self.config.db_path, self.config.backup_path);
-                    return Ok(());
-                }
-            });
-            res.unwrap()*/
-        } else {
-            Err("Backup Engine was not initialized".to_string())
         }
+        if let Err(e) = self.handle()?.write(batch) {
+            error!("Failed to batch delete from RocksDB. Error:{:?}", e);
+            return Err(e.to_string());
+        }
+        if self.config.async_write {
+            debug!("Queue delete tombstones to db");
+            for kv in data.iter() {
+                self.send_async(KeyVal::new_tombstone(&kv.key)).map_err(String::from)?;
+            }
+        }
+        Ok(())
     }
 
-    pub fn purge_old_backup(&self, num_backups_to_keep: usize) -> Result<(), String> {
+    /// put the value for a key into a given column family.
+    /// It is a synchronous write
+    #[inline]
+    pub fn put_cf(&self, cf_name: &str, key: &[u8], val: &[u8]) -> Result<(), String> {
         if !self.enabled {
             debug!("DB not enabled for DB Path: {}", self.config.db_path);
             return Ok(());
         }
-        if !self.config.backup_enabled {
+        self.check_writable()?;
+        let db = self.handle()?;
+        let cf = match db.cf_handle(cf_name) {
+            Some(cf) => cf,
+            None => return Err(format!("column family not found: {}", cf_name)),
+        };
+        match db.put_cf(cf, key, self.encrypt(val)?) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// get the value for a key from a given column family
+    #[inline]
+    pub fn get_cf(&self, cf_name: &str, key: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        if !self.enabled {
+            debug!("DB not enabled for DB Path: {}", self.config.db_path);
+            return Ok(None);
+        }
+        let db = self.handle()?;
+        let cf = match db.cf_handle(cf_name) {
+            Some(cf) => cf,
+            None => return Err(format!("column family not found: {}", cf_name)),
+        };
+        match db.get_cf(cf, key) {
+            Ok(Some(value)) => Ok(Some(self.decrypt(&value)?)),
+            Ok(None) => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// queue the KeyVal for the async writers. if the writers are gone,
+    /// the KeyVal is returned with the error so the caller can retry or log it
+    #[inline]
+    pub fn put_key_val_async(&self, key_val: &KeyVal) -> Result<(), AsyncWriteError> {
+        if !self.enabled {
+            debug!("DB not enabled for DB Path: {}", self.config.db_path);
+            return Ok(());
+        }
+        match self.cipher.as_ref() {
+            Some(cipher) => {
+                let val = cipher.encode(&key_val.val).map_err(AsyncWriteError::Failed)?;
+                self.send_async(KeyVal::new(&key_val.key, &val))
+            }
+            None => self.send_async(key_val.clone()),
+        }
+    }
+
+    #[inline]
+    pub fn put_async(&self, key: &[u8], val: &[u8]) -> Result<(), AsyncWriteError> {
+        if !self.enabled {
+            debug!("DB not enabled for DB Path: {}", self.config.db_path);
+            return Ok(());
+        }
+        let val = self.encrypt(val).map_err(AsyncWriteError::Failed)?;
+        self.send_async(KeyVal::new(&key, &val))
+    }
+
+    #[inline]
+    fn send_async(&self, key_val: KeyVal) -> Result<(), AsyncWriteError> {
+        let bytes = (key_val.key.len() + key_val.val.len()) as u64;
+        //the KeyVal is sent while the queue file is locked so the writers see the file order
+        let sent = match self.durable_queue.as_ref() {
+            Some(queue) => queue
+                .append_then(key_val, |key_val| self.sender.send(key_val))
+                .map_err(AsyncWriteError::Failed)?,
+            None => self.sender.send(key_val),
+        };
+        match sent {
+            Ok(_) => {
+                self.stats.queued_bytes.fetch_add(bytes, Ordering::SeqCst);
+                Ok(())
+            }
+            Err(e) => {
+                error!("Async write queue disconnected for DB Path: {}", self.config.db_path);
+                Err(AsyncWriteError::Disconnected(e.into_inner()))
+            }
+        }
+    }
+
+    /// merge the value into the key using the configured merge_operator
+    #[inline]
+    pub fn merge(&self, key: &[u8], val: &[u8]) -> Result<(), String> {
+        if !self.enabled {
+            debug!("DB not enabled for DB Path: {}", self.config.db_path);
+            return Ok(());
+        }
+        self.check_writable()?;
+        if self.config.merge_operator.is_empty() {
+            return Err("merge_operator not configured".to_string());
+        }
+        if self.cipher.is_some() {
+            return Err("merge not supported with encrypted values".to_string());
+        }
+        match self.handle()?.merge(key, val) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// delete the key. with async_write, the key is deleted right away and a tombstone
+    /// is queued as well, so a put still in the queue can't bring the key back.
+    /// with multiple async writer threads, the puts and the tombstone may still be reordered
+    #[inline]
+    pub fn delete(&self, key: &[u8]) -> Result<(), String> {
+        if !self.enabled {
+            debug!("DB not enabled for DB Path: {}", self.config.db_path);
+            return Ok(());
+        }
+        self.check_writable()?;
+        if let Err(e) = self.handle()?.delete(key) {
+            return Err(e.to_string());
+        }
+        if self.config.async_write {
+            debug!("Queue delete tombstone to db");
+            self.send_async(KeyVal::new_tombstone(key)).map_err(String::from)?;
+        }
+        Ok(())
+    }
+
+    /// number of pending writes in the async write queue
+    #[inline]
+    pub fn write_queue_len(&self) -> usize {
+        self.sender.len()
+    }
+
+    /// capacity of the async write queue. None if the queue is unbounded
+    #[inline]
+    pub fn write_queue_capacity(&self) -> Option<usize> {
+        self.sender.capacity()
+    }
+
+    /// write and sync the WAL to disk. no-op if WAL is disabled
+    pub fn sync_wal(&self) -> Result<(), String> {
+        if !self.enabled || self.config.disable_wal {
+            debug!("DB or WAL not enabled for DB Path: {}", self.config.db_path);
+            return Ok(());
+        }
+        match self.handle()?.flush_wal(true) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// underlying rocksdb handle. None if the db is closed.
+    /// reopen waits until the returned handle is dropped
+    #[cfg(feature = "raw-access")]
+    pub fn raw_db(&self) -> Option<Arc<rocks_db>> {
+        self.handle().ok()
+    }
+
+    /// check the db responds to a read and the async writers are running
+    pub fn health_check(&self) -> Result<(), String> {
+        if !self.enabled {
+            return Ok(());
+        }
+        if let Err(e) = self.handle()?.get(HEALTH_CHECK_KEY) {
+            return Err(format!("db read failed. Error:{}", e));
+        }
+        if self.config.async_write && !self.config.open_read_only {
+            let timeout = WRITER_HEARTBEAT_TIMEOUT
+                .max(Duration::from_millis(self.config.async_writer_threads_sleep_ms * 10));
+            let last = self.stats.heartbeat.load(Ordering::SeqCst);
+            let elapsed = RocksDb::now_millis().saturating_sub(last);
+            if elapsed > timeout.as_millis() as u64 {
+                return Err(format!("async writers stalled for {} ms", elapsed));
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn now_millis() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0)
+    }
+
+    /// flush the memtables to disk
+    pub fn flush(&self) -> Result<(), String> {
+        if !self.enabled {
+            debug!("DB not enabled for DB Path: {}", self.config.db_path);
+            return Ok(());
+        }
+        match self.handle()?.flush() {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// replace the key prefixes dropped by the compaction filter. keys are not deleted
+    /// right away, they are removed lazily when compactions rewrite their files
+    pub fn set_drop_prefixes(&self, prefixes: Vec<Vec<u8>>) {
+        let mut current = self.drop_prefixes.prefixes.write();
+        self.drop_prefixes.active.store(!prefixes.is_empty(), Ordering::Relaxed);
+        *current = prefixes;
+    }
+
+    /// key prefixes dropped by the compaction filter
+    pub fn drop_prefixes(&self) -> Vec<Vec<u8>> {
+        self.drop_prefixes.prefixes.read().clone()
+    }
+
+    /// compact the whole db, e.g. to drop the keys of the drop prefixes right away.
+    /// it blocks until the compaction is done
+    pub fn compact(&self) -> Result<(), String> {
+        if !self.enabled {
+            debug!("DB not enabled for DB Path: {}", self.config.db_path);
+            return Ok(());
+        }
+        self.check_writable()?;
+        self.handle()?.compact_range(None::<&[u8]>, None::<&[u8]>);
+        Ok(())
+    }
+
+    /// estimated number of keys in the db from rocksdb.estimate-num-keys property
+    pub fn estimated_num_keys(&self) -> u64 {
+        if !self.enabled {
+            debug!("DB not enabled for DB Path: {}", self.config.db_path);
+            return 0;
+        }
+        self.int_property("rocksdb.estimate-num-keys")
+    }
+
+    /// bytes used by the block cache
+    pub fn block_cache_usage(&self) -> u64 {
+        if !self.enabled {
+            return 0;
+        }
+        self.int_property("rocksdb.block-cache-usage")
+    }
+
+    /// bytes used by the active and immutable memtables
+    pub fn memtable_size(&self) -> u64 {
+        if !self.enabled {
+            return 0;
+        }
+        self.int_property("rocksdb.cur-size-all-mem-tables")
+    }
+
+    /// bytes of the KeyVals waiting in the async write queue
+    #[inline]
+    pub fn write_queue_bytes(&self) -> u64 {
+        self.stats.queued_bytes.load(Ordering::SeqCst)
+    }
+
+    /// largest number of KeyVals written by an async writer in a single batch
+    #[inline]
+    pub fn max_write_batch_len(&self) -> u64 {
+        self.stats.max_batch_len.load(Ordering::SeqCst)
+    }
+
+    /// value of an integer db property. 0 if not available
+    fn int_property(&self, name: &str) -> u64 {
+        let db = match self.handle() {
+            Ok(db) => db,
+            Err(_) => return 0,
+        };
+        match db.property_value(name) {
+            Ok(Some(val)) => val.trim().parse::<u64>().unwrap_or(0),
+            Ok(None) => 0,
+            Err(e) => {
+                error!("Failed to get {}. Error:{:?}", name, e);
+                0
+            }
+        }
+    }
+
+    /// number of files and size per level parsed from rocksdb.levelstats property
+    pub fn levels_stats(&self) -> Vec<LevelStat> {
+        if !self.enabled {
+            debug!("DB not enabled for DB Path: {}", self.config.db_path);
+            return vec![];
+        }
+        let db = match self.handle() {
+            Ok(db) => db,
+            Err(_) => return vec![],
+        };
+        let stats = match db.property_value("rocksdb.levelstats") {
+            Ok(Some(stats)) => stats,
+            Ok(None) => return vec![],
+            Err(e) => {
+                error!("Failed to get rocksdb.levelstats. Error:{:?}", e);
+                return vec![];
+            }
+        };
+        //Level Files Size(MB)
+        //--------------------
+        //  0        1        0
+        let mut levels = Vec::new();
+        for line in stats.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 3 {
+                continue;
+            }
+            if let (Ok(level), Ok(num_files), Ok(size_mb)) = (
+                fields[0].parse::<u32>(),
+                fields[1].parse::<u64>(),
+                fields[2].parse::<f64>(),
+            ) {
+                levels.push(LevelStat {
+                    level,
+                    num_files,
+                    size_bytes: (size_mb * 1024.0 * 1024.0) as u64,
+                });
+            }
+        }
+        levels
+    }
+
+    /// read options used for sequential scans.
+    /// read-ahead reduces IO stalls while walking large key ranges
+    #[inline]
+    fn scan_read_options(&self) -> ReadOptions {
+        let mut read_opts = ReadOptions::default();
+        if self.config.scan_readahead_size_mb > 0 {
+            read_opts.set_readahead_size(self.config.scan_readahead_size_mb * 1024 * 1024);
+        }
+        read_opts
+    }
+
+    /// walk the db from a given position until the callback returns false.
+    /// when consistent is set, iterate over a snapshot so concurrent writes are not observed
+    fn scan<F>(&self, mode: IteratorMode, consistent: bool, mut f: F) -> Result<(), String>
+    where
+        F: FnMut(&[u8], &[u8]) -> bool,
+    {
+        let db = self.handle()?;
+        if consistent {
+            let snapshot = db.snapshot();
+            for (key, val) in snapshot.iterator(mode) {
+                if !f(&key, &self.decrypt(&val)?) {
+                    break;
+                }
+            }
+        } else {
+            for (key, val) in db.iterator_opt(mode, &self.scan_read_options()) {
+                if !f(&key, &self.decrypt(&val)?) {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// get all the key-val pairs from the start of the db
+    pub fn iter(&self, consistent: bool) -> Result<Vec<(Vec<u8>, Vec<u8>)>, String> {
+        if !self.enabled {
+            debug!("DB not enabled for DB Path: {}", self.config.db_path);
+            return Ok(vec![]);
+        }
+        let mut result = Vec::new();
+        self.scan(IteratorMode::Start, consistent, |key, val| {
+            result.push((key.to_vec(), val.to_vec()));
+            true
+        })?;
+        Ok(result)
+    }
+
+    /// lazily iterate over all the key-val pairs from the start of the db.
+    /// reopen waits for the iterator to be dropped
+    pub fn iter_all(&self) -> Result<RocksDbIterator, String> {
+        let db = self.handle()?;
+        Ok(RocksDbIterator {
+            iter: db.iterator_opt(IteratorMode::Start, &self.scan_read_options()),
+            _db: db,
+            cipher: self.cipher.clone(),
+        })
+    }
+
+    /// invoke the callback for each key-val pair where key starts with a given prefix.
+    /// scan stops when the callback returns false
+    pub fn scan_prefix_with<F>(&self, prefix: &[u8], consistent: bool, mut f: F) -> Result<(), String>
+    where
+        F: FnMut(&[u8], &[u8]) -> bool,
+    {
+        if !self.enabled {
+            debug!("DB not enabled for DB Path: {}", self.config.db_path);
+            return Ok(());
+        }
+        self.scan(
+            IteratorMode::From(prefix, Direction::Forward),
+            consistent,
+            |key, val| key.starts_with(prefix) && f(key, val),
+        )
+    }
+
+    /// get all the key-val pairs where key starts with a given prefix
+    pub fn scan_prefix(
+        &self,
+        prefix: &[u8],
+        consistent: bool,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, String> {
+        let mut result = Vec::new();
+        self.scan_prefix_with(prefix, consistent, |key, val| {
+            result.push((key.to_vec(), val.to_vec()));
+            true
+        })?;
+        Ok(result)
+    }
+
+    /// number of keys starting with a given prefix. keys and values are not copied
+    pub fn count_prefix(&self, prefix: &[u8]) -> Result<u64, String> {
+        let mut count = 0u64;
+        self.scan_prefix_with(prefix, false, |_, _| {
+            count += 1;
+            true
+        })?;
+        Ok(count)
+    }
+
+    /// get up to limit key-val pairs with start <= key < end. None start/end is unbounded.
+    /// forward scans seek to start, reverse scans seek to end and return keys in descending order
+    pub fn scan_range(
+        &self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        limit: usize,
+        reverse: bool,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, String> {
+        if !self.enabled {
+            debug!("DB not enabled for DB Path: {}", self.config.db_path);
+            return Ok(vec![]);
+        }
+        let mut result = Vec::new();
+        if limit == 0 {
+            return Ok(result);
+        }
+        let mode = match (reverse, start, end) {
+            (false, Some(start), _) => IteratorMode::From(start, Direction::Forward),
+            (false, None, _) => IteratorMode::Start,
+            (true, _, Some(end)) => IteratorMode::From(end, Direction::Reverse),
+            (true, _, None) => IteratorMode::End,
+        };
+        self.scan(mode, false, |key, val| {
+            if let Some(end) = end {
+                if key >= end {
+                    //forward scan is past the end, reverse scan seeks at or before the end
+                    return reverse;
+                }
+            }
+            if let Some(start) = start {
+                if key < start {
+                    return false;
+                }
+            }
+            result.push((key.to_vec(), val.to_vec()));
+            result.len() < limit
+        })?;
+        Ok(result)
+    }
+
+    pub fn backup_db(&self) -> Result<(), String> {
+        if !self.enabled {
+            debug!("DB not enabled for DB Path: {}", self.config.db_path);
+            return Ok(());
+        }
+        self.check_writable()?;
+        if !self.config.backup_enabled {
+            info!("DB backup not enabled for DB Path: {}", self.config.db_path);
+            return Ok(());
+        }
+        //only one backup at a time
+        if self
+            .backup_running
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            return Err("backup already in progress".to_string());
+        }
+        let res = self.create_backup();
+        self.backup_running.store(false, Ordering::SeqCst);
+        res
+    }
+
+    fn create_backup(&self) -> Result<(), String> {
+        if let Ok(mut backup_engine) = RocksDb::create_backup_engine(&self.config) {
+            if let Err(e) = backup_engine.create_new_backup(&*self.handle()?) {
+                error!(
+                    "Failed to purge old backups for DB with path: {}. Error:{:?}",
+                    self.config.backup_path, e
+                );
+                return Err(e.to_string());
+            }
+            info!(
+                "Purged old backup for DB Path: {},  Backup Path: {}.",
+                self.config.db_path, self.config.backup_path
+            );
+            if self.config.verify_backup_on_create {
+                return self.verify_backup();
+            }
+            Ok(())
+
+            /*let res = self.backup_engine.map(|mut be| {
+                if let Err(e) = be.create_new_backup(&self.db) {
+                    {
+                        error!("Failed to create a new backup using path: {}. Error:{:?}", self.config.backup_path, e);
+                        return Err(e.to_string());
+                    }
+                }else {
+                    info!("Backup completed. DB Path: {},  Backup Path: {}",
+                          self.config.db_path, self.config.backup_path);
+                    return Ok(());
+                }
+            });
+            res.unwrap()*/
+        } else {
+            Err("Backup Engine was not initialized".to_string())
+        }
+    }
+
+    /// verify the latest backup by restoring it into a scratch path and opening it.
+    /// the backup is deleted if the verification fails
+    pub fn verify_backup(&self) -> Result<(), String> {
+        let verify_path = format!("{}_verify", self.config.backup_path);
+        let _ = fs::remove_dir_all(&verify_path);
+        let res = RocksDb::restore_and_open(&self.config, &verify_path);
+        let _ = fs::remove_dir_all(&verify_path);
+        if let Err(e) = res {
+            error!(
+                "Backup verification failed for Backup Path: {}. Deleting the backup. Error:{}",
+                self.config.backup_path, e
+            );
+            RocksDb::delete_latest_backup(&self.config)?;
+            return Err(format!("backup verification failed: {}", e));
+        }
+        info!(
+            "Verified latest backup for DB Path: {},  Backup Path: {}.",
+            self.config.db_path, self.config.backup_path
+        );
+        Ok(())
+    }
+
+    /// restore the latest backup into a given path and open it with the options and
+    /// column families of the db
+    fn restore_and_open(config: &RocksDbConfig, path: &str) -> Result<(), String> {
+        let mut backup_engine = RocksDb::create_backup_engine(&config)?;
+        let restore_option = rocksdb::backup::RestoreOptions::default();
+        if let Err(e) = backup_engine.restore_from_latest_backup(&path, &path, &restore_option) {
+            return Err(e.to_string());
+        }
+        let opts = RocksDb::create_rocks_db_options(&config)?;
+        let cfs: Vec<&str> = config.column_families.iter().map(|cf| cf.as_str()).collect();
+        let res = if cfs.is_empty() {
+            rocks_db::open(&opts, &path)
+        } else {
+            rocks_db::open_cf(&opts, &path, &cfs)
+        };
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// delete the latest backup. the BackupEngine has no call to delete a single backup,
+    /// so its meta file is removed and the engine purges the files only referenced by it
+    fn delete_latest_backup(config: &RocksDbConfig) -> Result<(), String> {
+        let backup_path = &config.backup_path;
+        let meta_path = format!("{}/meta", backup_path);
+        let entries = match fs::read_dir(&meta_path) {
+            Ok(entries) => entries,
+            Err(e) => return Err(e.to_string()),
+        };
+        let latest = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().to_str().and_then(|n| n.parse::<u32>().ok()))
+            .max();
+        if let Some(id) = latest {
+            if let Err(e) = fs::remove_file(format!("{}/{}", meta_path, id)) {
+                error!("Failed to delete backup: {} in {}. Error:{:?}", id, backup_path, e);
+                return Err(e.to_string());
+            }
+            info!("Deleted backup: {} in {}", id, backup_path);
+        }
+        //opening the engine deletes the files of the removed backup
+        let mut backup_engine = RocksDb::create_backup_engine(&config)?;
+        let num_backups = backup_engine.get_backup_info().len();
+        backup_engine
+            .purge_old_backups(num_backups)
+            .map_err(|e| e.to_string())
+    }
+
+    /// backups available in backup_path, oldest first
+    pub fn list_backups(&self) -> Result<Vec<BackupInfo>, String> {
+        if !self.config.backup_enabled {
+            return Err("backup not enabled".to_string());
+        }
+        let backup_engine = RocksDb::create_backup_engine(&self.config)?;
+        Ok(backup_engine
+            .get_backup_info()
+            .into_iter()
+            .map(|info| BackupInfo {
+                backup_id: info.backup_id,
+                timestamp: info.timestamp,
+                size: info.size,
+                num_files: info.num_files,
+            })
+            .collect())
+    }
+
+    pub fn purge_old_backup(&self, num_backups_to_keep: usize) -> Result<(), String> {
+        if !self.enabled {
+            debug!("DB not enabled for DB Path: {}", self.config.db_path);
+            return Ok(());
+        }
+        self.check_writable()?;
+        if !self.config.backup_enabled {
             info!("DB backup not enabled for DB Path: {}", self.config.db_path);
             return Ok(());
         }
@@ -433,3 +1796,646 @@ impl RocksDb {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::test_util::rocks_db_test_config as test_config;
+
+    #[test]
+    fn test_scan_with_readahead() {
+        //read-ahead is a perf knob, this only checks the scan still returns every key
+        let mut config = test_config("scan_readahead");
+        config.scan_readahead_size_mb = 4;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown).unwrap();
+        let total = 10_000;
+        for i in 0..total {
+            let key = format!("key_{:08}", i);
+            db.put(key.as_bytes(), b"val").unwrap();
+        }
+        assert_eq!(db.iter(false).unwrap().len(), total);
+        assert_eq!(db.scan_prefix(b"key_0000", false).unwrap().len(), total);
+        assert_eq!(db.scan_prefix(b"key_00000", false).unwrap().len(), 1_000);
+    }
+
+    #[test]
+    fn test_consistent_scan_ignores_concurrent_writes() {
+        let config = test_config("consistent_scan");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown).unwrap();
+        for i in 0..100 {
+            let key = format!("scan_{:04}", i);
+            db.put(key.as_bytes(), b"old").unwrap();
+        }
+        let mut seen = 0;
+        let mut writer_done = false;
+        db.scan_prefix_with(b"scan_", true, |_key, val| {
+            if !writer_done {
+                let writer = db.clone();
+                thread::spawn(move || {
+                    for i in 100..200 {
+                        let key = format!("scan_{:04}", i);
+                        writer.put(key.as_bytes(), b"new").unwrap();
+                    }
+                })
+                .join()
+                .unwrap();
+                writer_done = true;
+            }
+            assert_eq!(val, b"old");
+            seen += 1;
+            true
+        })
+        .unwrap();
+        assert_eq!(seen, 100);
+        assert_eq!(db.scan_prefix(b"scan_", true).unwrap().len(), 200);
+    }
+
+    #[test]
+    fn test_bloom_filter_bits_per_key() {
+        let mut config = test_config("bloom_filter_bits");
+        config.use_default_block_config = false;
+        config.bloom_filter = true;
+        config.bloom_filter_bits_per_key = 16;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown.clone()).unwrap();
+        db.put(b"bloom_key", b"bloom_val").unwrap();
+        assert_eq!(db.get(b"bloom_key").unwrap(), Some(b"bloom_val".to_vec()));
+        assert_eq!(db.get(b"bloom_missing").unwrap(), None);
+        drop(db);
+
+        config.bloom_filter_bits_per_key = 0;
+        assert!(RocksDb::new(&config, shutdown).is_err());
+    }
+
+    #[test]
+    fn test_max_write_batch_size() {
+        let mut config = test_config("max_write_batch_size");
+        config.async_write = true;
+        config.max_write_batch_size = 100;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown).unwrap();
+        let total = 10_000;
+        for i in 0..total {
+            let key = format!("batch_{:06}", i);
+            db.put(key.as_bytes(), b"val").unwrap();
+        }
+        while db.write_queue_bytes() > 0 {
+            thread::sleep(Duration::from_millis(config.async_writer_threads_sleep_ms));
+        }
+        //last drained batch may still be in flight
+        thread::sleep(Duration::from_millis(config.async_writer_threads_sleep_ms * 2));
+        assert!(db.max_write_batch_len() > 0);
+        assert!(db.max_write_batch_len() <= 100);
+        assert_eq!(db.scan_prefix(b"batch_", false).unwrap().len(), total);
+    }
+
+    #[test]
+    fn test_async_send_error_returns_key_val() {
+        let mut config = test_config("async_send_error");
+        config.async_write = true;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown.clone()).unwrap();
+        shutdown.store(true, Ordering::SeqCst);
+        //writer threads exit on shutdown and drop the receiver
+        thread::sleep(Duration::from_millis(config.async_writer_threads_sleep_ms * 2));
+
+        let kv = KeyVal::new(b"lost_key", b"lost_val");
+        match db.put_key_val_async(&kv) {
+            Err(AsyncWriteError::Disconnected(unsent)) => {
+                assert_eq!(unsent.key, b"lost_key".to_vec());
+                assert_eq!(unsent.val, b"lost_val".to_vec());
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(db.put(b"lost_key", b"lost_val").unwrap_err().contains("lost_key"));
+    }
+
+    #[test]
+    fn test_put_with_opts_sync_survives_reopen() {
+        let mut config = test_config("put_with_opts");
+        config.async_write = true;
+        config.disable_wal = true;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown.clone()).unwrap();
+        let kv = KeyVal::new(b"durable_key", b"durable_val");
+        db.put_with_opts(&kv, true, false).unwrap();
+        shutdown.store(true, Ordering::SeqCst);
+        drop(db);
+        //let the writer thread observe the shutdown and release the db
+        thread::sleep(Duration::from_millis(config.async_writer_threads_sleep_ms * 2));
+
+        let db = RocksDb::new(&config, Arc::new(AtomicBool::new(false))).unwrap();
+        assert_eq!(db.get(b"durable_key").unwrap(), Some(b"durable_val".to_vec()));
+    }
+
+    #[test]
+    fn test_async_writer_adaptive_sleep() {
+        let mut config = test_config("adaptive_sleep");
+        config.async_write = true;
+        config.num_async_writer_threads = 1;
+        config.async_writer_threads_sleep_ms = 1_000;
+        config.async_writer_min_sleep_ms = 1;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown.clone()).unwrap();
+        //short idle period, the writer backoff is still well below the cap
+        thread::sleep(Duration::from_millis(30));
+        let start = Instant::now();
+        db.put(b"adaptive_key", b"val").unwrap();
+        while db.get(b"adaptive_key").unwrap().is_none() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(1));
+        }
+        //a fixed sleep flushes it after up to async_writer_threads_sleep_ms
+        assert!(start.elapsed() < Duration::from_millis(config.async_writer_threads_sleep_ms / 2));
+        shutdown.store(true, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_write_queue_len() {
+        let mut config = test_config("write_queue_len");
+        config.async_write = true;
+        config.async_writer_threads_sleep_ms = 5_000;
+        config.async_writer_min_sleep_ms = 5_000;
+        config.async_write_queue_length = 100;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown.clone()).unwrap();
+        //let the writer find the queue empty and go to sleep
+        thread::sleep(Duration::from_millis(100));
+        for i in 0..10 {
+            let key = format!("queued_{}", i);
+            db.put(key.as_bytes(), b"val").unwrap();
+        }
+        assert!(db.write_queue_len() > 0);
+        assert_eq!(db.write_queue_capacity(), Some(100));
+        shutdown.store(true, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_async_delete_after_queued_put() {
+        let mut config = test_config("async_delete");
+        config.async_write = true;
+        config.async_writer_threads_sleep_ms = 200;
+        config.async_writer_min_sleep_ms = 200;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown.clone()).unwrap();
+        //let the writer find the queue empty and go to sleep, so the put is still queued
+        thread::sleep(Duration::from_millis(50));
+        db.put(b"tombstone_key", b"val").unwrap();
+        db.delete(b"tombstone_key").unwrap();
+        assert_eq!(db.write_queue_len(), 2);
+        while db.write_queue_len() > 0 {
+            thread::sleep(Duration::from_millis(50));
+        }
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(db.get(b"tombstone_key").unwrap(), None);
+        shutdown.store(true, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_async_write_unbounded() {
+        let mut config = test_config("async_write_unbounded");
+        config.async_write = true;
+        config.async_write_unbounded = true;
+        config.async_write_queue_length = 10;
+        config.async_writer_threads_sleep_ms = 5_000;
+        config.async_writer_min_sleep_ms = 5_000;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown.clone()).unwrap();
+        //let the writer find the queue empty and go to sleep
+        thread::sleep(Duration::from_millis(100));
+        let start = Instant::now();
+        for i in 0..1_000 {
+            let key = format!("unbounded_{}", i);
+            db.put(key.as_bytes(), b"val").unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(db.write_queue_len(), 1_000);
+        assert_eq!(db.write_queue_capacity(), None);
+        shutdown.store(true, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_levels_stats() {
+        let config = test_config("levels_stats");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown).unwrap();
+        for i in 0..1_000 {
+            let key = format!("level_key_{}", i);
+            db.put(key.as_bytes(), b"level_val").unwrap();
+        }
+        db.flush().unwrap();
+        let levels = db.levels_stats();
+        assert!(!levels.is_empty());
+        assert_eq!(levels[0].level, 0);
+        assert!(levels[0].num_files > 0);
+    }
+
+    #[test]
+    fn test_max_key_and_value_size() {
+        let mut config = test_config("max_key_value_size");
+        config.max_key_size = 8;
+        config.max_value_size = 16;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown).unwrap();
+        assert_eq!(
+            db.put(b"key", &[b'v'; 17]),
+            Err("value exceeds max_value_size".to_string())
+        );
+        assert_eq!(
+            db.put(b"too_long_key", b"val"),
+            Err("key exceeds max_key_size".to_string())
+        );
+        assert_eq!(db.get(b"key").unwrap(), None);
+
+        assert!(db.put(b"key_at_8", &[b'v'; 16]).is_ok());
+        assert_eq!(db.get(b"key_at_8").unwrap(), Some(vec![b'v'; 16]));
+    }
+
+    #[test]
+    fn test_durable_async_queue_recovery() {
+        let mut config = test_config("durable_async_queue");
+        config.async_write = true;
+        config.durable_async_queue = true;
+        //no writer thread, so queued writes are never drained. simulates a crash
+        config.num_async_writer_threads = 0;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown.clone()).unwrap();
+        for i in 0..100 {
+            let key = format!("queued_key_{}", i);
+            db.put(key.as_bytes(), b"queued_val").unwrap();
+        }
+        assert_eq!(db.get(b"queued_key_0").unwrap(), None);
+        drop(db);
+
+        config.num_async_writer_threads = 1;
+        let db = RocksDb::new(&config, shutdown.clone()).unwrap();
+        for i in 0..100 {
+            let key = format!("queued_key_{}", i);
+            assert_eq!(db.get(key.as_bytes()).unwrap(), Some(b"queued_val".to_vec()));
+        }
+
+        //the queue file is cut once the writer caught up
+        for i in 0..1000 {
+            let key = format!("applied_key_{}", i);
+            db.put(key.as_bytes(), b"applied_val").unwrap();
+        }
+        let path = RocksDb::durable_queue_path(&config);
+        let mut len = fs::metadata(&path).unwrap().len();
+        for _ in 0..100 {
+            if len == 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+            len = fs::metadata(&path).unwrap().len();
+        }
+        assert_eq!(len, 0);
+        assert_eq!(db.get(b"applied_key_999").unwrap(), Some(b"applied_val".to_vec()));
+        shutdown.store(true, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_durable_async_queue_requires_wal() {
+        let mut config = test_config("durable_async_queue_no_wal");
+        config.async_write = true;
+        config.durable_async_queue = true;
+        config.disable_wal = true;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        assert!(RocksDb::new(&config, shutdown).is_err());
+    }
+
+    #[test]
+    fn test_durable_async_queue_out_of_order_apply() {
+        let path = format!("{}/async_queue.log", crate::test_util::test_dir("queue_apply_order"));
+        let queue = DurableQueue::open(&path, 0).unwrap();
+        for i in 0..4 {
+            let key = format!("order_key_{}", i);
+            queue.append_then(KeyVal::new(key.as_bytes(), b"val"), |_| ()).unwrap();
+        }
+        //two writers took two records each, the second one finishes first
+        let (_, first) = queue.receive(|| vec![KeyVal::new(b"", b""); 2]);
+        let (_, second) = queue.receive(|| vec![KeyVal::new(b"", b""); 2]);
+        assert_eq!((first.clone(), second.clone()), (0..2, 2..4));
+        queue.mark_applied(second, || Ok(()));
+        assert_eq!(DurableQueue::recover(&path).unwrap().len(), 4);
+
+        queue.mark_applied(first, || Ok(()));
+        assert!(DurableQueue::recover(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_verify_backup_on_create() {
+        let mut config = test_config("verify_backup");
+        let _ = fs::remove_dir_all(&config.backup_path);
+        config.verify_backup_on_create = true;
+        //the restored backup is opened with the column families of the db
+        config.column_families = vec!["backup_cf".to_string()];
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown).unwrap();
+        for i in 0..1_000 {
+            let key = format!("backup_key_{}", i);
+            db.put(key.as_bytes(), b"val").unwrap();
+        }
+        db.backup_db().unwrap();
+
+        //corrupt every file of the backup
+        for dir in &["private", "shared", "shared_checksum"] {
+            let path = format!("{}/{}", config.backup_path, dir);
+            for entry in walk_files(&path) {
+                fs::write(&entry, b"corrupt").unwrap();
+            }
+        }
+        assert!(db.verify_backup().is_err());
+        assert!(db.list_backups().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_list_backups() {
+        let config = test_config("list_backups");
+        let _ = fs::remove_dir_all(&config.backup_path);
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown).unwrap();
+        assert!(db.list_backups().unwrap().is_empty());
+        db.put(b"backup_key_1", b"val").unwrap();
+        db.backup_db().unwrap();
+        db.put(b"backup_key_2", b"val").unwrap();
+        db.backup_db().unwrap();
+
+        let backups = db.list_backups().unwrap();
+        assert_eq!(backups.len(), 2);
+        assert!(backups[0].backup_id < backups[1].backup_id);
+        assert!(backups.iter().all(|info| info.num_files > 0 && info.size > 0));
+
+        let mut config = test_config("list_backups_disabled");
+        config.backup_enabled = false;
+        let db = RocksDb::new(&config, Arc::new(AtomicBool::new(false))).unwrap();
+        assert_eq!(db.list_backups().unwrap_err(), "backup not enabled");
+    }
+
+    #[test]
+    fn test_auto_backup_scheduler() {
+        let mut config = test_config("auto_backup");
+        let _ = fs::remove_dir_all(&config.backup_path);
+        config.auto_backup_interval_secs = 1;
+        config.num_backups_to_keep = 2;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown.clone()).unwrap();
+        db.put(b"backup_key", b"val").unwrap();
+        thread::sleep(Duration::from_millis(4_500));
+        shutdown.store(true, Ordering::SeqCst);
+
+        let meta = format!("{}/meta", config.backup_path);
+        let mut ids: Vec<u32> = fs::read_dir(&meta)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().parse().unwrap())
+            .collect();
+        ids.sort();
+        //at least 3 backups were taken and only the last 2 are kept
+        assert_eq!(ids.len(), 2);
+        assert!(ids[0] > 1);
+    }
+
+    #[test]
+    fn test_open_locked_db() {
+        let mut config = test_config("open_locked");
+        config.open_retry_count = 2;
+        config.open_retry_sleep_ms = 10;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let _db = RocksDb::new(&config, shutdown.clone()).unwrap();
+        match RocksDb::new(&config, shutdown) {
+            Err(e) => assert_eq!(
+                e,
+                format!("db_path already locked by another process: {}", config.db_path)
+            ),
+            Ok(_) => panic!("expected the second open to fail on the db lock"),
+        }
+    }
+
+    #[test]
+    fn test_open_read_only() {
+        let config = test_config("open_read_only");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown.clone()).unwrap();
+        db.put(b"read_only_key", b"val").unwrap();
+        db.flush().unwrap();
+
+        let mut read_only_config = config.clone();
+        read_only_config.open_read_only = true;
+        let read_only_db = RocksDb::new(&read_only_config, shutdown).unwrap();
+        assert_eq!(read_only_db.get(b"read_only_key").unwrap(), Some(b"val".to_vec()));
+        assert_eq!(
+            read_only_db.put(b"read_only_key", b"new_val"),
+            Err("db opened read-only".to_string())
+        );
+        assert!(read_only_db.delete(b"read_only_key").is_err());
+        assert!(read_only_db.backup_db().is_err());
+    }
+
+    #[test]
+    fn test_binary_search_index_type() {
+        let mut config = test_config("binary_search_index");
+        config.use_default_block_config = false;
+        config.lru_cache_size_mb = 8;
+        config.index_type = "binary_search".to_string();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown).unwrap();
+        for i in 0..1_000 {
+            let key = format!("index_{:04}", i);
+            db.put(key.as_bytes(), b"val").unwrap();
+        }
+        db.flush().unwrap();
+        assert_eq!(db.get(b"index_0042").unwrap(), Some(b"val".to_vec()));
+        assert_eq!(db.scan_prefix(b"index_00", false).unwrap().len(), 100);
+    }
+
+    #[test]
+    fn test_wal_size_limit_and_ttl() {
+        let mut config = test_config("wal_limits");
+        config.wal_size_limit_mb = 64;
+        config.wal_ttl_secs = 3600;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown).unwrap();
+        db.put(b"wal_key", b"val").unwrap();
+        assert_eq!(db.get(b"wal_key").unwrap(), Some(b"val".to_vec()));
+    }
+
+    #[test]
+    fn test_wal_recovery_mode() {
+        let mut config = test_config("wal_recovery_mode");
+        config.wal_recovery_mode = "point_in_time".to_string();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown.clone()).unwrap();
+        db.put(b"recovery_key", b"val").unwrap();
+        drop(db);
+
+        let db = RocksDb::new(&config, shutdown.clone()).unwrap();
+        assert_eq!(db.get(b"recovery_key").unwrap(), Some(b"val".to_vec()));
+        drop(db);
+
+        config.wal_recovery_mode = "unknown".to_string();
+        assert!(RocksDb::new(&config, shutdown).is_err());
+    }
+
+    #[test]
+    fn test_health_check_detects_stopped_writers() {
+        let mut config = test_config("writer_health_check");
+        config.async_write = true;
+        config.async_writer_threads_sleep_ms = 10;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown.clone()).unwrap();
+        assert!(db.health_check().is_ok());
+
+        shutdown.store(true, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(config.async_writer_threads_sleep_ms * 5));
+        //writers have exited, age the last heartbeat past the timeout
+        db.stats.heartbeat.store(0, Ordering::SeqCst);
+        assert!(db.health_check().unwrap_err().contains("stalled"));
+    }
+
+    #[test]
+    fn test_block_cache_shard_bits() {
+        let mut config = test_config("block_cache_shard_bits");
+        config.use_default_block_config = false;
+        config.lru_cache_size_mb = 8;
+        config.block_cache_shard_bits = 8;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown).unwrap();
+        for i in 0..1_000 {
+            let key = format!("shard_bits_{:04}", i);
+            db.put(key.as_bytes(), b"val").unwrap();
+        }
+        db.flush().unwrap();
+        for i in 0..1_000 {
+            let key = format!("shard_bits_{:04}", i);
+            assert_eq!(db.get(key.as_bytes()).unwrap(), Some(b"val".to_vec()));
+        }
+        assert!(db.block_cache_usage() > 0);
+    }
+
+    #[test]
+    fn test_pin_l0_filter_and_index_blocks() {
+        let mut config = test_config("pin_l0_blocks");
+        config.use_default_block_config = false;
+        config.bloom_filter = true;
+        config.pin_l0_filter_and_index_blocks = true;
+        config.cache_index_and_filter_blocks_with_high_priority = true;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown).unwrap();
+        for i in 0..1_000 {
+            let key = format!("pinned_l0_{:04}", i);
+            db.put(key.as_bytes(), b"val").unwrap();
+        }
+        db.flush().unwrap();
+        for i in 0..1_000 {
+            let key = format!("pinned_l0_{:04}", i);
+            assert_eq!(db.get(key.as_bytes()).unwrap(), Some(b"val".to_vec()));
+        }
+        assert_eq!(db.get(b"pinned_l0_missing").unwrap(), None);
+    }
+
+    #[test]
+    fn test_universal_compaction_style() {
+        let mut config = test_config("universal_compaction");
+        config.use_default_config = false;
+        config.compaction_style = "universal".to_string();
+        config.write_buffer_size_mb = 1;
+        config.min_write_buffer_number = 1;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown).unwrap();
+        //each flush writes a level 0 file, enough of them trigger a universal compaction
+        for file in 0..8 {
+            for i in 0..1_000 {
+                let key = format!("universal_{}_{:04}", file, i);
+                db.put(key.as_bytes(), &[b'v'; 128]).unwrap();
+            }
+            db.flush().unwrap();
+        }
+        for file in 0..8 {
+            for i in (0..1_000).step_by(97) {
+                let key = format!("universal_{}_{:04}", file, i);
+                assert_eq!(db.get(key.as_bytes()).unwrap(), Some(vec![b'v'; 128]));
+            }
+        }
+
+        let mut config = test_config("invalid_compaction");
+        config.use_default_config = false;
+        config.compaction_style = "tiered".to_string();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        assert!(RocksDb::new(&config, shutdown).is_err());
+    }
+
+    #[test]
+    fn test_level0_triggers() {
+        let mut config = test_config("level0_triggers");
+        config.use_default_config = false;
+        config.level0_file_num_compaction_trigger = 2;
+        config.level0_slowdown_writes_trigger = 8;
+        config.level0_stop_writes_trigger = 12;
+        config.write_buffer_size_mb = 1;
+        config.min_write_buffer_number = 1;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown).unwrap();
+        //a burst of level 0 files stays under the stop trigger as compactions catch up
+        for file in 0..16 {
+            for i in 0..500 {
+                let key = format!("level0_{}_{:04}", file, i);
+                db.put(key.as_bytes(), &[b'v'; 128]).unwrap();
+            }
+            db.flush().unwrap();
+        }
+        assert_eq!(db.get(b"level0_15_0499").unwrap(), Some(vec![b'v'; 128]));
+    }
+
+    #[test]
+    fn test_memtable_factory() {
+        let mut config = test_config("memtable_vector");
+        config.memtable_factory = "vector".to_string();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown.clone()).unwrap();
+        let data: Vec<KeyVal> = (0..1_000)
+            .map(|i| KeyVal::new(format!("memtable_key_{}", i).as_bytes(), b"val"))
+            .collect();
+        db.batch_put(&data).unwrap();
+        assert_eq!(db.get(b"memtable_key_999").unwrap(), Some(b"val".to_vec()));
+        drop(db);
+
+        let mut config = test_config("memtable_hash_skiplist");
+        config.memtable_factory = "hash_skiplist".to_string();
+        assert!(RocksDb::new(&config, shutdown.clone()).is_err());
+        config.use_default_block_config = false;
+        config.lru_cache_size_mb = 8;
+        let db = RocksDb::new(&config, shutdown).unwrap();
+        db.put(b"memtable_key", b"val").unwrap();
+        assert_eq!(db.get(b"memtable_key").unwrap(), Some(b"val".to_vec()));
+    }
+
+    #[test]
+    fn test_point_lookup_block_size() {
+        let mut config = test_config("point_lookup");
+        config.point_lookup_block_size_mb = 8;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = RocksDb::new(&config, shutdown).unwrap();
+        db.put(b"point_key", b"val").unwrap();
+        db.flush().unwrap();
+        assert_eq!(db.get(b"point_key").unwrap(), Some(b"val".to_vec()));
+        assert_eq!(db.get(b"missing_key").unwrap(), None);
+    }
+
+    fn walk_files(path: &str) -> Vec<String> {
+        let mut files = vec![];
+        if let Ok(entries) = fs::read_dir(path) {
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                let path = entry.path().to_string_lossy().to_string();
+                if entry.path().is_dir() {
+                    files.extend(walk_files(&path));
+                } else {
+                    files.push(path);
+                }
+            }
+        }
+        files
+    }
+}
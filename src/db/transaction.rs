@@ -0,0 +1,121 @@
+/************************************************
+
+   File Name: bhatho:db::transaction
+   Author: Rohit Joshi <rohit.c.joshi@gmail.com>
+   Date: 2019-02-17:15:15
+   License: Apache 2.0
+
+**************************************************/
+use regex::bytes::Regex;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::cache::sharded_cache::ShardedCache;
+use crate::db::codec::ValueCodec;
+use crate::db::db_manager::validate_key;
+use crate::db::rocks_db::RocksDb;
+
+/// Multi-key transaction of a DbManager.
+/// puts and deletes are buffered and written to the db as a single atomic batch on commit.
+/// Transactions bypass the async write path and the cache entries of the touched keys
+/// are invalidated on commit. Reads see the buffered writes of the transaction,
+/// but there is no isolation from concurrent writers
+pub struct Txn {
+    db: Option<Arc<RocksDb>>,
+    cache: Arc<ShardedCache>,
+    //prepended to every key, see DbManagerConfig::key_prefix
+    key_prefix: Vec<u8>,
+    //None value is a delete
+    writes: HashMap<Vec<u8>, Option<Vec<u8>>>,
+    //encodes the values on commit, see DbManager::with_value_codec
+    codec: Option<Arc<dyn ValueCodec>>,
+    //see DbManagerConfig::key_validation_regex
+    key_validator: Option<Regex>,
+}
+
+impl Txn {
+    pub(crate) fn new(
+        db: Option<Arc<RocksDb>>,
+        cache: Arc<ShardedCache>,
+        key_prefix: Vec<u8>,
+        codec: Option<Arc<dyn ValueCodec>>,
+        key_validator: Option<Regex>,
+    ) -> Txn {
+        Txn {
+            db,
+            cache,
+            key_prefix,
+            writes: HashMap::new(),
+            codec,
+            key_validator,
+        }
+    }
+
+    #[inline]
+    fn prefix_key(&self, key: &[u8]) -> Vec<u8> {
+        let mut prefixed = Vec::with_capacity(self.key_prefix.len() + key.len());
+        prefixed.extend_from_slice(&self.key_prefix);
+        prefixed.extend_from_slice(key);
+        prefixed
+    }
+
+    /// get the value written in this transaction, else from the db
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        let key = self.prefix_key(key);
+        if let Some(val) = self.writes.get(&key) {
+            return Ok(val.clone());
+        }
+        let stored = match self.db.as_ref() {
+            Some(db) => db.get(&key)?,
+            None => return Ok(None),
+        };
+        match (self.codec.as_ref(), stored) {
+            (Some(codec), Some(val)) => Ok(Some(codec.decode(&val)?)),
+            (_, stored) => Ok(stored),
+        }
+    }
+
+    pub fn put(&mut self, key: &[u8], val: &[u8]) -> Result<(), String> {
+        validate_key(self.key_validator.as_ref(), key)?;
+        if let Some(db) = self.db.as_ref() {
+            db.config.validate_size(&key, &val)?;
+        }
+        let key = self.prefix_key(key);
+        self.writes.insert(key, Some(val.to_vec()));
+        Ok(())
+    }
+
+    pub fn delete(&mut self, key: &[u8]) {
+        let key = self.prefix_key(key);
+        self.writes.insert(key, None);
+    }
+
+    /// write all the puts and deletes to the db atomically
+    pub fn commit(self) -> Result<(), String> {
+        if let Some(db) = self.db.as_ref() {
+            match self.codec.as_ref() {
+                Some(codec) => {
+                    let mut encoded = HashMap::with_capacity(self.writes.len());
+                    for (key, val) in self.writes.iter() {
+                        let val = match val {
+                            Some(val) => Some(codec.encode(val)?),
+                            None => None,
+                        };
+                        encoded.insert(key.clone(), val);
+                    }
+                    db.write_all(&encoded)?;
+                }
+                None => db.write_all(&self.writes)?,
+            }
+        }
+        for key in self.writes.keys() {
+            let _ = self.cache.delete(&key);
+        }
+        Ok(())
+    }
+
+    /// discard all the puts and deletes
+    pub fn rollback(self) {
+        debug!("Rolling back transaction with {} writes", self.writes.len());
+    }
+}
@@ -20,17 +20,25 @@ pub struct KeyVal {
     pub db_name: Vec<u8>,
     pub skip_db: bool,
     pub skip_cache: bool,
+    /// cache ttl of this entry overriding the ttl_secs of the cache config
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+    /// a delete queued for the async writers in order with the puts
+    #[serde(default)]
+    pub deleted: bool,
 }
 
 impl fmt::Debug for KeyVal {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "KeyVal {{ hash: {}, key: {}, val:{}, db_name:{}, skip_db:{}, skip_cache:{} }}",
+        write!(f, "KeyVal {{ hash: {}, key: {}, val:{}, db_name:{}, skip_db:{}, skip_cache:{}, ttl_secs:{:?}, deleted:{} }}",
                self.hash,
                String::from_utf8_lossy(&self.key),
                String::from_utf8_lossy(&self.val),
                String::from_utf8_lossy(&self.db_name),
                self.skip_db,
-               self.skip_cache
+               self.skip_cache,
+               self.ttl_secs,
+               self.deleted
         )
     }
 }
@@ -44,6 +52,8 @@ impl Clone for KeyVal {
             db_name: self.db_name.clone(),
             skip_db: self.skip_db,
             skip_cache: self.skip_cache,
+            ttl_secs: self.ttl_secs,
+            deleted: self.deleted,
         }
     }
 }
@@ -59,10 +69,28 @@ impl KeyVal {
             db_name: vec![],
             skip_db: false,
             skip_cache: false,
+            ttl_secs: None,
+            deleted: false,
         }
     }
 
 
+    /// KeyVal cached for ttl_secs regardless of the ttl of the cache config
+    #[inline]
+    pub fn new_with_ttl(key: &[u8], val: &[u8], ttl_secs: u64) -> KeyVal {
+        let mut kv = KeyVal::new(key, val);
+        kv.ttl_secs = Some(ttl_secs);
+        kv
+    }
+
+    /// KeyVal deleting the key when written by the async writers
+    #[inline]
+    pub fn new_tombstone(key: &[u8]) -> KeyVal {
+        let mut kv = KeyVal::new_with_key(key);
+        kv.deleted = true;
+        kv
+    }
+
     #[inline]
     pub fn new_with_db_name(db_name: &[u8], key: &[u8], val: &[u8]) -> KeyVal {
         let hash = KeyVal::get_hash_code(&key);
@@ -73,6 +101,8 @@ impl KeyVal {
             db_name: db_name.to_vec(),
             skip_db: false,
             skip_cache: false,
+            ttl_secs: None,
+            deleted: false,
         }
     }
 
@@ -85,6 +115,8 @@ impl KeyVal {
             db_name: vec![],
             skip_db: false,
             skip_cache: false,
+            ttl_secs: None,
+            deleted: false,
         }
     }
 
@@ -98,6 +130,8 @@ impl KeyVal {
             db_name: vec![],
             skip_db: false,
             skip_cache: false,
+            ttl_secs: None,
+            deleted: false,
         }
     }
 
@@ -111,6 +145,8 @@ impl KeyVal {
             db_name: db_name.to_vec(),
             skip_db: false,
             skip_cache: false,
+            ttl_secs: None,
+            deleted: false,
         }
     }
 
@@ -133,13 +169,31 @@ impl KeyVal {
         KeyVal::gen_consistent_slot(h, slot_count)
     }
 
+    ///
+    /// get the slot based on total slot count and hash seed
+    pub fn key_slot_with_seed(key: &[u8], slot_count: usize, seed: u64) -> u64 {
+        if slot_count == 1 {
+            return 0;
+        }
+        let h = KeyVal::get_hash_code_with_seed(key, seed);
+        KeyVal::gen_consistent_slot(h, slot_count)
+    }
+
     pub fn hash(&self) -> u64 {
         self.hash
     }
 
     #[inline]
     pub fn get_hash_code(key: &[u8]) -> u64 {
-        let mut hasher = XxHash::with_seed(0);
+        KeyVal::get_hash_code_with_seed(key, 0)
+    }
+
+    ///
+    /// hash code for a given seed.
+    /// Changing the seed changes the slot of every key, so existing cache placement is invalidated
+    #[inline]
+    pub fn get_hash_code_with_seed(key: &[u8], seed: u64) -> u64 {
+        let mut hasher = XxHash::with_seed(seed);
         hasher.write(&key);
         hasher.finish()
 
@@ -149,13 +203,20 @@ impl KeyVal {
         //jh.slot(&key, self.config.num_shards as u32)
     }
 
+    ///
+    /// get the slot of a hash code based on total slot count, as slot does for a KeyVal
     #[inline]
-    fn get_slot_from_hash(hash: u64, slot_count: usize) -> u64 {
+    pub fn slot_for_hash(hash: u64, slot_count: usize) -> u64 {
+        if slot_count == 1 {
+            return 0;
+        }
         KeyVal::gen_consistent_slot(hash, slot_count)
     }
 
+    ///
+    /// jump consistent hash of a hash code into one of slot_count slots
     #[inline]
-    fn gen_consistent_slot(hash: u64, slot_count: usize) -> u64 {
+    pub fn gen_consistent_slot(hash: u64, slot_count: usize) -> u64 {
         let mut h = hash;
         let (mut b, mut j) = (-1i64, 0i64);
         while j < slot_count as i64 {
@@ -185,4 +246,34 @@ mod tests {
     fn test_get_hash_code() {
         let key = b"1234567890abcdefghijkl";
     }
+
+    #[test]
+    fn test_key_slot_with_seed() {
+        let slot_count = 1024;
+        let mut moved = 0;
+        for i in 0..100 {
+            let key = format!("key_{}", i);
+            assert_eq!(KeyVal::key_slot(key.as_bytes(), slot_count),
+                       KeyVal::key_slot_with_seed(key.as_bytes(), slot_count, 0));
+            if KeyVal::key_slot_with_seed(key.as_bytes(), slot_count, 0)
+                != KeyVal::key_slot_with_seed(key.as_bytes(), slot_count, 42) {
+                moved += 1;
+            }
+        }
+        assert!(moved > 0);
+    }
+
+    #[test]
+    fn test_slot_for_hash() {
+        assert_eq!(KeyVal::slot_for_hash(0, 1), 0);
+        assert_eq!(KeyVal::slot_for_hash(0, 16), 0);
+        assert_eq!(KeyVal::slot_for_hash(1, 16), 6);
+        assert_eq!(KeyVal::slot_for_hash(42, 1024), 571);
+        assert_eq!(KeyVal::slot_for_hash(12_345_678_901_234_567_890, 128), 49);
+        assert_eq!(KeyVal::slot_for_hash(u64::max_value(), 7), 2);
+
+        let kv = KeyVal::new(b"slot_key", b"val");
+        assert_eq!(kv.slot(64), KeyVal::slot_for_hash(kv.hash(), 64));
+        assert_eq!(kv.slot(64), KeyVal::gen_consistent_slot(kv.hash(), 64));
+    }
 }
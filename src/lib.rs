@@ -12,19 +12,30 @@ extern crate log;
 #[macro_use]
 extern crate serde_derive;
 
+use glob::glob;
 use regex;
 use regex::Regex;
+use std::collections::HashMap;
+use std::path::Path;
 use std::str;
 use std::sync::Arc;
 use std::sync::atomic::AtomicBool;
+use std::sync::mpsc;
+use std::thread;
 
 use crate::db::config::DbManagerConfig;
-use crate::db::db_manager::DbManager;
+use crate::db::db_manager::{DbManager, DbMetrics, KeyValIter, MemoryBreakdown};
+use crate::db::rocks_db::BackupInfo;
+use crate::db::transaction::Txn;
 use crate::keyval::KeyVal;
 
+#[cfg(feature = "async")]
+pub mod async_bhatho;
 pub mod cache;
 pub mod db;
 pub mod keyval;
+#[cfg(test)]
+pub(crate) mod test_util;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RegExMapping {
@@ -32,7 +43,8 @@ pub struct RegExMapping {
     new_db_name: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
 pub struct DbNameExtractor {
     pub enabled: bool,
     pub override_nonempty: bool,
@@ -42,9 +54,21 @@ pub struct DbNameExtractor {
 ///
 /// define a crate level config structure
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct BhathoConfig {
     pub db_configs: Vec<DbManagerConfig>,
     pub db_name_extractor_from_key: DbNameExtractor,
+    /// "modulo" or "jumphash".
+    /// modulo remaps almost every key when a db is added or removed,
+    /// jumphash only moves about 1/n of the keys to the new db
+    pub shard_strategy: String,
+    /// return an error for a db_name not matching any db instead of routing by the key hash
+    pub strict_db_name_routing: bool,
+    /// glob of existing db directories e.g. "/data/tenants/*". A db named after the directory
+    /// is opened for each match using db_template_config.
+    /// db_configs with the same name or db_path take precedence
+    pub db_root_glob: Option<String>,
+    pub db_template_config: DbManagerConfig,
 }
 
 impl Default for BhathoConfig {
@@ -59,6 +83,87 @@ impl Default for BhathoConfig {
         BhathoConfig {
             db_configs,
             db_name_extractor_from_key,
+            shard_strategy: "modulo".to_string(),
+            strict_db_name_routing: false,
+            db_root_glob: None,
+            db_template_config: DbManagerConfig::default(),
+        }
+    }
+}
+
+impl BhathoConfig {
+    ///
+    /// check the config without opening any db or creating any directory.
+    /// returns all the violations found
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        if let Err(e) = Bhatho::check_shard_strategy(&self.shard_strategy) {
+            errors.push(e);
+        }
+        if self.db_name_extractor_from_key.enabled {
+            for mapping in &self.db_name_extractor_from_key.regex_mappings {
+                if let Err(e) = Regex::new(&mapping.extract_name_regex) {
+                    errors.push(format!(
+                        "invalid extract_name_regex: {}. Error:{}",
+                        mapping.extract_name_regex, e
+                    ));
+                }
+            }
+        }
+        for (i, db_config) in self.db_configs.iter().enumerate() {
+            let name = &db_config.name;
+            let cache_config = &db_config.cache_config;
+            let num_shards = cache_config.effective_num_shards();
+            if num_shards == 0 {
+                errors.push(format!("db: {} num_shards must be > 0", name));
+            } else if cache_config.enabled && cache_config.cache_capacity < num_shards {
+                errors.push(format!(
+                    "db: {} cache_capacity ({}) must be >= num_shards ({})",
+                    name, cache_config.cache_capacity, num_shards
+                ));
+            } else if cache_config.enabled
+                && cache_config.cache_capacity < num_shards * cache_config.sub_shards_per_shard
+            {
+                errors.push(format!(
+                    "db: {} cache_capacity ({}) must be >= num_shards * sub_shards_per_shard ({})",
+                    name,
+                    cache_config.cache_capacity,
+                    num_shards * cache_config.sub_shards_per_shard
+                ));
+            }
+            if let Some(re) = db_config.key_validation_regex.as_ref() {
+                if let Err(e) = regex::bytes::Regex::new(re) {
+                    errors.push(format!(
+                        "db: {} invalid key_validation_regex: {}. Error:{}",
+                        name, re, e
+                    ));
+                }
+            }
+            let rocks_db_config = &db_config.rocks_db_config;
+            if rocks_db_config.min_write_buffer_number > rocks_db_config.max_write_buffer_number {
+                errors.push(format!(
+                    "db: {} min_write_buffer_number ({}) must be <= max_write_buffer_number ({})",
+                    name,
+                    rocks_db_config.min_write_buffer_number,
+                    rocks_db_config.max_write_buffer_number
+                ));
+            }
+            if rocks_db_config.durable_async_queue && rocks_db_config.disable_wal {
+                errors.push(format!("db: {} durable_async_queue requires disable_wal false", name));
+            }
+            for other in self.db_configs.iter().skip(i + 1) {
+                if other.name == *name {
+                    errors.push(format!("duplicate db name: {}", name));
+                }
+                if other.rocks_db_config.db_path == rocks_db_config.db_path {
+                    errors.push(format!("duplicate db_path: {}", rocks_db_config.db_path));
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
         }
     }
 }
@@ -112,8 +217,10 @@ impl Bhatho {
     /// e.g Key suffix or prefix
     /// TODO: might want to convert into more efficient lookup compared to string compared
     /// may be hash table
+    /// with strict_db_name_routing, unknown db name returns an error instead of
+    /// falling back to the hash of the key
     #[inline(always)]
-    fn get_shard(&self, kv: &KeyVal) -> usize {
+    fn get_shard(&self, kv: &KeyVal) -> Result<usize, String> {
         let mut db_name = kv.db_name.clone();
         if self.config.db_name_extractor_from_key.enabled {
             if let Ok(name) = self.extract_table_name_from_key(&kv) {
@@ -122,19 +229,104 @@ impl Bhatho {
         }
         if !db_name.is_empty() {
             for i in 0..self.dbs.len() {
-                if self.dbs[i].name.as_bytes() == kv.db_name.as_slice() {
-                    return i;
+                if self.dbs[i].name.as_bytes() == db_name.as_slice() {
+                    return Ok(i);
                 }
             }
-            //FIXME: Should we return error
+            if self.config.strict_db_name_routing {
+                return Err(format!("unknown db_name: {}", String::from_utf8_lossy(&db_name)));
+            }
         }
 
-        (kv.hash % self.dbs.len() as u64) as usize
+        Ok(Bhatho::hash_shard(&self.config.shard_strategy, &kv, self.dbs.len()))
+    }
+
+    ///
+    /// get shard from the key hash using a given shard strategy
+    #[inline(always)]
+    fn hash_shard(shard_strategy: &str, kv: &KeyVal, num_dbs: usize) -> usize {
+        match shard_strategy {
+            //jump consistent hash of the key hash, deterministic across restarts
+            "jumphash" => kv.slot(num_dbs) as usize,
+            _ => (kv.hash % num_dbs as u64) as usize,
+        }
+    }
+
+    /// reject a shard_strategy hash_shard doesn't know
+    fn check_shard_strategy(shard_strategy: &str) -> Result<(), String> {
+        match shard_strategy {
+            "modulo" | "jumphash" => Ok(()),
+            _ => Err(format!("unknown shard_strategy: {}", shard_strategy)),
+        }
+    }
+
+    ///
+    /// get shard for a given db name
+    #[inline(always)]
+    fn get_shard_by_name(&self, db_name: &[u8]) -> Result<usize, String> {
+        for i in 0..self.dbs.len() {
+            if self.dbs[i].name.as_bytes() == db_name {
+                return Ok(i);
+            }
+        }
+        Err(format!("unknown db_name: {}", String::from_utf8_lossy(db_name)))
+    }
+
+    ///
+    /// db_configs followed by a config for each db directory matching db_root_glob
+    fn expand_db_configs(config: &BhathoConfig) -> Result<Vec<DbManagerConfig>, String> {
+        let mut db_configs = config.db_configs.clone();
+        let pattern = match config.db_root_glob.as_ref() {
+            Some(pattern) => pattern,
+            None => return Ok(db_configs),
+        };
+        let paths = match glob(pattern) {
+            Ok(paths) => paths,
+            Err(e) => return Err(format!("invalid db_root_glob: {}. Error:{}", pattern, e)),
+        };
+        for entry in paths {
+            let path = match entry {
+                Ok(path) => path,
+                Err(e) => {
+                    error!("Failed to read db_root_glob entry. Error:{}", e);
+                    continue;
+                }
+            };
+            if !path.is_dir() {
+                continue;
+            }
+            let db_path = path.to_string_lossy().to_string();
+            let name = match path.file_name() {
+                Some(name) => name.to_string_lossy().to_string(),
+                None => continue,
+            };
+            if db_configs
+                .iter()
+                .any(|c| c.name == name || Path::new(&c.rocks_db_config.db_path) == path)
+            {
+                info!("Skipping db: {} matched by db_root_glob, already configured", name);
+                continue;
+            }
+            info!("Discovered db: {} at path: {}", name, db_path);
+            let mut db_config = config.db_template_config.clone();
+            db_config.name = name.clone();
+            db_config.rocks_db_config.wal_dir = format!("{}/wal", db_path);
+            db_config.rocks_db_config.backup_path =
+                format!("{}/{}", db_config.rocks_db_config.backup_path, name);
+            db_config.rocks_db_config.db_path = db_path;
+            db_configs.push(db_config);
+        }
+        Ok(db_configs)
     }
 
     pub fn new(config: &BhathoConfig, shutdown: Arc<AtomicBool>) -> Result<Bhatho, String> {
-        let mut dbs = Vec::with_capacity(config.db_configs.len());
-        for db_config in config.db_configs.iter() {
+        Bhatho::check_shard_strategy(&config.shard_strategy)?;
+        let db_configs = Bhatho::expand_db_configs(&config)?;
+        if db_configs.is_empty() {
+            return Err("no db configured".to_string());
+        }
+        let mut dbs = Vec::with_capacity(db_configs.len());
+        for db_config in db_configs.iter() {
             let db_mgr = DbManager::new(db_config, shutdown.clone())?;
             dbs.push(db_mgr);
         }
@@ -159,66 +351,497 @@ impl Bhatho {
     /// get the value for a given key
     #[inline(always)]
     pub fn get(&self, kv: &KeyVal) -> Result<Option<(Vec<u8>, bool)>, String> {
-        let shard = self.get_shard(&kv);
+        let shard = self.get_shard(&kv)?;
 
         self.dbs[shard].get_key_val(&kv)
     }
 
+    ///
+    /// get the value for a given key as str (wrapper function)
+    #[inline(always)]
+    pub fn get_str(&self, key: &str) -> Result<Option<String>, String> {
+        let kv = KeyVal::new_with_key(key.as_bytes());
+        Ok(self
+            .get(&kv)?
+            .map(|(val, _)| String::from_utf8_lossy(&val).to_string()))
+    }
+
+    ///
+    /// get the values for the given keys. values are returned in the order of the keys,
+    /// None for a key not found
+    pub fn multi_get(&self, kvs: &[KeyVal]) -> Result<Vec<Option<Vec<u8>>>, String> {
+        let mut vals = Vec::with_capacity(kvs.len());
+        for kv in kvs.iter() {
+            vals.push(self.get(&kv)?.map(|(val, _)| val));
+        }
+        Ok(vals)
+    }
+
+    ///
+    /// get the values for the given str keys as a map (wrapper function).
+    /// None for a key not found
+    pub fn get_many_str(&self, keys: &[&str]) -> Result<HashMap<String, Option<String>>, String> {
+        let kvs: Vec<KeyVal> = keys
+            .iter()
+            .map(|key| KeyVal::new_with_key(key.as_bytes()))
+            .collect();
+        let vals = self.multi_get(&kvs)?;
+        Ok(keys
+            .iter()
+            .zip(vals.into_iter())
+            .map(|(key, val)| {
+                (
+                    key.to_string(),
+                    val.map(|val| String::from_utf8_lossy(&val).to_string()),
+                )
+            })
+            .collect())
+    }
+
+    ///
+    /// true if the key is currently cached. It only checks the cache, never the DB,
+    /// and doesn't update the recency of the key
+    #[inline(always)]
+    pub fn in_cache(&self, kv: &KeyVal) -> bool {
+        match self.get_shard(&kv) {
+            Ok(shard) => self.dbs[shard].in_cache(&kv),
+            Err(_) => false,
+        }
+    }
+
+    ///
+    /// put the key, val pair as str (wrapper function)
+    #[inline(always)]
+    pub fn put_str(&self, key: &str, val: &str) -> Result<(), String> {
+        self.put(&KeyVal::new(key.as_bytes(), val.as_bytes()))
+    }
+
+    ///
+    /// get the value for a given key from DB, skipping the cache lookup.
+    /// The cache is refreshed with the value read from DB
+    #[inline(always)]
+    pub fn get_consistent(&self, kv: &KeyVal) -> Result<Option<(Vec<u8>, bool)>, String> {
+        let shard = self.get_shard(&kv)?;
+
+        self.dbs[shard].get_consistent(&kv)
+    }
+
     ///
     /// put the key, val pair to DB and Lru Cache
     #[inline(always)]
     pub fn put(&self, kv: &KeyVal) -> Result<(), String> {
-        let shard = self.get_shard(&kv);
+        let shard = self.get_shard(&kv)?;
 
         self.dbs[shard].put_key_val(&kv)
     }
 
+    ///
+    /// put the key, val pair to Lru Cache only. It is never written to DB
+    #[inline(always)]
+    pub fn put_cache_only(&self, kv: &KeyVal) -> Result<(), String> {
+        let shard = self.get_shard(&kv)?;
+        self.dbs[shard].put_cache_only(&kv)
+    }
+
+    ///
+    /// remove all the cached entries of a given db with the key starting with prefix.
+    /// returns number of entries removed. db is not modified
+    pub fn invalidate_cache_prefix(&self, db_name: &[u8], prefix: &[u8]) -> Result<u64, String> {
+        let shard = self.get_shard_by_name(db_name)?;
+        Ok(self.dbs[shard].invalidate_cache_prefix(prefix))
+    }
+
+    ///
+    /// pin the key val pair in the cache so it is never evicted, e.g. config or lookup keys.
+    /// pinned entries don't count against the cache capacity. db is not modified,
+    /// use put to persist the value. a put or delete of the key updates or removes the pin
+    pub fn pin_key(&self, kv: &KeyVal) -> Result<(), String> {
+        let shard = self.get_shard(&kv)?;
+        self.dbs[shard].pin_key(&kv.key, &kv.val)
+    }
+
+    ///
+    /// unpin the key, making it subject to eviction again. returns false if the key is not pinned
+    pub fn unpin_key(&self, kv: &KeyVal) -> Result<bool, String> {
+        let shard = self.get_shard(&kv)?;
+        Ok(self.dbs[shard].unpin_key(&kv.key))
+    }
+
+    ///
+    /// load up to limit key-val pairs of a given db with the key starting with prefix
+    /// into the cache, e.g. hot keys at startup. returns number of entries loaded
+    pub fn warm_prefix(&self, db_name: &[u8], prefix: &[u8], limit: usize) -> Result<u64, String> {
+        let shard = self.get_shard_by_name(db_name)?;
+        self.dbs[shard].warm_prefix(prefix, limit)
+    }
+
+    ///
+    /// copy the cache entries of a given db into the cache of the db with the same name
+    /// of another instance, e.g. to warm a new instance during a blue/green deploy.
+    /// the cache of this instance is left intact. returns number of entries copied
+    pub fn transfer_cache(&self, target: &Bhatho, db_name: &[u8]) -> Result<u64, String> {
+        let shard = self.get_shard_by_name(db_name)?;
+        let target_shard = target.get_shard_by_name(db_name)?;
+        Ok(self.dbs[shard].transfer_cache(&target.dbs[target_shard]))
+    }
+
+    ///
+    /// number of cached entries of each cache shard of a given db.
+    /// an uneven distribution points to a bad hash or a hot key prefix
+    pub fn shard_occupancy(&self, db_name: &[u8]) -> Result<Vec<usize>, String> {
+        let shard = self.get_shard_by_name(db_name)?;
+        Ok(self.dbs[shard].cache_shard_lengths())
+    }
+
+    ///
+    /// change the total cache capacity of a given db at runtime.
+    /// shrinking evicts the excess entries immediately
+    pub fn resize_cache(&self, db_name: &[u8], new_total: usize) -> Result<(), String> {
+        let shard = self.get_shard_by_name(db_name)?;
+        self.dbs[shard].resize_cache(new_total)
+    }
+
+    ///
+    /// put the key, val pair to DB and Lru Cache and return the previous value.
+    /// It forces a synchronous DB write
+    #[inline(always)]
+    pub fn put_and_get_prev(&self, kv: &KeyVal) -> Result<Option<Vec<u8>>, String> {
+        let shard = self.get_shard(&kv)?;
+
+        self.dbs[shard].put_and_get_prev(&kv)
+    }
+
+    ///
+    /// put the key-val pairs to DB and Lru Cache with one batch write per db.
+    /// all the dbs are attempted and the first error is returned
+    pub fn batch_put(&self, kvs: &[KeyVal]) -> Result<(), String> {
+        let mut by_shard: Vec<Vec<KeyVal>> = vec![Vec::new(); self.dbs.len()];
+        for kv in kvs.iter() {
+            by_shard[self.get_shard(&kv)?].push(kv.clone());
+        }
+        let mut res = Ok(());
+        for (shard, data) in by_shard.iter().enumerate() {
+            if data.is_empty() {
+                continue;
+            }
+            if let Err(e) = self.dbs[shard].batch_put(data) {
+                error!("batch_put failed for db: {}. Error:{}", self.dbs[shard].name, e);
+                if res.is_ok() {
+                    res = Err(e);
+                }
+            }
+        }
+        res
+    }
+
+    ///
+    /// put the key, val string pairs with batch_put
+    pub fn put_batch_str(&self, pairs: &[(String, String)]) -> Result<(), String> {
+        if pairs.is_empty() {
+            return Ok(());
+        }
+        let kvs: Vec<KeyVal> = pairs
+            .iter()
+            .map(|(key, val)| KeyVal::new(key.as_bytes(), val.as_bytes()))
+            .collect();
+        self.batch_put(&kvs)
+    }
+
+    ///
+    /// bulk load the key-val pairs using multiple threads.
+    /// entries are partitioned by target db and each partition is split into
+    /// chunks so threads write disjoint batches. returns the number of entries loaded
+    pub fn parallel_load(&self, entries: Vec<KeyVal>, threads: usize) -> Result<u64, String> {
+        let threads = std::cmp::max(threads, 1);
+        let mut partitions: Vec<Vec<KeyVal>> = vec![Vec::new(); self.dbs.len()];
+        for kv in entries {
+            let shard = self.get_shard(&kv)?;
+            partitions[shard].push(kv);
+        }
+
+        //split each partition so that every thread gets a batch
+        let chunks_per_db = std::cmp::max(threads / self.dbs.len(), 1);
+        let mut work: Vec<Vec<(usize, Vec<KeyVal>)>> = vec![Vec::new(); threads];
+        let mut next = 0;
+        for (shard, mut partition) in partitions.into_iter().enumerate() {
+            let chunk_size = std::cmp::max(partition.len() / chunks_per_db + 1, 1);
+            while !partition.is_empty() {
+                let rest = partition.split_off(std::cmp::min(chunk_size, partition.len()));
+                work[next % threads].push((shard, partition));
+                partition = rest;
+                next += 1;
+            }
+        }
+
+        let mut handles = Vec::with_capacity(threads);
+        for batches in work.into_iter().filter(|batches| !batches.is_empty()) {
+            let dbs = self.dbs.clone();
+            handles.push(thread::spawn(move || -> Result<u64, String> {
+                let mut loaded = 0;
+                for (shard, batch) in batches.iter() {
+                    dbs[*shard].batch_put(batch)?;
+                    loaded += batch.len() as u64;
+                }
+                Ok(loaded)
+            }));
+        }
+
+        let mut total = 0;
+        for handle in handles {
+            match handle.join() {
+                Ok(res) => total += res?,
+                Err(e) => return Err(format!("parallel_load thread panicked: {:?}", e)),
+            }
+        }
+        Ok(total)
+    }
+
+    ///
+    /// delete the key-val pairs from db and lru cache with one batch write per db.
+    /// all the dbs are attempted and the first error is returned
+    pub fn delete_many(&self, kvs: &[KeyVal]) -> Result<(), String> {
+        let mut by_shard: Vec<Vec<KeyVal>> = vec![Vec::new(); self.dbs.len()];
+        for kv in kvs.iter() {
+            by_shard[self.get_shard(&kv)?].push(kv.clone());
+        }
+        let mut res = Ok(());
+        for (shard, data) in by_shard.iter().enumerate() {
+            if data.is_empty() {
+                continue;
+            }
+            if let Err(e) = self.dbs[shard].delete_many(data) {
+                error!("delete_many failed for db: {}. Error:{}", self.dbs[shard].name, e);
+                if res.is_ok() {
+                    res = Err(e);
+                }
+            }
+        }
+        res
+    }
+
+    ///
+    /// delete the key-val pair from db and lru cache and return the previous value
+    #[inline(always)]
+    pub fn delete_and_get_prev(&self, kv: &KeyVal) -> Result<Option<Vec<u8>>, String> {
+        let shard = self.get_shard(&kv)?;
+        self.dbs[shard].delete_and_get_prev(&kv)
+    }
+
     ///
     /// delete the key-val pair from db and lru cache for a given key
     #[inline(always)]
     pub fn delete(&self, kv: &KeyVal) -> Result<(), String> {
-        let shard = self.get_shard(&kv);
+        let shard = self.get_shard(&kv)?;
         self.dbs[shard].delete_key_val(&kv)
     }
 
     ///
-    /// Export all the Keys from LRU Cache to a file path configured in the cache mgr
-    pub fn export_lru_keys(&self, db_name: &[u8]) -> Result<(), String> {
+    /// iterate over all the key-val pairs stored in the db.
+    /// It reads directly from the db and bypasses the cache.
+    /// a value failing to decode is returned as an error
+    pub fn iter_all(
+        &self,
+        db_name: &[u8],
+    ) -> Result<KeyValIter<'_>, String> {
+        let shard = self.get_shard_by_name(db_name)?;
+        self.dbs[shard].iter_all()
+    }
+
+    ///
+    /// number of keys of a given db starting with a given prefix, without reading them
+    /// into memory. It reads directly from the db and bypasses the cache
+    pub fn count_prefix(&self, db_name: &[u8], prefix: &[u8]) -> Result<u64, String> {
+        let shard = self.get_shard_by_name(db_name)?;
+        self.dbs[shard].count_prefix(prefix)
+    }
+
+    ///
+    /// get up to limit key-val pairs of a given db with start <= key < end, in key order.
+    /// It reads directly from the db and bypasses the cache.
+    /// For pagination, pass the last key of a page as the start of the next one
+    /// and skip the first pair, as start is inclusive
+    pub fn scan_range(
+        &self,
+        db_name: &[u8],
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, String> {
+        let shard = self.get_shard_by_name(db_name)?;
+        self.dbs[shard].scan_range(start, end, limit, false)
+    }
+
+    ///
+    /// same as scan_range but returns the pairs in descending key order, starting before end.
+    /// For newest-first pagination, pass the last key of a page as the end of the next one
+    pub fn scan_range_rev(
+        &self,
+        db_name: &[u8],
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, String> {
+        let shard = self.get_shard_by_name(db_name)?;
+        self.dbs[shard].scan_range(start, end, limit, true)
+    }
+
+    ///
+    /// true if the db failed to open and a given db is serving from cache only
+    pub fn is_degraded(&self, db_name: &[u8]) -> Result<bool, String> {
+        let shard = self.get_shard_by_name(db_name)?;
+        Ok(self.dbs[shard].is_degraded())
+    }
+
+    ///
+    /// cache and db stats for each db
+    pub fn metrics(&self) -> Vec<DbMetrics> {
+        self.dbs.iter().map(|db| db.metrics()).collect()
+    }
+
+    ///
+    /// approximate memory used by each component for each db
+    pub fn memory_breakdown(&self) -> Vec<MemoryBreakdown> {
+        self.dbs.iter().map(|db| db.memory_breakdown()).collect()
+    }
+
+    ///
+    /// close and reopen a given db in place, e.g. after its on-disk state is changed externally.
+    /// The cache is preserved. reads and writes of the db block while it is reopened
+    pub fn reopen(&self, db_name: &[u8]) -> Result<(), String> {
+        let shard = self.get_shard_by_name(db_name)?;
+        self.dbs[shard].reopen()
+    }
+
+    ///
+    /// atomically replace a given db with the db at new_db_path, e.g. one rebuilt offline.
+    /// the cache of the db is cleared. the old db is closed once in-flight reads complete
+    pub fn swap_db(&self, db_name: &[u8], new_db_path: &str) -> Result<(), String> {
+        let shard = self.get_shard_by_name(db_name)?;
+        self.dbs[shard].swap_db(new_db_path)
+    }
+
+    ///
+    /// liveness/readiness probe. checks each db responds to a read and its async writers
+    /// are running. returns an error naming the first unhealthy db
+    pub fn health_check(&self) -> Result<(), String> {
+        for db in self.dbs.iter() {
+            if let Err(e) = db.health_check() {
+                return Err(format!("db: {} unhealthy. Error:{}", db.name, e));
+            }
+        }
+        Ok(())
+    }
+
+    ///
+    /// write and sync the WAL of a given db to disk
+    pub fn sync_wal(&self, db_name: &[u8]) -> Result<(), String> {
+        let shard = self.get_shard_by_name(db_name)?;
+        self.dbs[shard].sync_wal()
+    }
+
+    ///
+    /// physically drop the keys of a given db starting with any of the prefixes, e.g. to
+    /// offboard a tenant without deleting each key. keys are removed lazily when compactions
+    /// rewrite their files, compact removes them right away
+    pub fn set_drop_prefixes(&self, db_name: &[u8], prefixes: Vec<Vec<u8>>) -> Result<(), String> {
+        let shard = self.get_shard_by_name(db_name)?;
+        self.dbs[shard].set_drop_prefixes(prefixes)
+    }
+
+    ///
+    /// compact a given db. it blocks until the compaction is done
+    pub fn compact(&self, db_name: &[u8]) -> Result<(), String> {
+        let shard = self.get_shard_by_name(db_name)?;
+        self.dbs[shard].compact()
+    }
+
+    ///
+    /// start a multi-key transaction on a given db
+    pub fn transaction(&self, db_name: &[u8]) -> Result<Txn, String> {
+        let shard = self.get_shard_by_name(db_name)?;
+        Ok(self.dbs[shard].transaction())
+    }
+
+    ///
+    /// number of pending writes in the async write queue for a given db
+    pub fn write_queue_len(&self, db_name: &[u8]) -> Result<usize, String> {
+        let shard = self.get_shard_by_name(db_name)?;
+        Ok(self.dbs[shard].write_queue_len())
+    }
+
+    ///
+    /// capacity of the async write queue for a given db
+    pub fn write_queue_capacity(&self, db_name: &[u8]) -> Result<Option<usize>, String> {
+        let shard = self.get_shard_by_name(db_name)?;
+        Ok(self.dbs[shard].write_queue_capacity())
+    }
+
+    ///
+    /// estimated number of keys across all the dbs
+    pub fn total_estimated_keys(&self) -> u64 {
+        self.dbs.iter().map(|db| db.estimated_num_keys()).sum()
+    }
+
+    ///
+    /// estimated number of keys per db name
+    pub fn estimated_keys_per_db(&self) -> Vec<(String, u64)> {
+        self.dbs
+            .iter()
+            .map(|db| (db.name.clone(), db.estimated_num_keys()))
+            .collect()
+    }
+
+    ///
+    /// Export all the Keys from LRU Cache to a file path configured in the cache mgr.
+    /// Empty db_name exports every db. Each db is exported in background and sends
+    /// (db_name, exported key count or the export error) to the returned receiver once done.
+    /// The receiver is disconnected after all the exports are done
+    pub fn export_lru_keys(
+        &self,
+        db_name: &[u8],
+    ) -> Result<mpsc::Receiver<(String, Result<u64, String>)>, String> {
         info!("Exporting Lru Keys.  might take a while. Make sure instance remains up.");
-        for i in 0..self.dbs.len() {
+        let shards: Vec<usize> = if db_name.is_empty() {
+            (0..self.dbs.len()).collect()
+        } else {
+            vec![self.get_shard_by_name(db_name)?]
+        };
+        let (tx, rx) = mpsc::channel();
+        for i in shards {
             let dbs = self.dbs.clone();
-            let db_name_str = String::from_utf8_lossy(db_name).to_string();
+            let tx = tx.clone();
             std::thread::spawn(move || {
                 let db = &dbs[i];
-
-                //let db =db.lock();
-                let mut count = 0u64;
-                if !db_name_str.is_empty() {
-                    if db.name == db_name_str {
-                        info!("Exporting keys for  db cache : {}", db_name_str);
-                        count = match db.export_lru_keys() {
-                            Ok(c) => c,
-                            Err(e) => {
-                                error!("Failed to export lru cache for db: {}. Error:{:?}", db_name_str, e);
-                                0
-                            }
-                        }
-                    }
-                } else {
-                    info!("Exporting keys for  db cache : {}", db_name_str);
-                    count = match db.export_lru_keys() {
-                        Ok(c) => c,
-                        Err(e) => {
-                            error!("Failed to export lru cache for db: {}. Error:{:?}", db_name_str, e);
-                            0
-                        }
+                info!("Exporting keys for  db cache : {}", db.name);
+                let res = db.export_lru_keys();
+                match res.as_ref() {
+                    Ok(count) => info!("{} Keys export completed for db cache: {}", count, db.name),
+                    Err(e) => {
+                        error!("Failed to export lru cache for db: {}. Error:{:?}", db.name, e)
                     }
                 }
-
-                info!("{} Keys export completed for db cache: {}", count, db_name_str);
+                if tx.send((db.name.clone(), res)).is_err() {
+                    debug!("Export completion receiver dropped for db: {}", db.name);
+                }
             });
-        };
-        Ok(())
+        }
+        Ok(rx)
+    }
+
+    ///
+    /// load the values of the keys exported by export_lru_keys for a given db into its cache.
+    /// returns number of keys loaded
+    pub fn import_lru_keys(&self, db_name: &[u8]) -> Result<u64, String> {
+        let shard = self.get_shard_by_name(db_name)?;
+        self.dbs[shard].import_lru_keys()
+    }
+
+    ///
+    /// backups available for a given db, oldest first.
+    /// returns an error if backups are not enabled for the db
+    pub fn list_backups(&self, db_name: &[u8]) -> Result<Vec<BackupInfo>, String> {
+        let shard = self.get_shard_by_name(db_name)?;
+        self.dbs[shard].list_backups()
     }
 
     pub fn backup_db(&self, db_name: &[u8]) -> Result<(), String> {
@@ -258,6 +881,7 @@ mod tests {
     use std::thread;
 
     use super::*;
+    use crate::test_util::bhatho_test_config as test_config;
 
     use self::rand::distributions::Alphanumeric;
     use self::rand::prelude::*;
@@ -341,9 +965,7 @@ mod tests {
 
     #[test]
     fn init_db_test() {
-        let mut conf = BhathoConfig::default();
-
-        conf.db_configs[0].rocks_db_config.restore_from_backup_at_startup = false;
+        let conf = test_config("init_db");
         let shutdown = Arc::new(AtomicBool::new(false));
         let bhatho = Bhatho::new(&conf, shutdown);
         if let Err(e) = bhatho {
@@ -362,4 +984,669 @@ mod tests {
         let (v2, _from_cache) = res.unwrap().unwrap();
         assert!(val.to_vec() == v2);
     }
+
+    #[test]
+    fn iter_all_test() {
+        let conf = test_config("iter_all");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        let total = 1_000;
+        for i in 0..total {
+            let key = format!("iter_key_{}", i);
+            let kv = KeyVal::new_with_db_name(b"iter_all", key.as_bytes(), b"val");
+            db.put(&kv).unwrap();
+        }
+        assert_eq!(db.iter_all(b"iter_all").unwrap().count(), total);
+        assert!(db.iter_all(b"unknown").is_err());
+    }
+
+    #[test]
+    fn put_and_get_prev_test() {
+        let mut conf = test_config("put_and_get_prev");
+        conf.db_configs[0].rocks_db_config.async_write = true;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown.clone()).unwrap();
+        let kv = KeyVal::new(b"prev_key", b"val_1");
+        assert_eq!(db.put_and_get_prev(&kv).unwrap(), None);
+        let kv = KeyVal::new(b"prev_key", b"val_2");
+        assert_eq!(db.put_and_get_prev(&kv).unwrap(), Some(b"val_1".to_vec()));
+        shutdown.store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    #[test]
+    fn parallel_load_test() {
+        let conf = test_config("parallel_load");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        let total = 50_000;
+        let mut entries = Vec::with_capacity(total);
+        for i in 0..total {
+            let key = format!("parallel_key_{}", i);
+            entries.push(KeyVal::new(key.as_bytes(), key.as_bytes()));
+        }
+        assert_eq!(db.parallel_load(entries, 8).unwrap(), total as u64);
+        for i in 0..total {
+            let key = format!("parallel_key_{}", i);
+            let (val, _) = db.get(&KeyVal::new_with_key(key.as_bytes())).unwrap().unwrap();
+            assert_eq!(val, key.as_bytes().to_vec());
+        }
+    }
+
+    #[test]
+    fn reopen_test() {
+        let conf = test_config("reopen");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        let kv = KeyVal::new(b"reopen_key", b"val");
+        db.put(&kv).unwrap();
+        db.reopen(b"reopen").unwrap();
+
+        assert_eq!(db.get(&kv).unwrap(), Some((b"val".to_vec(), true)));
+        assert_eq!(db.get_consistent(&kv).unwrap(), Some((b"val".to_vec(), false)));
+        db.put(&KeyVal::new(b"reopen_key_2", b"val")).unwrap();
+        assert_eq!(db.iter_all(b"reopen").unwrap().count(), 2);
+    }
+
+    #[test]
+    fn swap_db_test() {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let rebuilt_conf = test_config("swap_db_rebuilt");
+        let rebuilt = Bhatho::new(&rebuilt_conf, shutdown.clone()).unwrap();
+        rebuilt.put(&KeyVal::new(b"swap_key", b"new_val")).unwrap();
+        rebuilt.put(&KeyVal::new(b"swap_key_2", b"new_val")).unwrap();
+        drop(rebuilt);
+
+        let conf = test_config("swap_db");
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        let kv = KeyVal::new(b"swap_key", b"old_val");
+        db.put(&kv).unwrap();
+        assert_eq!(db.get(&kv).unwrap(), Some((b"old_val".to_vec(), true)));
+        let pinned = KeyVal::new(b"swap_key_2", b"pinned_val");
+        db.pin_key(&pinned).unwrap();
+        assert_eq!(db.get(&pinned).unwrap(), Some((b"pinned_val".to_vec(), true)));
+
+        let new_db_path = &rebuilt_conf.db_configs[0].rocks_db_config.db_path;
+        db.swap_db(b"swap_db", new_db_path).unwrap();
+        assert_eq!(db.get(&kv).unwrap(), Some((b"new_val".to_vec(), false)));
+        assert_eq!(db.get(&pinned).unwrap(), Some((b"new_val".to_vec(), false)));
+        assert_eq!(db.iter_all(b"swap_db").unwrap().count(), 2);
+    }
+
+    #[test]
+    fn drop_prefixes_test() {
+        let conf = test_config("drop_prefixes");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        for i in 0..100 {
+            db.put(&KeyVal::new(format!("tenant_1:{}", i).as_bytes(), b"val")).unwrap();
+            db.put(&KeyVal::new(format!("tenant_2:{}", i).as_bytes(), b"val")).unwrap();
+        }
+        db.set_drop_prefixes(b"drop_prefixes", vec![b"tenant_1:".to_vec()]).unwrap();
+        db.compact(b"drop_prefixes").unwrap();
+
+        assert_eq!(db.get(&KeyVal::new(b"tenant_1:42", b"")).unwrap(), None);
+        assert_eq!(db.count_prefix(b"drop_prefixes", b"tenant_1:").unwrap(), 0);
+        assert_eq!(db.count_prefix(b"drop_prefixes", b"tenant_2:").unwrap(), 100);
+        assert!(db.set_drop_prefixes(b"unknown", vec![]).is_err());
+    }
+
+    #[test]
+    fn sync_wal_test() {
+        let conf = test_config("sync_wal");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        {
+            let db = Bhatho::new(&conf, shutdown.clone()).unwrap();
+            db.put(&KeyVal::new(b"wal_key", b"val")).unwrap();
+            db.sync_wal(b"sync_wal").unwrap();
+            assert!(db.sync_wal(b"unknown_db").is_err());
+        }
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        let (val, from_cache) = db.get(&KeyVal::new_with_key(b"wal_key")).unwrap().unwrap();
+        assert_eq!(val, b"val".to_vec());
+        assert!(!from_cache);
+    }
+
+    #[test]
+    fn get_str_put_str_test() {
+        let conf = test_config("get_put_str");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        db.put_str("भाथो", "नमस्ते 🙏").unwrap();
+        assert_eq!(db.get_str("भाथो").unwrap(), Some("नमस्ते 🙏".to_string()));
+        assert_eq!(db.get_str("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn strict_db_name_routing_test() {
+        let mut conf = test_config("strict_routing");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown.clone()).unwrap();
+        let kv = KeyVal::new_with_db_name(b"unknown_db", b"strict_key", b"val");
+        assert!(db.put(&kv).is_ok());
+        drop(db);
+
+        conf.strict_db_name_routing = true;
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        let err = Err("unknown db_name: unknown_db".to_string());
+        assert_eq!(db.put(&kv), err);
+        assert_eq!(db.get(&kv).map(|_| ()), err);
+        assert_eq!(db.delete(&kv), err);
+        let kv = KeyVal::new_with_db_name(b"strict_routing", b"strict_key", b"val");
+        assert!(db.put(&kv).is_ok());
+    }
+
+    #[test]
+    fn delete_many_test() {
+        let mut conf = test_config("delete_many_1");
+        let second = test_config("delete_many_2");
+        conf.db_configs.push(second.db_configs[0].clone());
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        let kvs: Vec<KeyVal> = (0..100)
+            .map(|i| {
+                let key = format!("delete_many_{}", i);
+                KeyVal::new(key.as_bytes(), b"val")
+            })
+            .collect();
+        for kv in kvs.iter() {
+            db.put(&kv).unwrap();
+        }
+        db.delete_many(&kvs[..50]).unwrap();
+        for (i, kv) in kvs.iter().enumerate() {
+            let found = db.get(&KeyVal::new_with_key(&kv.key)).unwrap();
+            assert_eq!(found.is_none(), i < 50);
+        }
+    }
+
+    #[test]
+    fn delete_many_after_async_put_test() {
+        let mut conf = test_config("delete_many_async");
+        let rocks_db_config = &mut conf.db_configs[0].rocks_db_config;
+        rocks_db_config.async_write = true;
+        rocks_db_config.async_writer_threads_sleep_ms = 200;
+        rocks_db_config.async_writer_min_sleep_ms = 200;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown.clone()).unwrap();
+        //let the writer find the queue empty and go to sleep, so the put is still queued
+        thread::sleep(std::time::Duration::from_millis(50));
+        let kv = KeyVal::new(b"delete_many_async_key", b"val");
+        db.put(&kv).unwrap();
+        db.delete_many(&[kv.clone()]).unwrap();
+        while db.dbs[0].write_queue_len() > 0 {
+            thread::sleep(std::time::Duration::from_millis(50));
+        }
+        thread::sleep(std::time::Duration::from_millis(100));
+        assert_eq!(db.get(&KeyVal::new_with_key(&kv.key)).unwrap(), None);
+        shutdown.store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    #[test]
+    fn metrics_test() {
+        let mut conf = test_config("metrics_1");
+        let second = test_config("metrics_2");
+        conf.db_configs.push(second.db_configs[0].clone());
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        for i in 0..100 {
+            let key = format!("metrics_key_{}", i);
+            db.put(&KeyVal::new(key.as_bytes(), b"val")).unwrap();
+        }
+        db.get(&KeyVal::new_with_key(b"metrics_key_0")).unwrap();
+        db.get(&KeyVal::new_with_key(b"missing_key")).unwrap();
+
+        let metrics = db.metrics();
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics.iter().map(|m| m.cache_len).sum::<usize>(), 100);
+        assert_eq!(metrics.iter().map(|m| m.cache_hits).sum::<u64>(), 1);
+        assert_eq!(metrics.iter().map(|m| m.cache_misses).sum::<u64>(), 1);
+        assert_eq!(metrics.iter().map(|m| m.disk_reads).sum::<u64>(), 1);
+    }
+
+    #[test]
+    fn total_estimated_keys_test() {
+        let mut conf = test_config("estimated_keys_1");
+        let second = test_config("estimated_keys_2");
+        conf.db_configs.push(second.db_configs[0].clone());
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        let total = 1_000;
+        for i in 0..total {
+            let key = format!("estimated_key_{}", i);
+            db.put(&KeyVal::new(key.as_bytes(), b"val")).unwrap();
+        }
+        for mgr in db.dbs.iter() {
+            mgr.flush().unwrap();
+        }
+        let per_db = db.estimated_keys_per_db();
+        assert_eq!(per_db.len(), 2);
+        assert_eq!(per_db.iter().map(|(_, count)| count).sum::<u64>(), db.total_estimated_keys());
+        let estimate = db.total_estimated_keys();
+        assert!(estimate >= total / 2 && estimate <= total * 2);
+    }
+
+    #[test]
+    fn db_root_glob_test() {
+        let root = &crate::test_util::test_dir("glob_root");
+        std::fs::create_dir_all(format!("{}/tenant_a", root)).unwrap();
+        std::fs::create_dir_all(format!("{}/tenant_b", root)).unwrap();
+        std::fs::write(format!("{}/not_a_db", root), b"").unwrap();
+
+        let mut conf = BhathoConfig::default();
+        conf.db_configs.clear();
+        conf.db_root_glob = Some(format!("{}/*", root));
+        let template = &mut conf.db_template_config;
+        template.rocks_db_config.backup_path = format!("{}_bkup", root);
+        template.rocks_db_config.restore_from_backup_at_startup = false;
+        template.rocks_db_config.async_write = false;
+        template.cache_config.cache_capacity = 1_000;
+        template.cache_config.num_shards = 4;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+
+        let names: Vec<&str> = db.dbs.iter().map(|mgr| mgr.name.as_str()).collect();
+        assert_eq!(names, vec!["tenant_a", "tenant_b"]);
+        let mut kv = KeyVal::new(b"glob_key", b"val");
+        kv.db_name = b"tenant_b".to_vec();
+        db.put(&kv).unwrap();
+        assert_eq!(db.get(&kv).unwrap(), Some((b"val".to_vec(), true)));
+    }
+
+    #[test]
+    fn get_many_str_test() {
+        let conf = test_config("get_many_str");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        db.put_str("many_a", "val_a").unwrap();
+        db.put_str("many_b", "val_b").unwrap();
+
+        let vals = db.get_many_str(&["many_a", "many_b", "many_c"]).unwrap();
+        assert_eq!(vals.len(), 3);
+        assert_eq!(vals["many_a"], Some("val_a".to_string()));
+        assert_eq!(vals["many_b"], Some("val_b".to_string()));
+        assert_eq!(vals["many_c"], None);
+    }
+
+    #[test]
+    fn health_check_test() {
+        let mut conf = test_config("bhatho_health_check");
+        conf.db_configs[0].rocks_db_config.async_write = true;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        assert!(db.health_check().is_ok());
+    }
+
+    #[test]
+    fn in_cache_test() {
+        let conf = test_config("in_cache");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        let kv = KeyVal::new(b"in_cache_key", b"val");
+        assert!(!db.in_cache(&kv));
+        db.put(&kv).unwrap();
+        assert!(db.in_cache(&kv));
+
+        assert_eq!(db.invalidate_cache_prefix(b"in_cache", b"").unwrap(), 1);
+        assert!(!db.in_cache(&kv));
+        assert_eq!(db.get(&kv).unwrap(), Some((b"val".to_vec(), false)));
+    }
+
+    #[test]
+    fn export_lru_keys_test() {
+        let mut conf = test_config("export_keys_a");
+        let mut second = test_config("export_keys_b").db_configs[0].clone();
+        conf.db_configs[0].cache_config.keys_dump_file =
+            format!("{}.dump", crate::test_util::test_dir("export_keys_a"));
+        second.cache_config.keys_dump_file =
+            format!("{}.dump", crate::test_util::test_dir("export_keys_b"));
+        conf.db_configs.push(second);
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        for i in 0..100 {
+            let mut kv = KeyVal::new(format!("export_key_{}", i).as_bytes(), b"val");
+            let name: &[u8] = if i % 2 == 0 { b"export_keys_a" } else { b"export_keys_b" };
+            kv.db_name = name.to_vec();
+            db.put(&kv).unwrap();
+        }
+
+        let mut done: Vec<(String, Result<u64, String>)> =
+            db.export_lru_keys(b"").unwrap().iter().collect();
+        done.sort();
+        assert_eq!(
+            done,
+            vec![("export_keys_a".to_string(), Ok(50)), ("export_keys_b".to_string(), Ok(50))]
+        );
+        let rx = db.export_lru_keys(b"export_keys_b").unwrap();
+        let done: Vec<(String, Result<u64, String>)> = rx.iter().collect();
+        assert_eq!(done, vec![("export_keys_b".to_string(), Ok(50))]);
+        assert!(db.export_lru_keys(b"unknown").is_err());
+
+        //dump path under a regular file can't be created
+        let mut conf = test_config("export_keys_failed");
+        let file_path = crate::test_util::test_dir("export_keys_file");
+        std::fs::create_dir_all(Path::new(&file_path).parent().unwrap()).unwrap();
+        std::fs::write(&file_path, b"").unwrap();
+        conf.db_configs[0].cache_config.keys_dump_file = format!("{}/keys.dump", file_path);
+        let db = Bhatho::new(&conf, Arc::new(AtomicBool::new(false))).unwrap();
+        db.put(&KeyVal::new(b"export_key", b"val")).unwrap();
+        let done: Vec<(String, Result<u64, String>)> =
+            db.export_lru_keys(b"").unwrap().iter().collect();
+        assert_eq!(done.len(), 1);
+        assert!(done[0].1.is_err());
+    }
+
+    #[test]
+    fn validate_config_test() {
+        assert!(BhathoConfig::default().validate().is_ok());
+
+        let mut conf = BhathoConfig::default();
+        conf.db_configs[0].name = "db".to_string();
+        conf.db_configs[0].cache_config.num_shards = 0;
+        conf.db_configs.push(conf.db_configs[0].clone());
+        conf.db_configs[1].cache_config.num_shards = 16;
+        conf.db_configs[1].cache_config.cache_capacity = 8;
+        conf.db_configs[1].rocks_db_config.min_write_buffer_number = 6;
+        conf.db_configs[1].key_validation_regex = Some("^(tenant".to_string());
+        conf.db_configs[1].rocks_db_config.durable_async_queue = true;
+        conf.db_configs[1].rocks_db_config.disable_wal = true;
+        conf.shard_strategy = "consistent".to_string();
+        conf.db_name_extractor_from_key.enabled = true;
+        conf.db_name_extractor_from_key.regex_mappings.push(RegExMapping {
+            extract_name_regex: "^(tenant".to_string(),
+            new_db_name: "tenant".to_string(),
+        });
+        let errors = conf.validate().unwrap_err();
+        let expected = [
+            "unknown shard_strategy: consistent",
+            "db: db num_shards must be > 0",
+            "db: db cache_capacity (8) must be >= num_shards (16)",
+            "db: db min_write_buffer_number (6) must be <= max_write_buffer_number (5)",
+            "db: db durable_async_queue requires disable_wal false",
+            "duplicate db name: db",
+            "duplicate db_path: /tmp/kanudo_db",
+        ];
+        for msg in expected.iter() {
+            assert!(errors.iter().any(|e| e == msg), "missing error: {}", msg);
+        }
+        assert!(errors.iter().any(|e| e.starts_with("invalid extract_name_regex: ^(tenant")));
+        assert!(errors
+            .iter()
+            .any(|e| e.starts_with("db: db invalid key_validation_regex: ^(tenant")));
+        assert_eq!(errors.len(), expected.len() + 2);
+    }
+
+    /// number of threads of this process with a given name
+    #[cfg(target_os = "linux")]
+    fn count_threads(name: &str) -> usize {
+        std::fs::read_dir("/proc/self/task")
+            .unwrap()
+            .filter_map(|task| std::fs::read_to_string(task.unwrap().path().join("comm")).ok())
+            .filter(|comm| comm.trim() == name)
+            .count()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn drop_joins_writer_threads_test() {
+        let iterations = 25;
+        let writers_per_db = 4;
+        for _ in 0..iterations {
+            let mut conf = test_config("drop_joins_writers");
+            conf.db_configs[0].rocks_db_config.async_write = true;
+            conf.db_configs[0].rocks_db_config.num_async_writer_threads = writers_per_db;
+            conf.db_configs[0].rocks_db_config.async_writer_threads_sleep_ms = 10;
+            let shutdown = Arc::new(AtomicBool::new(false));
+            let db = Bhatho::new(&conf, shutdown).unwrap();
+            db.put(&KeyVal::new(b"drop_key", b"val")).unwrap();
+            drop(db);
+        }
+        //other tests may run writers concurrently, leaked ones would be iterations * writers
+        assert!(count_threads("bhatho-writer") < iterations * writers_per_db / 2);
+    }
+
+    #[test]
+    fn scan_range_test() {
+        let conf = test_config("scan_range");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        let name = b"scan_range";
+        for i in 0..10 {
+            let key = format!("range_key_{}", i);
+            db.put(&KeyVal::new(key.as_bytes(), key.as_bytes())).unwrap();
+        }
+
+        //full range
+        let all = db.scan_range(name, None, None, 100).unwrap();
+        assert_eq!(all.len(), 10);
+        assert_eq!(all[0].0, b"range_key_0".to_vec());
+        assert_eq!(all[9].1, b"range_key_9".to_vec());
+
+        //start inclusive, end exclusive
+        let range = db
+            .scan_range(name, Some(b"range_key_3"), Some(b"range_key_6"), 100)
+            .unwrap();
+        let keys: Vec<_> = range.into_iter().map(|(key, _)| key).collect();
+        assert_eq!(
+            keys,
+            vec![b"range_key_3".to_vec(), b"range_key_4".to_vec(), b"range_key_5".to_vec()]
+        );
+
+        //limited range and the next page from the last key
+        let page = db.scan_range(name, None, None, 4).unwrap();
+        assert_eq!(page.len(), 4);
+        let last = page[3].0.clone();
+        let next = db.scan_range(name, Some(&last), None, 5).unwrap();
+        assert_eq!(next[0].0, last);
+        assert_eq!(next[4].0, b"range_key_7".to_vec());
+
+        //empty range
+        let empty = db.scan_range(name, Some(b"range_key_5"), Some(b"range_key_5"), 10);
+        assert!(empty.unwrap().is_empty());
+        assert!(db.scan_range(name, Some(b"zzz"), None, 10).unwrap().is_empty());
+        assert!(db.scan_range(name, None, None, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn scan_range_rev_test() {
+        let conf = test_config("scan_range_rev");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        let name = b"scan_range_rev";
+        for i in 1..=5 {
+            let key = format!("k{}", i);
+            db.put(&KeyVal::new(key.as_bytes(), key.as_bytes())).unwrap();
+        }
+
+        let keys = |page: Vec<(Vec<u8>, Vec<u8>)>| -> Vec<Vec<u8>> {
+            page.into_iter().map(|(key, _)| key).collect()
+        };
+        let page = db.scan_range_rev(name, None, None, 3).unwrap();
+        assert_eq!(keys(page), vec![b"k5".to_vec(), b"k4".to_vec(), b"k3".to_vec()]);
+
+        //end stays exclusive, start inclusive
+        let page = db.scan_range_rev(name, Some(b"k2"), Some(b"k4"), 10).unwrap();
+        assert_eq!(keys(page), vec![b"k3".to_vec(), b"k2".to_vec()]);
+
+        //the next page ends before the last key of the previous one
+        let page = db.scan_range_rev(name, None, Some(b"k3"), 3).unwrap();
+        assert_eq!(keys(page), vec![b"k2".to_vec(), b"k1".to_vec()]);
+    }
+
+    #[test]
+    fn warm_prefix_test() {
+        let conf = test_config("warm_prefix");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        let name = b"warm_prefix";
+        for i in 0..5 {
+            db.put(&KeyVal::new(format!("hot:{}", i).as_bytes(), b"val")).unwrap();
+            db.put(&KeyVal::new(format!("cold:{}", i).as_bytes(), b"val")).unwrap();
+        }
+        db.invalidate_cache_prefix(name, b"").unwrap();
+
+        assert_eq!(db.warm_prefix(name, b"hot:", 100).unwrap(), 5);
+        for i in 0..5 {
+            let hot = db.get(&KeyVal::new(format!("hot:{}", i).as_bytes(), b"")).unwrap();
+            assert_eq!(hot, Some((b"val".to_vec(), true)));
+            let cold = db.get(&KeyVal::new(format!("cold:{}", i).as_bytes(), b"")).unwrap();
+            assert_eq!(cold, Some((b"val".to_vec(), false)));
+        }
+
+        db.invalidate_cache_prefix(name, b"hot:").unwrap();
+        assert_eq!(db.warm_prefix(name, b"hot:", 2).unwrap(), 2);
+    }
+
+    #[test]
+    fn pin_key_test() {
+        let mut conf = test_config("pin_key");
+        conf.db_configs[0].cache_config.cache_capacity = 16;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        let pinned = KeyVal::new(b"lookup_key", b"lookup_val");
+        db.put(&pinned).unwrap();
+        db.pin_key(&pinned).unwrap();
+        for i in 0..1_000 {
+            db.put(&KeyVal::new(format!("overflow_{}", i).as_bytes(), b"val")).unwrap();
+        }
+        let key = KeyVal::new(b"lookup_key", b"");
+        assert_eq!(db.get(&key).unwrap(), Some((b"lookup_val".to_vec(), true)));
+        assert!(db.unpin_key(&key).unwrap());
+        assert!(!db.unpin_key(&key).unwrap());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_bhatho_test() {
+        use crate::async_bhatho::AsyncBhatho;
+
+        let conf = test_config("async_bhatho");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = AsyncBhatho::new(Bhatho::new(&conf, shutdown).unwrap());
+        db.put(KeyVal::new(b"async_key", b"async_val")).await.unwrap();
+        let key = KeyVal::new(b"async_key", b"");
+        assert_eq!(db.get(key.clone()).await.unwrap(), Some((b"async_val".to_vec(), true)));
+        db.delete(key.clone()).await.unwrap();
+        assert_eq!(db.get(key).await.unwrap(), None);
+    }
+
+    #[test]
+    fn count_prefix_test() {
+        let conf = test_config("count_prefix");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        for i in 0..50 {
+            db.put(&KeyVal::new(format!("user:{}", i).as_bytes(), b"val")).unwrap();
+            db.put(&KeyVal::new(format!("order:{}", i).as_bytes(), b"val")).unwrap();
+        }
+        assert_eq!(db.count_prefix(b"count_prefix", b"user:").unwrap(), 50);
+        assert_eq!(db.count_prefix(b"count_prefix", b"missing:").unwrap(), 0);
+    }
+
+    #[test]
+    fn put_batch_str_test() {
+        let conf = test_config("put_batch_str");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        assert!(db.put_batch_str(&[]).is_ok());
+        let pairs: Vec<(String, String)> = (0..1_000)
+            .map(|i| (format!("config_{}", i), format!("value_{}", i)))
+            .collect();
+        db.put_batch_str(&pairs).unwrap();
+        for i in (0..1_000).step_by(111) {
+            let val = db.get_str(&format!("config_{}", i)).unwrap();
+            assert_eq!(val, Some(format!("value_{}", i)));
+        }
+    }
+
+    #[test]
+    fn shard_occupancy_test() {
+        let conf = test_config("shard_occupancy");
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        for i in 0..500 {
+            db.put(&KeyVal::new(format!("occupancy_{}", i).as_bytes(), b"val")).unwrap();
+        }
+        let occupancy = db.shard_occupancy(b"shard_occupancy").unwrap();
+        assert_eq!(occupancy.len(), 16);
+        assert_eq!(occupancy.iter().sum::<usize>(), 500);
+    }
+
+    #[test]
+    fn cache_max_value_size_test() {
+        let mut conf = test_config("cache_max_value_size");
+        conf.db_configs[0].cache_config.cache_max_value_size = 16;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        let big = vec![b'v'; 1024];
+        db.put(&KeyVal::new(b"small_key", b"small")).unwrap();
+        db.put(&KeyVal::new(b"big_key", b"small")).unwrap();
+        db.put(&KeyVal::new(b"big_key", &big)).unwrap();
+
+        let small = KeyVal::new(b"small_key", b"");
+        assert_eq!(db.get(&small).unwrap(), Some((b"small".to_vec(), true)));
+        let key = KeyVal::new(b"big_key", b"");
+        for _ in 0..3 {
+            assert_eq!(db.get(&key).unwrap(), Some((big.clone(), false)));
+            assert!(!db.in_cache(&key));
+        }
+    }
+
+    #[test]
+    fn cache_shadow_mode_test() {
+        let mut conf = test_config("cache_shadow_mode");
+        conf.db_configs[0].cache_config.shadow_mode = true;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let db = Bhatho::new(&conf, shutdown).unwrap();
+        db.put(&KeyVal::new(b"shadow_key", b"val")).unwrap();
+        db.put(&KeyVal::new(b"disk_only_key", b"val")).unwrap();
+        db.invalidate_cache_prefix(b"cache_shadow_mode", b"disk_only").unwrap();
+
+        for _ in 0..3 {
+            let kv = KeyVal::new(b"shadow_key", b"");
+            assert_eq!(db.get(&kv).unwrap(), Some((b"val".to_vec(), false)));
+        }
+        let kv = KeyVal::new(b"disk_only_key", b"");
+        assert_eq!(db.get(&kv).unwrap(), Some((b"val".to_vec(), false)));
+        let kv = KeyVal::new(b"missing_key", b"");
+        assert_eq!(db.get(&kv).unwrap(), None);
+
+        let metrics = &db.metrics()[0];
+        assert_eq!(metrics.cache_hits, 3);
+        //disk_only_key is cached by the read, missing_key is not found
+        assert_eq!(metrics.cache_misses, 2);
+        assert_eq!(metrics.disk_reads, 5);
+    }
+
+    #[test]
+    fn shard_strategy_rebalance_test() {
+        let total = 10_000;
+        let mut moved_modulo = 0;
+        let mut moved_jumphash = 0;
+        for i in 0..total {
+            let key = format!("rebalance_key_{}", i);
+            let kv = KeyVal::new(key.as_bytes(), b"");
+            if Bhatho::hash_shard("modulo", &kv, 4) != Bhatho::hash_shard("modulo", &kv, 5) {
+                moved_modulo += 1;
+            }
+            if Bhatho::hash_shard("jumphash", &kv, 4) != Bhatho::hash_shard("jumphash", &kv, 5) {
+                moved_jumphash += 1;
+            }
+        }
+        //modulo moves ~80% of the keys, jumphash ~20%
+        assert!(moved_jumphash * 2 < moved_modulo);
+        assert!(moved_jumphash < total / 3);
+    }
+
+    #[test]
+    fn shard_strategy_config_test() {
+        let mut conf = test_config("unknown_shard_strategy");
+        conf.shard_strategy = "consistent".to_string();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        assert!(Bhatho::new(&conf, shutdown).is_err());
+
+        //configs written before shard_strategy and db_template_config still load
+        let json = r#"{"db_configs": [], "db_name_extractor_from_key": {"enabled": false}}"#;
+        let conf: BhathoConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(conf.shard_strategy, "modulo");
+        assert!(conf.db_configs.is_empty());
+    }
 }
@@ -0,0 +1,58 @@
+/************************************************
+
+   File Name: bhatho:test_util
+   Author: Rohit Joshi <rohit.c.joshi@gmail.com>
+   Date: 2019-02-17:15:15
+   License: Apache 2.0
+
+**************************************************/
+use std::fs;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::db::config::{DbManagerConfig, RocksDbConfig};
+use crate::BhathoConfig;
+
+static NEXT_DIR_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// unique directory path for a test under the temp dir.
+/// the process id and a counter keep tests and concurrent test runs apart
+pub(crate) fn test_dir(name: &str) -> String {
+    let id = NEXT_DIR_ID.fetch_add(1, Ordering::SeqCst);
+    let path = std::env::temp_dir()
+        .join("bhatho_test")
+        .join(format!("{}_{}_{}", name, process::id(), id));
+    let path = path.to_string_lossy().to_string();
+    let _ = fs::remove_dir_all(&path);
+    path
+}
+
+/// RocksDbConfig with sync writes and no restore, in a unique directory
+pub(crate) fn rocks_db_test_config(name: &str) -> RocksDbConfig {
+    let path = test_dir(name);
+    let mut config = RocksDbConfig::default();
+    config.db_path = path.clone();
+    config.wal_dir = format!("{}/wal", path);
+    config.backup_path = format!("{}_bkup", path);
+    let _ = fs::remove_dir_all(&config.backup_path);
+    config.restore_from_backup_at_startup = false;
+    config.async_write = false;
+    config
+}
+
+/// DbManagerConfig named name with a small cache, in a unique directory
+pub(crate) fn db_manager_test_config(name: &str) -> DbManagerConfig {
+    let mut config = DbManagerConfig::default();
+    config.name = name.to_string();
+    config.rocks_db_config = rocks_db_test_config(name);
+    config.cache_config.cache_capacity = 10_000;
+    config.cache_config.num_shards = 16;
+    config
+}
+
+/// BhathoConfig with a single db from db_manager_test_config
+pub(crate) fn bhatho_test_config(name: &str) -> BhathoConfig {
+    let mut conf = BhathoConfig::default();
+    conf.db_configs[0] = db_manager_test_config(name);
+    conf
+}